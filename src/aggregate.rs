@@ -0,0 +1,430 @@
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+use crate::parser::{
+    AggregateFunction, BinaryOperator, Condition, Expr, apply_arithmetic, resolve_column,
+};
+use crate::storage::{Column, Value};
+
+/// Running state for a single aggregate call within one group.
+enum Accumulator {
+    Count(i64),
+    Sum(Option<Value>),
+    Avg { sum: f64, count: i64 },
+    Min(Option<Value>),
+    Max(Option<Value>),
+}
+
+impl Accumulator {
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum(None),
+            AggregateFunction::Avg => Accumulator::Avg { sum: 0.0, count: 0 },
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+        }
+    }
+
+    /// Folds one input into the accumulator. `None` stands for the implicit
+    /// argument of `COUNT(*)`; NULL inputs are ignored by every function.
+    fn update(&mut self, value: Option<Value>) -> Result<()> {
+        let value = match value {
+            None => {
+                if let Accumulator::Count(count) = self {
+                    *count += 1;
+                }
+                return Ok(());
+            }
+            Some(Value::Null) => return Ok(()),
+            Some(value) => value,
+        };
+
+        match self {
+            Accumulator::Count(count) => *count += 1,
+            Accumulator::Sum(sum) => {
+                if !matches!(
+                    value,
+                    Value::Integer(_) | Value::Real(_) | Value::Interval(_)
+                ) {
+                    return Err(anyhow!(
+                        "SUM expects a numeric or INTERVAL value, got {:?}",
+                        value
+                    ));
+                }
+                *sum = Some(match sum.take() {
+                    Some(total) => apply_arithmetic(BinaryOperator::Add, total, value)?,
+                    None => value,
+                });
+            }
+            Accumulator::Avg { sum, count } => {
+                *sum += match value {
+                    Value::Integer(i) => i as f64,
+                    Value::Real(r) => r,
+                    v => return Err(anyhow!("AVG expects a numeric value, got {:?}", v)),
+                };
+                *count += 1;
+            }
+            Accumulator::Min(min) => {
+                if min.as_ref().is_none_or(|current| value < *current) {
+                    *min = Some(value);
+                }
+            }
+            Accumulator::Max(max) => {
+                if max.as_ref().is_none_or(|current| value > *current) {
+                    *max = Some(value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::Integer(count),
+            Accumulator::Sum(sum) => sum.unwrap_or(Value::Null),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => Value::Real(sum / count as f64),
+            Accumulator::Min(value) | Accumulator::Max(value) => value.unwrap_or(Value::Null),
+        }
+    }
+}
+
+struct Group {
+    row: Vec<Value>,
+    accumulators: Vec<Accumulator>,
+}
+
+/// A finished group: the first row seen for the group, used to evaluate
+/// grouping columns, and one value per aggregate expression.
+pub struct AggregatedGroup {
+    pub row: Vec<Value>,
+    pub values: Vec<Value>,
+}
+
+/// Hash-based GROUP BY. Rows are pushed one at a time, so only one entry per
+/// distinct group key is held in memory, never the scanned rows themselves.
+pub struct HashAggregator<'a> {
+    group_by: &'a [Expr],
+    aggregates: &'a [Expr],
+    columns: &'a [Column],
+    groups: Vec<Group>,
+    lookup: HashMap<Vec<Value>, usize>,
+}
+
+impl<'a> HashAggregator<'a> {
+    pub fn new(group_by: &'a [Expr], aggregates: &'a [Expr], columns: &'a [Column]) -> Self {
+        HashAggregator {
+            group_by,
+            aggregates,
+            columns,
+            groups: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Value>) -> Result<()> {
+        let key = self
+            .group_by
+            .iter()
+            .map(|expr| expr.evaluate(&row, self.columns))
+            .collect::<Result<Vec<_>>>()?;
+
+        let group_idx = match self.lookup.get(&key) {
+            Some(&idx) => idx,
+            None => {
+                self.groups.push(self.new_group(row.clone()));
+                self.lookup.insert(key, self.groups.len() - 1);
+                self.groups.len() - 1
+            }
+        };
+
+        for (i, aggregate) in self.aggregates.iter().enumerate() {
+            let Expr::Aggregate { arg, .. } = aggregate else {
+                continue;
+            };
+            let value = match arg {
+                Some(arg) => Some(arg.evaluate(&row, self.columns)?),
+                None => None,
+            };
+            self.groups[group_idx].accumulators[i].update(value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the groups in the order their keys were first seen. Without a
+    /// GROUP BY clause there is always exactly one group, even for no input.
+    pub fn finish(mut self) -> Vec<AggregatedGroup> {
+        if self.groups.is_empty() && self.group_by.is_empty() {
+            let row = vec![Value::Null; self.columns.len()];
+            self.groups.push(self.new_group(row));
+        }
+
+        self.groups
+            .into_iter()
+            .map(|group| AggregatedGroup {
+                row: group.row,
+                values: group
+                    .accumulators
+                    .into_iter()
+                    .map(Accumulator::finish)
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn new_group(&self, row: Vec<Value>) -> Group {
+        let accumulators = self
+            .aggregates
+            .iter()
+            .map(|aggregate| match aggregate {
+                Expr::Aggregate { function, .. } => Accumulator::new(*function),
+                _ => Accumulator::Count(0),
+            })
+            .collect();
+
+        Group { row, accumulators }
+    }
+}
+
+/// Adds every distinct aggregate call in `expr` to `aggregates`.
+pub fn collect_aggregates(expr: &Expr, aggregates: &mut Vec<Expr>) {
+    expr.walk(&mut |expr| {
+        if matches!(expr, Expr::Aggregate { .. }) && !aggregates.contains(expr) {
+            aggregates.push(expr.clone());
+        }
+    });
+}
+
+/// Fails if `exprs` or `having` read a column outside of an aggregate call
+/// without grouping on it, as its value could differ between the rows of a
+/// group.
+pub fn check_grouping(
+    exprs: &[&Expr],
+    having: Option<&Condition>,
+    group_by: &[Expr],
+    columns: &[Column],
+) -> Result<()> {
+    let grouped_columns: Vec<usize> = group_by
+        .iter()
+        .filter_map(|expr| match expr {
+            Expr::Column { table, name } => resolve_column(columns, table.as_deref(), name).ok(),
+            _ => None,
+        })
+        .collect();
+
+    // Aggregate calls and grouped expressions have one value per group, so
+    // whatever columns they read are fine.
+    let grouped = |expr: &Expr| {
+        (matches!(expr, Expr::Aggregate { .. }) || group_by.contains(expr))
+            .then_some(Expr::Literal(Value::Null))
+    };
+
+    let mut result = Ok(());
+    let mut check = |expr: &Expr| {
+        if let Expr::Column { table, name } = expr
+            && result.is_ok()
+        {
+            result = match resolve_column(columns, table.as_deref(), name) {
+                Ok(idx) if grouped_columns.contains(&idx) => Ok(()),
+                Ok(_) => Err(anyhow!(
+                    "Column {} must appear in GROUP BY or be used in an aggregate function",
+                    expr
+                )),
+                Err(err) => Err(err),
+            };
+        }
+    };
+
+    for expr in exprs {
+        expr.transform(&grouped).walk(&mut check);
+    }
+    if let Some(having) = having {
+        having.transform_exprs(&grouped).walk_exprs(&mut check);
+    }
+    result
+}
+
+/// Replaces each aggregate call in `expr` with its computed value for a group.
+pub fn substitute_aggregates(expr: &Expr, aggregates: &[Expr], values: &[Value]) -> Option<Expr> {
+    aggregates
+        .iter()
+        .position(|aggregate| aggregate == expr)
+        .map(|idx| Expr::Literal(values[idx].clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::tests::{TempDir, execute, open, query};
+    use crate::storage::{Storage, Value};
+
+    fn emp(dir: &TempDir) -> Storage {
+        let mut storage = open(dir);
+        query(
+            &mut storage,
+            "CREATE TABLE emp (name TEXT, dept TEXT, salary INTEGER)",
+        );
+        for (name, dept, salary) in [("a", "x", 10), ("b", "y", 20), ("c", "y", 30)] {
+            query(
+                &mut storage,
+                &format!(
+                    "INSERT INTO emp VALUES ('{}', '{}', {})",
+                    name, dept, salary
+                ),
+            );
+        }
+        storage
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_string())
+    }
+
+    #[test]
+    fn order_by_may_use_aggregates_not_in_the_select_list() {
+        let dir = TempDir::new("aggregate-order");
+        let mut storage = emp(&dir);
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT dept FROM emp GROUP BY dept ORDER BY COUNT(*) DESC"
+            ),
+            vec![vec![text("y")], vec![text("x")]]
+        );
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT dept FROM emp GROUP BY dept ORDER BY MIN(salary) DESC"
+            ),
+            vec![vec![text("y")], vec![text("x")]]
+        );
+    }
+
+    #[test]
+    fn ungrouped_columns_are_refused() {
+        let dir = TempDir::new("aggregate-ungrouped");
+        let mut storage = emp(&dir);
+        for sql in [
+            "SELECT dept, name FROM emp GROUP BY dept",
+            "SELECT name, COUNT(*) FROM emp",
+            "SELECT dept FROM emp GROUP BY dept HAVING salary > 10",
+            "SELECT dept FROM emp GROUP BY dept ORDER BY name",
+        ] {
+            let err = execute(&mut storage, sql).unwrap_err();
+            assert!(
+                err.to_string().contains("must appear in GROUP BY"),
+                "{}",
+                sql
+            );
+        }
+
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT emp.dept, COUNT(name) FROM emp GROUP BY dept HAVING MAX(salary) > 10"
+            ),
+            vec![vec![text("y"), Value::Integer(2)]]
+        );
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT salary + 1 FROM emp GROUP BY salary + 1 ORDER BY 1 DESC LIMIT 1"
+            ),
+            vec![vec![Value::Integer(31)]]
+        );
+    }
+
+    #[test]
+    fn aggregates_per_group_skip_nulls() {
+        let dir = TempDir::new("aggregate-groups");
+        let mut storage = emp(&dir);
+        query(&mut storage, "INSERT INTO emp VALUES ('d', 'y', NULL)");
+        query(&mut storage, "INSERT INTO emp VALUES ('e', NULL, 5)");
+
+        // NULL keys form a group of their own, which sorts first.
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT dept, COUNT(*), COUNT(salary), SUM(salary), AVG(salary), MIN(salary), MAX(salary) \
+                 FROM emp GROUP BY dept ORDER BY dept"
+            ),
+            vec![
+                vec![
+                    Value::Null,
+                    Value::Integer(1),
+                    Value::Integer(1),
+                    Value::Integer(5),
+                    Value::Real(5.0),
+                    Value::Integer(5),
+                    Value::Integer(5),
+                ],
+                vec![
+                    text("x"),
+                    Value::Integer(1),
+                    Value::Integer(1),
+                    Value::Integer(10),
+                    Value::Real(10.0),
+                    Value::Integer(10),
+                    Value::Integer(10),
+                ],
+                vec![
+                    text("y"),
+                    Value::Integer(3),
+                    Value::Integer(2),
+                    Value::Integer(50),
+                    Value::Real(25.0),
+                    Value::Integer(20),
+                    Value::Integer(30),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn an_ungrouped_aggregate_yields_one_row_even_without_input() {
+        let dir = TempDir::new("aggregate-empty");
+        let mut storage = emp(&dir);
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT COUNT(*), SUM(salary), AVG(salary) FROM emp"
+            ),
+            vec![vec![
+                Value::Integer(3),
+                Value::Integer(60),
+                Value::Real(20.0)
+            ]]
+        );
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT COUNT(*), SUM(salary), MIN(name) FROM emp WHERE salary > 100"
+            ),
+            vec![vec![Value::Integer(0), Value::Null, Value::Null]]
+        );
+    }
+
+    #[test]
+    fn having_filters_groups_on_their_aggregates() {
+        let dir = TempDir::new("aggregate-having");
+        let mut storage = emp(&dir);
+        query(&mut storage, "INSERT INTO emp VALUES ('d', 'x', 1)");
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT dept, SUM(salary) * 2 + 1 FROM emp GROUP BY dept \
+                 HAVING COUNT(*) > 1 AND SUM(salary) >= 20 ORDER BY dept"
+            ),
+            vec![vec![text("y"), Value::Integer(101)]]
+        );
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT dept FROM emp GROUP BY dept HAVING MIN(salary) < 5 OR MAX(salary) = 30 ORDER BY dept"
+            ),
+            vec![vec![text("x")], vec![text("y")]]
+        );
+    }
+}
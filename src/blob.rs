@@ -0,0 +1,175 @@
+use anyhow::{Result, anyhow};
+
+use crate::storage::Value;
+
+// Binary values. BLOBs are written as `X'0a1b'` hex literals and printed as
+// `\x0a1b`; UUIDs are sixteen bytes written and printed in the usual
+// `8-4-4-4-12` hex form. Both compare byte by byte.
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let digits = text.as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses the digits of an `X'...'` literal: an even number of hex digits.
+pub fn parse_blob(text: &str) -> Result<Value> {
+    decode_hex(text).map(Value::Blob).ok_or_else(|| {
+        anyhow!(
+            "Invalid BLOB literal X'{}': expected pairs of hex digits",
+            text
+        )
+    })
+}
+
+pub fn format_blob(bytes: &[u8]) -> String {
+    format!("\\x{}", encode_hex(bytes))
+}
+
+/// Parses a UUID as 32 hex digits, optionally grouped by hyphens and
+/// wrapped in braces.
+pub fn parse_uuid(text: &str) -> Result<Value> {
+    let trimmed = text.trim();
+    let trimmed = trimmed
+        .strip_prefix('{')
+        .and_then(|inner| inner.strip_suffix('}'))
+        .unwrap_or(trimmed);
+    let digits: String = trimmed.chars().filter(|&c| c != '-').collect();
+
+    decode_hex(&digits)
+        .and_then(|bytes| <[u8; 16]>::try_from(bytes).ok())
+        .map(Value::Uuid)
+        .ok_or_else(|| anyhow!("Invalid UUID '{}'", text))
+}
+
+pub fn format_uuid(bytes: &[u8; 16]) -> String {
+    format!(
+        "{}-{}-{}-{}-{}",
+        encode_hex(&bytes[..4]),
+        encode_hex(&bytes[4..6]),
+        encode_hex(&bytes[6..8]),
+        encode_hex(&bytes[8..10]),
+        encode_hex(&bytes[10..])
+    )
+}
+
+/// A random version 4 UUID.
+pub fn random_uuid() -> Value {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Value::Uuid(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{TempDir, integers, open, query};
+
+    #[test]
+    fn blob_literals_are_pairs_of_hex_digits() {
+        assert_eq!(parse_blob("ff00").unwrap(), Value::Blob(vec![0xff, 0x00]));
+        assert_eq!(parse_blob("0A").unwrap(), Value::Blob(vec![0x0a]));
+        assert_eq!(parse_blob("").unwrap(), Value::Blob(Vec::new()));
+        for text in ["0", "0g", "abc", " 00"] {
+            assert!(parse_blob(text).is_err(), "{}", text);
+        }
+        assert_eq!(format_blob(&[0x00, 0xff]), "\\x00ff");
+    }
+
+    #[test]
+    fn uuids_parse_with_or_without_hyphens_and_braces() {
+        let uuid = parse_uuid("a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11").unwrap();
+        for text in [
+            "A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11",
+            "a0eebc999c0b4ef8bb6d6bb9bd380a11",
+            "{a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11}",
+            " a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11 ",
+        ] {
+            assert_eq!(parse_uuid(text).unwrap(), uuid, "{}", text);
+        }
+        assert_eq!(uuid.to_string(), "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11");
+
+        for text in [
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a1",
+            "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a1100",
+            "not-a-uuid",
+        ] {
+            assert!(parse_uuid(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn random_uuids_are_version_4() {
+        let (Value::Uuid(a), Value::Uuid(b)) = (random_uuid(), random_uuid()) else {
+            panic!("expected UUIDs");
+        };
+        assert_ne!(a, b);
+        for bytes in [a, b] {
+            assert_eq!(bytes[6] >> 4, 4);
+            assert_eq!(bytes[8] >> 6, 0b10);
+        }
+    }
+
+    #[test]
+    fn blobs_and_uuids_compare_and_index_byte_by_byte() {
+        let dir = TempDir::new("blob-index");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE b (id INTEGER, data BLOB, u UUID)",
+        );
+        query(&mut storage, "CREATE INDEX bd ON b (data)");
+        query(&mut storage, "CREATE INDEX bu ON b (u)");
+        for (id, data, u) in [
+            (1, "X'ff00'", "UUID 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'"),
+            (2, "X''", "gen_random_uuid()"),
+            (3, "X'00ff'", "UUID '00000000-0000-0000-0000-000000000001'"),
+            (4, "X'0a'", "NULL"),
+        ] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO b VALUES ({}, {}, {})", id, data, u),
+            );
+        }
+
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM b WHERE data > X'00'")),
+            vec![3, 4, 1]
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM b WHERE data = X'FF00'")),
+            vec![1]
+        );
+        // Row 2's random UUID may fall on either side of the bound.
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT id FROM b WHERE u <= UUID 'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11' AND id <> 2"
+            )),
+            vec![3, 1]
+        );
+        assert_eq!(
+            query(&mut storage, "SELECT data FROM b WHERE id = 1"),
+            vec![vec![Value::Blob(vec![0xff, 0x00])]]
+        );
+    }
+}
@@ -0,0 +1,719 @@
+use anyhow::{Result, anyhow};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::row::{decode_values, encode_values};
+use crate::storage::Value;
+
+const PAGE_SIZE: usize = 4096;
+const MAGIC: &[u8; 8] = b"SCYBTREE";
+const VERSION: u32 = 2;
+
+/// The header: magic, format version, root page, page count and the head of
+/// the free list.
+const HEADER_LEN: usize = 24;
+
+/// The longest encoded key an index accepts. Every entry then takes less
+/// than a third of a page, so splitting a full node by bytes always gives
+/// two halves that fit.
+pub const MAX_KEY_LEN: usize = 1024;
+
+const LEAF: u8 = 0;
+const INTERNAL: u8 = 1;
+const FREE: u8 = 2;
+
+/// Bytes of a node before its entries: kind, entry count and link.
+const NODE_HEADER_LEN: usize = 7;
+
+/// Bytes of an entry before its key: row id and key length.
+const ENTRY_HEADER_LEN: usize = 10;
+
+/// A node smaller than this after a removal is merged with a sibling if
+/// the two fit in one page.
+const MIN_NODE_LEN: usize = PAGE_SIZE / 4;
+
+// Node layout:
+//
+//   leaf      kind:u8 count:u16 next:u32 entry*
+//   internal  kind:u8 count:u16 child:u32 (entry child:u32)*
+//   free      kind:u8 0:u16 next_free:u32
+//   entry     row_id:u64 key_len:u16 key
+//
+// Keys are encoded like rows, see `row::encode_values`. Page 0 is the
+// header, so a link of zero ends the leaf chain and the free list.
+
+/// An index entry: the indexed column values and the id of the row in the
+/// table's heap file. The row id makes every entry unique, so duplicate keys
+/// need no special handling and a single row can be removed exactly.
+type Entry = (Vec<Value>, u64);
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        entries: Vec<Entry>,
+        next: Option<u32>,
+    },
+    /// `children[i]` holds the entries below `keys[i]`, `children[i + 1]`
+    /// those at or above it.
+    Internal {
+        keys: Vec<Entry>,
+        children: Vec<u32>,
+    },
+}
+
+/// A B+tree stored in fixed-size pages of an index file.
+///
+/// Page 0 holds the header; every other page holds one node in the binary
+/// layout above, or is on the free list. Leaves are chained left to right so
+/// ordered scans never go back up the tree. A node left less than a quarter
+/// full by a removal is merged with a sibling when they fit in one page,
+/// and the page it frees is reused by later splits.
+pub struct BTree {
+    file: File,
+    root: u32,
+    page_count: u32,
+    free_head: u32,
+}
+
+/// Fails if `key` is too long to be stored in an index.
+pub fn check_key(key: &[Value]) -> Result<()> {
+    let len = encode_values(key).len();
+    if len > MAX_KEY_LEN {
+        return Err(anyhow!(
+            "Index key of {} bytes exceeds the limit of {} bytes",
+            len,
+            MAX_KEY_LEN
+        ));
+    }
+    Ok(())
+}
+
+impl BTree {
+    /// Creates an empty tree at `path`, replacing whatever was there.
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        let tree = BTree {
+            file,
+            root: 1,
+            page_count: 2,
+            free_head: 0,
+        };
+        tree.write_node(
+            1,
+            &Node::Leaf {
+                entries: Vec::new(),
+                next: None,
+            },
+        )?;
+        tree.write_header()?;
+
+        Ok(tree)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut header = [0u8; HEADER_LEN];
+        file.read_exact(&mut header)
+            .map_err(|_| anyhow!("{} is not a B+tree index file", path.display()))?;
+        if &header[..8] != MAGIC {
+            return Err(anyhow!("{} is not a B+tree index file", path.display()));
+        }
+
+        let version = u32::from_le_bytes(header[8..12].try_into()?);
+        if version != VERSION {
+            return Err(anyhow!(
+                "{} has unsupported index format version {}",
+                path.display(),
+                version
+            ));
+        }
+
+        Ok(BTree {
+            file,
+            root: u32::from_le_bytes(header[12..16].try_into()?),
+            page_count: u32::from_le_bytes(header[16..20].try_into()?),
+            free_head: u32::from_le_bytes(header[20..24].try_into()?),
+        })
+    }
+
+    /// Whether `path` holds a B+tree in the current format, as opposed to
+    /// an older format or nothing at all.
+    pub fn is_btree_file(path: &Path) -> bool {
+        let mut header = [0u8; 12];
+        File::open(path)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .is_ok()
+            && &header[..8] == MAGIC
+            && header[8..12] == VERSION.to_le_bytes()
+    }
+
+    pub fn insert(&mut self, key: Vec<Value>, row_id: u64) -> Result<()> {
+        check_key(&key)?;
+        if let Some((separator, right)) = self.insert_into(self.root, (key, row_id))? {
+            let root = self.allocate()?;
+            self.write_node(
+                root,
+                &Node::Internal {
+                    keys: vec![separator],
+                    children: vec![self.root, right],
+                },
+            )?;
+            self.root = root;
+        }
+
+        self.write_header()
+    }
+
+    /// Removes the entry for `key` and `row_id`. Returns whether it was there.
+    pub fn remove(&mut self, key: &[Value], row_id: u64) -> Result<bool> {
+        let entry = (key.to_vec(), row_id);
+        let Some(root) = self.remove_from(self.root, &entry)? else {
+            return Ok(false);
+        };
+
+        // Merges below may leave the root with a single child, which then
+        // becomes the root itself.
+        if let Node::Internal { keys, children } = root
+            && keys.is_empty()
+        {
+            self.free(self.root)?;
+            self.root = children[0];
+        }
+        self.write_header()?;
+        Ok(true)
+    }
+
+    /// Returns the entries in key order, starting at the first one whose key
+    /// is at least `start` (or at the very first entry). Leaves are read one
+    /// at a time as the scan reaches them.
+    pub fn scan(self, start: Option<&[Value]>) -> Result<Scan> {
+        let start = start.map(|key| (key.to_vec(), 0));
+
+        let mut page = self.root;
+        loop {
+            match self.read_node(page)? {
+                Node::Internal { keys, children } => {
+                    page = match &start {
+                        Some(start) => children[keys.partition_point(|k| k <= start)],
+                        None => children[0],
+                    };
+                }
+                Node::Leaf { mut entries, next } => {
+                    let skip = match &start {
+                        Some(start) => entries.partition_point(|entry| entry < start),
+                        None => 0,
+                    };
+                    return Ok(Scan {
+                        tree: self,
+                        entries: entries.split_off(skip).into_iter(),
+                        next,
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Inserts `entry` below `page`. If the node had to split, returns the
+    /// separator and page of the new right sibling for the parent to adopt.
+    fn insert_into(&mut self, page: u32, entry: Entry) -> Result<Option<(Entry, u32)>> {
+        match self.read_node(page)? {
+            Node::Leaf { mut entries, next } => {
+                let Err(pos) = entries.binary_search(&entry) else {
+                    return Ok(None);
+                };
+                entries.insert(pos, entry);
+
+                let node = Node::Leaf { entries, next };
+                if fits(&node) {
+                    self.write_node(page, &node)?;
+                    return Ok(None);
+                }
+
+                let Node::Leaf { mut entries, next } = node else {
+                    unreachable!()
+                };
+                let right_entries = entries.split_off(split_point(&entries));
+                let separator = right_entries[0].clone();
+                let right = self.allocate()?;
+
+                self.write_node(
+                    right,
+                    &Node::Leaf {
+                        entries: right_entries,
+                        next,
+                    },
+                )?;
+                self.write_node(
+                    page,
+                    &Node::Leaf {
+                        entries,
+                        next: Some(right),
+                    },
+                )?;
+
+                Ok(Some((separator, right)))
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                let idx = keys.partition_point(|k| k <= &entry);
+                let Some((separator, new_child)) = self.insert_into(children[idx], entry)? else {
+                    return Ok(None);
+                };
+                keys.insert(idx, separator);
+                children.insert(idx + 1, new_child);
+
+                let node = Node::Internal { keys, children };
+                if fits(&node) {
+                    self.write_node(page, &node)?;
+                    return Ok(None);
+                }
+
+                let Node::Internal {
+                    mut keys,
+                    mut children,
+                } = node
+                else {
+                    unreachable!()
+                };
+                let mid = split_point(&keys);
+                let right_keys = keys.split_off(mid + 1);
+                let separator = keys.pop().unwrap();
+                let right_children = children.split_off(mid + 1);
+                let right = self.allocate()?;
+
+                self.write_node(
+                    right,
+                    &Node::Internal {
+                        keys: right_keys,
+                        children: right_children,
+                    },
+                )?;
+                self.write_node(page, &Node::Internal { keys, children })?;
+
+                Ok(Some((separator, right)))
+            }
+        }
+    }
+
+    /// Removes `entry` below `page` and returns the node as it was left,
+    /// or `None` if the entry was not there. A child left too small is
+    /// merged with a sibling on the way back up.
+    fn remove_from(&mut self, page: u32, entry: &Entry) -> Result<Option<Node>> {
+        match self.read_node(page)? {
+            Node::Leaf { mut entries, next } => {
+                let Ok(pos) = entries.binary_search(entry) else {
+                    return Ok(None);
+                };
+                entries.remove(pos);
+
+                let node = Node::Leaf { entries, next };
+                self.write_node(page, &node)?;
+                Ok(Some(node))
+            }
+            Node::Internal {
+                mut keys,
+                mut children,
+            } => {
+                let idx = keys.partition_point(|k| k <= entry);
+                let Some(child) = self.remove_from(children[idx], entry)? else {
+                    return Ok(None);
+                };
+
+                let node = if encode_node(&child).len() < MIN_NODE_LEN
+                    && self.merge_children(&mut keys, &mut children, idx, child)?
+                {
+                    let node = Node::Internal { keys, children };
+                    self.write_node(page, &node)?;
+                    node
+                } else {
+                    Node::Internal { keys, children }
+                };
+                Ok(Some(node))
+            }
+        }
+    }
+
+    /// Merges `children[idx]`, whose new state is `child`, with its right
+    /// sibling, or its left one if it is the last child. The merged node
+    /// keeps the left page and the right page is freed. Returns `false`,
+    /// changing nothing, if there is no sibling or the two do not fit in a
+    /// page together.
+    fn merge_children(
+        &mut self,
+        keys: &mut Vec<Entry>,
+        children: &mut Vec<u32>,
+        idx: usize,
+        child: Node,
+    ) -> Result<bool> {
+        let (left_idx, left, right) = if idx + 1 < children.len() {
+            (idx, child, self.read_node(children[idx + 1])?)
+        } else if idx > 0 {
+            (idx - 1, self.read_node(children[idx - 1])?, child)
+        } else {
+            return Ok(false);
+        };
+
+        let merged = match (left, right) {
+            (
+                Node::Leaf { mut entries, .. },
+                Node::Leaf {
+                    entries: right_entries,
+                    next,
+                },
+            ) => {
+                entries.extend(right_entries);
+                Node::Leaf { entries, next }
+            }
+            (
+                Node::Internal {
+                    keys: mut merged_keys,
+                    children: mut merged_children,
+                },
+                Node::Internal {
+                    keys: right_keys,
+                    children: right_children,
+                },
+            ) => {
+                merged_keys.push(keys[left_idx].clone());
+                merged_keys.extend(right_keys);
+                merged_children.extend(right_children);
+                Node::Internal {
+                    keys: merged_keys,
+                    children: merged_children,
+                }
+            }
+            _ => return Err(anyhow!("Corrupt index: siblings at different depths")),
+        };
+        if !fits(&merged) {
+            return Ok(false);
+        }
+
+        self.write_node(children[left_idx], &merged)?;
+        self.free(children[left_idx + 1])?;
+        keys.remove(left_idx);
+        children.remove(left_idx + 1);
+        Ok(true)
+    }
+
+    /// A page for a new node, taken from the free list if it has any.
+    fn allocate(&mut self) -> Result<u32> {
+        if self.free_head == 0 {
+            self.page_count += 1;
+            return Ok(self.page_count - 1);
+        }
+
+        let page = self.free_head;
+        let buf = self.read_page(page)?;
+        if buf[0] != FREE {
+            return Err(anyhow!("Corrupt index: free list reaches a node"));
+        }
+        self.free_head = read_u32(&buf, 3).unwrap();
+        Ok(page)
+    }
+
+    fn free(&mut self, page: u32) -> Result<()> {
+        let mut buf = vec![0u8; PAGE_SIZE];
+        buf[0] = FREE;
+        buf[3..7].copy_from_slice(&self.free_head.to_le_bytes());
+        self.write_page(page, &buf)?;
+        self.free_head = page;
+        Ok(())
+    }
+
+    fn read_page(&self, page: u32) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; PAGE_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))?;
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_page(&self, page: u32, buf: &[u8]) -> Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))?;
+        file.write_all(buf)?;
+        Ok(())
+    }
+
+    fn read_node(&self, page: u32) -> Result<Node> {
+        decode_node(page, &self.read_page(page)?)
+    }
+
+    fn write_node(&self, page: u32, node: &Node) -> Result<()> {
+        let mut buf = encode_node(node);
+        if buf.len() > PAGE_SIZE {
+            return Err(anyhow!("Index node does not fit in an index page"));
+        }
+        buf.resize(PAGE_SIZE, 0);
+        self.write_page(page, &buf)
+    }
+
+    fn write_header(&self) -> Result<()> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&self.root.to_le_bytes());
+        header.extend_from_slice(&self.page_count.to_le_bytes());
+        header.extend_from_slice(&self.free_head.to_le_bytes());
+        self.write_page(0, &header)
+    }
+}
+
+/// An ordered scan over the entries of a `BTree`, see `BTree::scan`.
+pub struct Scan {
+    tree: BTree,
+    entries: std::vec::IntoIter<Entry>,
+    next: Option<u32>,
+}
+
+impl Iterator for Scan {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+
+            let page = self.next.take()?;
+            match self.tree.read_node(page) {
+                Ok(Node::Leaf { entries, next }) => {
+                    self.entries = entries.into_iter();
+                    self.next = next;
+                }
+                Ok(Node::Internal { .. }) => {
+                    return Some(Err(anyhow!(
+                        "Corrupt index: leaf chain reaches an internal node"
+                    )));
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+fn fits(node: &Node) -> bool {
+    encode_node(node).len() <= PAGE_SIZE
+}
+
+/// Where to split the entries of an overfull node: just past the entry at
+/// which the entries so far make up half the bytes, leaving at least one
+/// entry on either side.
+fn split_point(entries: &[Entry]) -> usize {
+    let lens: Vec<usize> = entries.iter().map(entry_len).collect();
+    let half = lens.iter().sum::<usize>() / 2;
+
+    let mut total = 0;
+    for (i, len) in lens.iter().enumerate() {
+        total += len;
+        if total >= half {
+            return (i + 1).clamp(1, entries.len() - 1);
+        }
+    }
+    entries.len() - 1
+}
+
+fn entry_len((key, _): &Entry) -> usize {
+    ENTRY_HEADER_LEN + encode_values(key).len()
+}
+
+fn encode_entry(buf: &mut Vec<u8>, (key, row_id): &Entry) {
+    let key = encode_values(key);
+    buf.extend_from_slice(&row_id.to_le_bytes());
+    buf.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&key);
+}
+
+fn encode_node(node: &Node) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(PAGE_SIZE);
+    match node {
+        Node::Leaf { entries, next } => {
+            buf.push(LEAF);
+            buf.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&next.unwrap_or(0).to_le_bytes());
+            for entry in entries {
+                encode_entry(&mut buf, entry);
+            }
+        }
+        Node::Internal { keys, children } => {
+            buf.push(INTERNAL);
+            buf.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&children[0].to_le_bytes());
+            for (key, child) in keys.iter().zip(&children[1..]) {
+                encode_entry(&mut buf, key);
+                buf.extend_from_slice(&child.to_le_bytes());
+            }
+        }
+    }
+    buf
+}
+
+fn decode_node(page: u32, buf: &[u8]) -> Result<Node> {
+    let corrupt = || anyhow!("Corrupt index page {}", page);
+
+    let count = buf
+        .get(1..3)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(corrupt)?;
+    let link = read_u32(buf, 3).ok_or_else(corrupt)?;
+
+    let mut pos = NODE_HEADER_LEN;
+    let read_entry = |pos: &mut usize| -> Result<Entry> {
+        let header = buf.get(*pos..*pos + ENTRY_HEADER_LEN).ok_or_else(corrupt)?;
+        let row_id = u64::from_le_bytes(header[..8].try_into()?);
+        let len = u16::from_le_bytes(header[8..].try_into()?) as usize;
+        let start = *pos + ENTRY_HEADER_LEN;
+        let key = buf.get(start..start + len).ok_or_else(corrupt)?;
+        *pos = start + len;
+        Ok((decode_values(key)?, row_id))
+    };
+
+    match buf[0] {
+        LEAF => Ok(Node::Leaf {
+            entries: (0..count)
+                .map(|_| read_entry(&mut pos))
+                .collect::<Result<_>>()?,
+            next: (link != 0).then_some(link),
+        }),
+        INTERNAL => {
+            let mut keys = Vec::with_capacity(count as usize);
+            let mut children = vec![link];
+            for _ in 0..count {
+                keys.push(read_entry(&mut pos)?);
+                children.push(read_u32(buf, pos).ok_or_else(corrupt)?);
+                pos += 4;
+            }
+            Ok(Node::Internal { keys, children })
+        }
+        _ => Err(corrupt()),
+    }
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    const N: u64 = 2000;
+
+    /// A key long enough that a few dozen fill a page.
+    fn key(i: u64) -> Vec<Value> {
+        vec![Value::Integer(i as i64), Value::Text(format!("{:0>60}", i))]
+    }
+
+    /// The keys 0..N in a scrambled but fixed order.
+    fn scrambled() -> impl Iterator<Item = u64> {
+        (0..N).map(|i| i * 7919 % N)
+    }
+
+    fn scan_ids(tree: BTree, start: Option<u64>) -> Vec<u64> {
+        let start = start.map(key);
+        tree.scan(start.as_deref())
+            .unwrap()
+            .map(|entry| entry.unwrap().1)
+            .collect()
+    }
+
+    fn depth(tree: &BTree) -> usize {
+        let mut page = tree.root;
+        let mut depth = 1;
+        while let Node::Internal { children, .. } = tree.read_node(page).unwrap() {
+            page = children[0];
+            depth += 1;
+        }
+        depth
+    }
+
+    fn filled(path: &Path) -> BTree {
+        let mut tree = BTree::create(path).unwrap();
+        for i in scrambled() {
+            tree.insert(key(i), i).unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn splits_keep_entries_in_order() {
+        let dir = TempDir::new("btree-split");
+        let path = dir.join("t.idx");
+        let tree = filled(&path);
+        assert!(depth(&tree) >= 3);
+        drop(tree);
+
+        let tree = BTree::open(&path).unwrap();
+        assert_eq!(scan_ids(tree, None), (0..N).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn range_scans_start_at_the_first_key_not_below_start() {
+        let dir = TempDir::new("btree-range");
+        let path = dir.join("t.idx");
+        let mut tree = filled(&path);
+        tree.insert(key(500), N).unwrap();
+
+        let ids = scan_ids(BTree::open(&path).unwrap(), Some(500));
+        assert_eq!(&ids[..3], &[500, N, 501]);
+        assert_eq!(ids.len() as u64, N - 500 + 1);
+
+        let past_end = vec![Value::Integer(N as i64)];
+        let tree = BTree::open(&path).unwrap();
+        assert_eq!(tree.scan(Some(&past_end)).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn removals_merge_nodes_and_reuse_their_pages() {
+        let dir = TempDir::new("btree-merge");
+        let path = dir.join("t.idx");
+        let mut tree = filled(&path);
+        let page_count = tree.page_count;
+
+        for i in scrambled().filter(|i| i % 100 != 0) {
+            assert!(tree.remove(&key(i), i).unwrap());
+        }
+        assert!(!tree.remove(&key(1), 1).unwrap());
+        assert_eq!(depth(&tree), 1);
+        assert_ne!(tree.free_head, 0);
+        drop(tree);
+
+        let mut tree = BTree::open(&path).unwrap();
+        let remaining: Vec<u64> = (0..N).step_by(100).collect();
+        assert_eq!(scan_ids(BTree::open(&path).unwrap(), None), remaining);
+
+        for i in scrambled().filter(|i| i % 100 != 0) {
+            tree.insert(key(i), i).unwrap();
+        }
+        assert_eq!(tree.page_count, page_count);
+        assert_eq!(scan_ids(tree, None), (0..N).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keys_past_the_limit_are_refused() {
+        let dir = TempDir::new("btree-key");
+        let mut tree = BTree::create(&dir.join("t.idx")).unwrap();
+        let long = vec![Value::Blob(vec![0; MAX_KEY_LEN])];
+        assert!(tree.insert(long, 1).is_err());
+
+        let nan = vec![Value::Real(f64::NAN)];
+        tree.insert(nan, 2).unwrap();
+        assert_eq!(scan_ids(tree, None), vec![2]);
+    }
+}
@@ -0,0 +1,852 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::storage::Value;
+
+// Dates are stored as days since 1970-01-01 and timestamps as microseconds
+// since 1970-01-01 00:00:00. Both use the proleptic Gregorian calendar and
+// carry no time zone; NOW() reads the clock in UTC. Only years 1 to 9999 can
+// be written, which keeps every value printable as `YYYY-MM-DD`.
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// Months and days vary in length, so intervals are compared as if every
+/// month had 30 days, as PostgreSQL does.
+const DAYS_PER_MONTH: i64 = 30;
+
+/// Days from 1970-01-01 to 0001-01-01 and to 9999-12-31.
+const MIN_DAYS: i64 = -719_162;
+const MAX_DAYS: i64 = 2_932_896;
+
+/// A span of time. Months, days and microseconds are kept apart because
+/// adding a month or a day to a timestamp depends on where it lands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Interval {
+    pub months: i64,
+    pub days: i64,
+    pub micros: i64,
+}
+
+impl Interval {
+    /// Length in microseconds, counting a month as 30 days.
+    fn approximate_micros(&self) -> i128 {
+        (self.months as i128 * DAYS_PER_MONTH as i128 + self.days as i128) * MICROS_PER_DAY as i128
+            + self.micros as i128
+    }
+
+    pub fn checked_add(&self, other: &Interval) -> Result<Interval> {
+        Ok(Interval {
+            months: self.months.checked_add(other.months).ok_or_else(overflow)?,
+            days: self.days.checked_add(other.days).ok_or_else(overflow)?,
+            micros: self.micros.checked_add(other.micros).ok_or_else(overflow)?,
+        })
+    }
+
+    pub fn checked_neg(&self) -> Result<Interval> {
+        Ok(Interval {
+            months: self.months.checked_neg().ok_or_else(overflow)?,
+            days: self.days.checked_neg().ok_or_else(overflow)?,
+            micros: self.micros.checked_neg().ok_or_else(overflow)?,
+        })
+    }
+
+    pub fn checked_mul(&self, factor: i64) -> Result<Interval> {
+        Ok(Interval {
+            months: self.months.checked_mul(factor).ok_or_else(overflow)?,
+            days: self.days.checked_mul(factor).ok_or_else(overflow)?,
+            micros: self.micros.checked_mul(factor).ok_or_else(overflow)?,
+        })
+    }
+
+    /// Multiplies by a fraction. Fractions of a month carry over into days
+    /// and fractions of a day into microseconds.
+    pub fn scale(&self, factor: f64) -> Result<Interval> {
+        if !factor.is_finite() {
+            return Err(overflow());
+        }
+
+        let months = self.months as f64 * factor;
+        let days = self.days as f64 * factor + months.fract() * DAYS_PER_MONTH as f64;
+        let micros = self.micros as f64 * factor + days.fract() * MICROS_PER_DAY as f64;
+
+        let whole = |value: f64| {
+            if value.abs() < i64::MAX as f64 {
+                Ok(value as i64)
+            } else {
+                Err(overflow())
+            }
+        };
+        Ok(Interval {
+            months: whole(months.trunc())?,
+            days: whole(days.trunc())?,
+            micros: whole(micros.round())?,
+        })
+    }
+}
+
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.approximate_micros()
+            .cmp(&other.approximate_micros())
+            .then_with(|| (self.months, self.days).cmp(&(other.months, other.days)))
+    }
+}
+
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        let mut unit = |count: i64, singular: &str, plural: &str| {
+            if count != 0 {
+                let name = if count.abs() == 1 { singular } else { plural };
+                parts.push(format!("{} {}", count, name));
+            }
+        };
+        unit(self.months / 12, "year", "years");
+        unit(self.months % 12, "mon", "mons");
+        unit(self.days, "day", "days");
+
+        if self.micros != 0 || parts.is_empty() {
+            let sign = if self.micros < 0 { "-" } else { "" };
+            parts.push(format!(
+                "{}{}",
+                sign,
+                format_time(self.micros.unsigned_abs() as i64)
+            ));
+        }
+
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+fn overflow() -> anyhow::Error {
+    anyhow!("Date or time value out of range")
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+/// Counts whole 400-year eras, whose length never changes, from a year that
+/// starts in March so that the leap day falls at the end.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`: the year, month and day of a day count.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn check_days(days: i64) -> Result<i64> {
+    if (MIN_DAYS..=MAX_DAYS).contains(&days) {
+        Ok(days)
+    } else {
+        Err(overflow())
+    }
+}
+
+fn date_value(days: i64) -> Result<Value> {
+    Ok(Value::Date(check_days(days)? as i32))
+}
+
+fn timestamp_value(micros: i64) -> Result<Value> {
+    check_days(micros.div_euclid(MICROS_PER_DAY))?;
+    Ok(Value::Timestamp(micros))
+}
+
+/// Parses an unsigned decimal number of at most `max_digits` digits.
+fn parse_number(text: &str, max_digits: usize) -> Option<i64> {
+    if text.is_empty() || text.len() > max_digits || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+fn parse_date_parts(text: &str) -> Option<i64> {
+    let mut parts = text.split('-');
+    let year = parse_number(parts.next()?, 4)?;
+    let month = parse_number(parts.next()?, 2)? as u32;
+    let day = parse_number(parts.next()?, 2)? as u32;
+    if parts.next().is_some()
+        || year < 1
+        || !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+    {
+        return None;
+    }
+    Some(days_from_civil(year, month, day))
+}
+
+/// Parses `HH:MM[:SS[.ffffff]]` into microseconds since midnight.
+fn parse_time_parts(text: &str) -> Option<i64> {
+    let mut parts = text.split(':');
+    let hour = parse_number(parts.next()?, 2)?;
+    let minute = parse_number(parts.next()?, 2)?;
+    let (second, micros) = match parts.next() {
+        Some(seconds) => {
+            let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+            let micros = if fraction.is_empty() {
+                0
+            } else {
+                parse_number(fraction, 6)? * 10i64.pow(6 - fraction.len() as u32)
+            };
+            (parse_number(whole, 2)?, micros)
+        }
+        None => (0, 0),
+    };
+    if parts.next().is_some() || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    Some(hour * MICROS_PER_HOUR + minute * MICROS_PER_MINUTE + second * MICROS_PER_SECOND + micros)
+}
+
+/// Parses `YYYY-MM-DD`.
+pub fn parse_date(text: &str) -> Result<Value> {
+    let days = parse_date_parts(text.trim()).ok_or_else(|| anyhow!("Invalid date '{}'", text))?;
+    date_value(days)
+}
+
+/// Parses `YYYY-MM-DD[ HH:MM[:SS[.ffffff]]]`, with `T` also accepted
+/// between the date and the time.
+pub fn parse_timestamp(text: &str) -> Result<Value> {
+    let invalid = || anyhow!("Invalid timestamp '{}'", text);
+
+    let trimmed = text.trim();
+    let (date, time) = match trimmed.split_once([' ', 'T']) {
+        Some((date, time)) => (date, parse_time_parts(time.trim()).ok_or_else(invalid)?),
+        None => (trimmed, 0),
+    };
+    let days = parse_date_parts(date).ok_or_else(invalid)?;
+    timestamp_value(days * MICROS_PER_DAY + time)
+}
+
+/// Parses an interval written as quantities and units, such as
+/// `1 year 2 months`, `-3 days` or `1.5 hours`, optionally followed by a
+/// `[-]HH:MM[:SS]` time.
+pub fn parse_interval(text: &str) -> Result<Value> {
+    let invalid = || anyhow!("Invalid interval '{}'", text);
+
+    let mut interval = Interval {
+        months: 0,
+        days: 0,
+        micros: 0,
+    };
+    let mut words = text.split_whitespace().peekable();
+    if words.peek().is_none() {
+        return Err(invalid());
+    }
+
+    while let Some(word) = words.next() {
+        if word.contains(':') {
+            let (negative, time) = match word.strip_prefix('-') {
+                Some(time) => (true, time),
+                None => (false, word.strip_prefix('+').unwrap_or(word)),
+            };
+            let micros = parse_time_parts(time).ok_or_else(invalid)?;
+            let micros = if negative { -micros } else { micros };
+            interval.micros = interval.micros.checked_add(micros).ok_or_else(overflow)?;
+            continue;
+        }
+
+        let quantity: f64 = word.parse().map_err(|_| invalid())?;
+        let unit = words.next().ok_or_else(invalid)?.to_lowercase();
+        let part = match unit.trim_end_matches('s') {
+            "year" | "yr" => Interval {
+                months: 12,
+                days: 0,
+                micros: 0,
+            },
+            "month" | "mon" => Interval {
+                months: 1,
+                days: 0,
+                micros: 0,
+            },
+            "week" => Interval {
+                months: 0,
+                days: 7,
+                micros: 0,
+            },
+            "day" => Interval {
+                months: 0,
+                days: 1,
+                micros: 0,
+            },
+            "hour" | "hr" => Interval {
+                months: 0,
+                days: 0,
+                micros: MICROS_PER_HOUR,
+            },
+            "minute" | "min" => Interval {
+                months: 0,
+                days: 0,
+                micros: MICROS_PER_MINUTE,
+            },
+            "second" | "sec" => Interval {
+                months: 0,
+                days: 0,
+                micros: MICROS_PER_SECOND,
+            },
+            "millisecond" | "m" => Interval {
+                months: 0,
+                days: 0,
+                micros: 1000,
+            },
+            "microsecond" | "u" => Interval {
+                months: 0,
+                days: 0,
+                micros: 1,
+            },
+            _ => return Err(anyhow!("Unknown interval unit '{}' in '{}'", unit, text)),
+        };
+        interval = interval.checked_add(&part.scale(quantity)?)?;
+    }
+
+    Ok(Value::Interval(interval))
+}
+
+fn format_time(micros: i64) -> String {
+    let seconds = micros / MICROS_PER_SECOND;
+    let mut text = format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    );
+    let fraction = micros % MICROS_PER_SECOND;
+    if fraction != 0 {
+        text.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+    }
+    text
+}
+
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub fn format_timestamp(micros: i64) -> String {
+    format!(
+        "{} {}",
+        format_date(micros.div_euclid(MICROS_PER_DAY) as i32),
+        format_time(micros.rem_euclid(MICROS_PER_DAY))
+    )
+}
+
+/// The current time in UTC.
+pub fn now() -> Result<Value> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| anyhow!("System clock is set before 1970"))?;
+    timestamp_value(elapsed.as_micros() as i64)
+}
+
+/// Moves a timestamp by `interval`. Months are added first, keeping the day
+/// of the month unless the new month is shorter, then days, then the time.
+fn add_interval(micros: i64, interval: &Interval) -> Result<i64> {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let time = micros.rem_euclid(MICROS_PER_DAY);
+
+    let (year, month, day) = civil_from_days(days);
+    let months = (year * 12 + month as i64 - 1)
+        .checked_add(interval.months)
+        .ok_or_else(overflow)?;
+    let year = months.div_euclid(12);
+    let month = months.rem_euclid(12) as u32 + 1;
+    if !(1..=9999).contains(&year) {
+        return Err(overflow());
+    }
+    let day = day.min(days_in_month(year, month));
+
+    let days = days_from_civil(year, month, day)
+        .checked_add(interval.days)
+        .ok_or_else(overflow)?;
+    check_days(days)?;
+    (days * MICROS_PER_DAY + time)
+        .checked_add(interval.micros)
+        .ok_or_else(overflow)
+}
+
+fn midnight(days: i32) -> i64 {
+    days as i64 * MICROS_PER_DAY
+}
+
+/// The timestamp at the start of a date.
+pub fn date_to_timestamp(days: i32) -> Value {
+    Value::Timestamp(midnight(days))
+}
+
+/// The date a timestamp falls on.
+pub fn timestamp_to_date(micros: i64) -> Value {
+    Value::Date(micros.div_euclid(MICROS_PER_DAY) as i32)
+}
+
+/// `left + right` or `left - right` where at least one side is a date,
+/// timestamp or interval. Returns `None` for combinations that have no
+/// meaning, such as adding two dates.
+pub fn add(left: &Value, right: &Value, subtract: bool) -> Result<Option<Value>> {
+    let signed = |interval: &Interval| {
+        if subtract {
+            interval.checked_neg()
+        } else {
+            Ok(*interval)
+        }
+    };
+
+    Ok(Some(match (left, right) {
+        (Value::Date(date), Value::Integer(days)) => {
+            let days = if subtract {
+                days.checked_neg()
+            } else {
+                Some(*days)
+            };
+            let days = days
+                .and_then(|days| (*date as i64).checked_add(days))
+                .ok_or_else(overflow)?;
+            date_value(days)?
+        }
+        (Value::Integer(days), Value::Date(date)) if !subtract => {
+            date_value((*date as i64).checked_add(*days).ok_or_else(overflow)?)?
+        }
+        (Value::Date(a), Value::Date(b)) if subtract => Value::Integer(*a as i64 - *b as i64),
+        (Value::Date(date), Value::Interval(interval)) => {
+            timestamp_value(add_interval(midnight(*date), &signed(interval)?)?)?
+        }
+        (Value::Interval(interval), Value::Date(date)) if !subtract => {
+            timestamp_value(add_interval(midnight(*date), interval)?)?
+        }
+        (Value::Timestamp(micros), Value::Interval(interval)) => {
+            timestamp_value(add_interval(*micros, &signed(interval)?)?)?
+        }
+        (Value::Interval(interval), Value::Timestamp(micros)) if !subtract => {
+            timestamp_value(add_interval(*micros, interval)?)?
+        }
+        (Value::Timestamp(a), Value::Timestamp(b)) if subtract => {
+            let micros = a.checked_sub(*b).ok_or_else(overflow)?;
+            Value::Interval(Interval {
+                months: 0,
+                days: micros / MICROS_PER_DAY,
+                micros: micros % MICROS_PER_DAY,
+            })
+        }
+        (Value::Interval(a), Value::Interval(b)) => Value::Interval(a.checked_add(&signed(b)?)?),
+        _ => return Ok(None),
+    }))
+}
+
+/// The fields EXTRACT and DATE_TRUNC understand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    DayOfWeek,
+    IsoDayOfWeek,
+    DayOfYear,
+    Epoch,
+}
+
+fn parse_field(name: &str) -> Result<Field> {
+    Ok(match name.to_lowercase().as_str() {
+        "year" | "years" => Field::Year,
+        "quarter" => Field::Quarter,
+        "month" | "months" => Field::Month,
+        "week" | "weeks" => Field::Week,
+        "day" | "days" => Field::Day,
+        "hour" | "hours" => Field::Hour,
+        "minute" | "minutes" => Field::Minute,
+        "second" | "seconds" => Field::Second,
+        "dow" => Field::DayOfWeek,
+        "isodow" => Field::IsoDayOfWeek,
+        "doy" => Field::DayOfYear,
+        "epoch" => Field::Epoch,
+        _ => return Err(anyhow!("Unknown date field '{}'", name)),
+    })
+}
+
+/// ISO day of the week, from 1 for Monday to 7 for Sunday. 1970-01-01 was a
+/// Thursday.
+fn iso_day_of_week(days: i64) -> i64 {
+    (days + 3).rem_euclid(7) + 1
+}
+
+/// ISO 8601 week number: weeks start on Monday, and week 1 is the one that
+/// holds the year's first Thursday.
+fn iso_week(days: i64) -> i64 {
+    let thursday = days - iso_day_of_week(days) + 4;
+    let (year, _, _) = civil_from_days(thursday);
+    (thursday - days_from_civil(year, 1, 1)) / 7 + 1
+}
+
+/// `EXTRACT(field FROM value)`. SECOND and EPOCH come back as REAL so they
+/// can carry fractions of a second; every other field is an INTEGER.
+pub fn extract(field: &str, value: &Value) -> Result<Value> {
+    let field = parse_field(field)?;
+
+    let micros = match value {
+        Value::Null => return Ok(Value::Null),
+        Value::Date(days) => midnight(*days),
+        Value::Timestamp(micros) => *micros,
+        Value::Interval(interval) => return extract_interval(field, interval),
+        v => {
+            return Err(anyhow!(
+                "EXTRACT expects a DATE, TIMESTAMP or INTERVAL, got {:?}",
+                v
+            ));
+        }
+    };
+
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let time = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    Ok(match field {
+        Field::Year => Value::Integer(year),
+        Field::Quarter => Value::Integer((month as i64 - 1) / 3 + 1),
+        Field::Month => Value::Integer(month as i64),
+        Field::Week => Value::Integer(iso_week(days)),
+        Field::Day => Value::Integer(day as i64),
+        Field::Hour => Value::Integer(time / MICROS_PER_HOUR),
+        Field::Minute => Value::Integer(time / MICROS_PER_MINUTE % 60),
+        Field::Second => Value::Real((time % MICROS_PER_MINUTE) as f64 / MICROS_PER_SECOND as f64),
+        Field::DayOfWeek => Value::Integer(iso_day_of_week(days) % 7),
+        Field::IsoDayOfWeek => Value::Integer(iso_day_of_week(days)),
+        Field::DayOfYear => Value::Integer(days - days_from_civil(year, 1, 1) + 1),
+        Field::Epoch => Value::Real(micros as f64 / MICROS_PER_SECOND as f64),
+    })
+}
+
+fn extract_interval(field: Field, interval: &Interval) -> Result<Value> {
+    Ok(match field {
+        Field::Year => Value::Integer(interval.months / 12),
+        Field::Quarter => Value::Integer(interval.months % 12 / 3 + 1),
+        Field::Month => Value::Integer(interval.months % 12),
+        Field::Day => Value::Integer(interval.days),
+        Field::Hour => Value::Integer(interval.micros / MICROS_PER_HOUR),
+        Field::Minute => Value::Integer(interval.micros / MICROS_PER_MINUTE % 60),
+        Field::Second => {
+            Value::Real((interval.micros % MICROS_PER_MINUTE) as f64 / MICROS_PER_SECOND as f64)
+        }
+        Field::Epoch => {
+            Value::Real(interval.approximate_micros() as f64 / MICROS_PER_SECOND as f64)
+        }
+        _ => return Err(anyhow!("Field {:?} does not apply to an INTERVAL", field)),
+    })
+}
+
+/// `DATE_TRUNC(field, value)`: the value with everything finer than `field`
+/// set to its lowest. A DATE stays a DATE, so truncating one to an hour or
+/// less leaves it as it is.
+pub fn date_trunc(field: &str, value: &Value) -> Result<Value> {
+    let field = parse_field(field)?;
+
+    let (micros, is_date) = match value {
+        Value::Null => return Ok(Value::Null),
+        Value::Date(days) => (midnight(*days), true),
+        Value::Timestamp(micros) => (*micros, false),
+        v => {
+            return Err(anyhow!(
+                "DATE_TRUNC expects a DATE or TIMESTAMP, got {:?}",
+                v
+            ));
+        }
+    };
+
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let time = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, _) = civil_from_days(days);
+
+    let truncated_days = match field {
+        Field::Year => days_from_civil(year, 1, 1),
+        Field::Quarter => days_from_civil(year, (month - 1) / 3 * 3 + 1, 1),
+        Field::Month => days_from_civil(year, month, 1),
+        Field::Week => days - iso_day_of_week(days) + 1,
+        Field::Day | Field::Hour | Field::Minute | Field::Second => days,
+        _ => return Err(anyhow!("DATE_TRUNC does not support field {:?}", field)),
+    };
+    if is_date {
+        return date_value(truncated_days);
+    }
+
+    let truncated_time = match field {
+        Field::Hour => time - time % MICROS_PER_HOUR,
+        Field::Minute => time - time % MICROS_PER_MINUTE,
+        Field::Second => time - time % MICROS_PER_SECOND,
+        _ => 0,
+    };
+    timestamp_value(truncated_days * MICROS_PER_DAY + truncated_time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{TempDir, integers, open, query};
+
+    fn date(text: &str) -> Value {
+        parse_date(text).unwrap()
+    }
+
+    fn timestamp(text: &str) -> Value {
+        parse_timestamp(text).unwrap()
+    }
+
+    fn interval(text: &str) -> Value {
+        parse_interval(text).unwrap()
+    }
+
+    fn sum(left: &Value, right: &Value) -> String {
+        add(left, right, false).unwrap().unwrap().to_string()
+    }
+
+    fn difference(left: &Value, right: &Value) -> String {
+        add(left, right, true).unwrap().unwrap().to_string()
+    }
+
+    #[test]
+    fn dates_and_timestamps_print_as_they_parse() {
+        for text in [
+            "2026-01-31",
+            "2024-02-29",
+            "2000-02-29",
+            "1969-12-31",
+            "0001-01-01",
+            "9999-12-31",
+        ] {
+            assert_eq!(date(text).to_string(), text);
+        }
+        for text in [
+            "2026-01-31 23:59:59.5",
+            "1969-12-31 00:00:01",
+            "2024-02-29 12:00:00",
+        ] {
+            assert_eq!(timestamp(text).to_string(), text);
+        }
+        assert_eq!(
+            timestamp("2024-02-29T12:00"),
+            timestamp("2024-02-29 12:00:00")
+        );
+        assert_eq!(timestamp("2024-02-29"), timestamp("2024-02-29 00:00:00"));
+
+        for text in [
+            "2026-02-30",
+            "2025-02-29",
+            "1900-02-29",
+            "2026-13-01",
+            "10000-01-01",
+            "2026-1-x",
+        ] {
+            assert!(parse_date(text).is_err(), "{}", text);
+        }
+        for text in [
+            "2026-01-31 24:00",
+            "2026-01-31 12:60",
+            "2026-01-31 12:00:00 x",
+        ] {
+            assert!(parse_timestamp(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn intervals_keep_months_days_and_time_apart() {
+        assert_eq!(interval("1 month").to_string(), "1 mon");
+        assert_eq!(
+            interval("1 year 2 days 03:04:05").to_string(),
+            "1 year 2 days 03:04:05"
+        );
+        assert_eq!(interval("-3 days").to_string(), "-3 days");
+        assert_eq!(interval("1.5 hours"), interval("01:30:00"));
+        assert_eq!(interval("2 weeks"), interval("14 days"));
+        assert!(parse_interval("").is_err());
+        assert!(parse_interval("3 fortnights").is_err());
+
+        // Compared as if every month had 30 days.
+        assert!(interval("1 month") > interval("29 days"));
+        assert!(interval("1 month") < interval("31 days"));
+    }
+
+    #[test]
+    fn arithmetic_lands_on_real_calendar_days() {
+        assert_eq!(sum(&date("2026-01-31"), &Value::Integer(1)), "2026-02-01");
+        assert_eq!(
+            difference(&date("2026-03-01"), &Value::Integer(1)),
+            "2026-02-28"
+        );
+        assert_eq!(difference(&date("2026-01-31"), &date("2024-01-01")), "761");
+
+        // A month later is the same day, or the last one of a shorter month.
+        assert_eq!(
+            sum(&date("2026-01-31"), &interval("1 month")),
+            "2026-02-28 00:00:00"
+        );
+        assert_eq!(
+            sum(&date("2024-01-31"), &interval("1 month")),
+            "2024-02-29 00:00:00"
+        );
+        assert_eq!(
+            sum(
+                &timestamp("2024-02-29 12:00:00"),
+                &interval("1 year 2 days 03:04:05")
+            ),
+            "2025-03-02 15:04:05"
+        );
+        assert_eq!(
+            difference(
+                &timestamp("2024-02-29 12:00:00"),
+                &timestamp("2024-02-28 00:00:00")
+            ),
+            "1 day 12:00:00"
+        );
+
+        assert!(
+            add(&date("2026-01-01"), &date("2026-01-02"), false)
+                .unwrap()
+                .is_none()
+        );
+        assert!(add(&date("9999-12-31"), &Value::Integer(1), false).is_err());
+    }
+
+    #[test]
+    fn extract_and_date_trunc_pick_calendar_fields() {
+        let ts = timestamp("2026-01-31 23:59:59.5");
+        for (field, expected) in [
+            ("year", Value::Integer(2026)),
+            ("quarter", Value::Integer(1)),
+            ("month", Value::Integer(1)),
+            ("day", Value::Integer(31)),
+            ("hour", Value::Integer(23)),
+            ("second", Value::Real(59.5)),
+            ("dow", Value::Integer(6)),
+            ("isodow", Value::Integer(6)),
+            ("doy", Value::Integer(31)),
+            ("week", Value::Integer(5)),
+            ("epoch", Value::Real(1_769_903_999.5)),
+        ] {
+            assert_eq!(extract(field, &ts).unwrap(), expected, "{}", field);
+        }
+        assert_eq!(
+            extract("week", &date("2027-01-01")).unwrap(),
+            Value::Integer(53)
+        );
+        assert_eq!(
+            extract("day", &interval("1 year 2 days")).unwrap(),
+            Value::Integer(2)
+        );
+        assert_eq!(extract("year", &Value::Null).unwrap(), Value::Null);
+        assert!(extract("century", &ts).is_err());
+
+        for (field, expected) in [
+            ("quarter", "2026-01-01 00:00:00"),
+            ("month", "2026-01-01 00:00:00"),
+            ("week", "2026-01-26 00:00:00"),
+            ("hour", "2026-01-31 23:00:00"),
+            ("second", "2026-01-31 23:59:59"),
+        ] {
+            assert_eq!(
+                date_trunc(field, &ts).unwrap().to_string(),
+                expected,
+                "{}",
+                field
+            );
+        }
+        assert_eq!(
+            date_trunc("month", &date("2026-01-31")).unwrap(),
+            date("2026-01-01")
+        );
+    }
+
+    #[test]
+    fn index_keys_order_by_time() {
+        let dir = TempDir::new("datetime-index");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE ev (id INTEGER, ts TIMESTAMP, d DATE)",
+        );
+        query(&mut storage, "CREATE INDEX evt ON ev (ts)");
+        query(&mut storage, "CREATE INDEX evd ON ev (d)");
+        for (id, ts) in [
+            (1, "2026-01-31 23:59:59.5"),
+            (2, "2024-02-29 12:00:00"),
+            (3, "1969-12-31 00:00:01"),
+            (4, "1970-01-01 00:00:00"),
+            (5, "0001-01-01 00:00:00"),
+        ] {
+            query(
+                &mut storage,
+                &format!(
+                    "INSERT INTO ev VALUES ({}, TIMESTAMP '{}', CAST(TIMESTAMP '{}' AS DATE))",
+                    id, ts, ts
+                ),
+            );
+        }
+
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT id FROM ev WHERE ts >= TIMESTAMP '0001-01-01'"
+            )),
+            vec![5, 3, 4, 2, 1]
+        );
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT id FROM ev WHERE ts BETWEEN TIMESTAMP '1969-12-31' AND TIMESTAMP '2025-01-01'"
+            )),
+            vec![3, 4, 2]
+        );
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT id FROM ev WHERE d < DATE '1970-01-01'"
+            )),
+            vec![5, 3]
+        );
+    }
+}
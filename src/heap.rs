@@ -0,0 +1,715 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::row::{decode_values, encode_values};
+use crate::storage::Value;
+
+const PAGE_SIZE: usize = 4096;
+
+const MAGIC: &[u8; 8] = b"SCYHEAP\0";
+const VERSION: u32 = 1;
+
+/// Bytes taken by the page header: the slot count and the start of the
+/// record area, both `u16`.
+const PAGE_HEADER_LEN: usize = 4;
+
+/// Bytes taken by one slot: the record's offset within the page and its
+/// length, both `u16`. An offset of zero marks a deleted row, since no
+/// record can start inside the page header.
+const SLOT_LEN: usize = 4;
+
+/// Records longer than this are moved to overflow pages, leaving only a
+/// pointer in the slot, so one large row does not leave most of a data page
+/// empty.
+const MAX_INLINE_LEN: usize = PAGE_SIZE / 4;
+
+/// Set in a slot's length when the slot holds an overflow pointer rather
+/// than the record itself. Inline records are always shorter than a page.
+const OVERFLOW_FLAG: u16 = 0x8000;
+
+/// An overflow pointer: the first overflow page and the record length, both
+/// `u32`.
+const OVERFLOW_POINTER_LEN: usize = 8;
+
+/// Bytes of an overflow page before its share of the record: the zeroed
+/// slot count and free end, then the next page of the chain as a `u32`.
+const OVERFLOW_HEADER_LEN: usize = 8;
+
+const OVERFLOW_DATA_LEN: usize = PAGE_SIZE - OVERFLOW_HEADER_LEN;
+
+// Heap file layout:
+//
+//   page 0    header page: MAGIC version:u32, rest unused
+//   page n    slot_count:u16 free_end:u16 slot* ... free ... record*
+//   slot      offset:u16 len:u16
+//   overflow  0:u16 0:u16 next:u32 data
+//   pointer   first:u32 len:u32
+//
+// Records are the encoded row values. They are packed from the end of the
+// page towards its start while the slot directory grows from the front, so
+// a page is full once the two meet. Rows are only ever appended, which keeps
+// row ids increasing in file order.
+//
+// A record longer than MAX_INLINE_LEN is split over a chain of overflow
+// pages and its slot holds a pointer to the chain, with OVERFLOW_FLAG set in
+// the slot's length. Overflow pages are appended after the data page being
+// filled, which stays the last data page of the file. A free end of zero
+// tells them apart from data pages, and having no slots they add no rows to
+// a scan. The last page of a chain has a next of zero.
+
+/// Identifies a row by its page and slot, packed as `page << 16 | slot`.
+/// Row ids never change once assigned, so they are what index entries and
+/// the write-ahead log refer to.
+pub fn row_id(page: u32, slot: u16) -> u64 {
+    ((page as u64) << 16) | slot as u64
+}
+
+fn split_row_id(row_id: u64) -> (u32, u16) {
+    ((row_id >> 16) as u32, row_id as u16)
+}
+
+/// Where the next appended row will go: the last data page and the state of
+/// its header, and the end of the file, past any overflow pages that follow
+/// that page. A cursor without slots stands for a fresh, empty page.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AppendCursor {
+    page: u32,
+    slot_count: u16,
+    free_end: u16,
+    /// Zero in cursors logged before overflow pages existed; see `end()`.
+    #[serde(default)]
+    end: u32,
+}
+
+impl AppendCursor {
+    /// The cursor of a heap file that holds no rows yet.
+    pub fn empty() -> Self {
+        AppendCursor::fresh(1)
+    }
+
+    fn fresh(page: u32) -> Self {
+        AppendCursor {
+            page,
+            slot_count: 0,
+            free_end: PAGE_SIZE as u16,
+            end: page,
+        }
+    }
+
+    /// The number of pages the file has in the state this cursor describes.
+    fn end(&self) -> u32 {
+        let data_end = if self.slot_count == 0 {
+            self.page
+        } else {
+            self.page + 1
+        };
+        self.end.max(data_end)
+    }
+
+    /// The id the next row will get if it fits on the current page. Every
+    /// row appended from here on has an id at least this large.
+    pub fn next_row_id(&self) -> u64 {
+        row_id(self.page, self.slot_count)
+    }
+
+    /// Reserves room for a record of `len` bytes, moving on to a new page
+    /// when the current one is full, and returns the row id it gets. A
+    /// record too long to keep inline also reserves its overflow pages,
+    /// which end up just before `end`.
+    pub fn place(&mut self, len: usize) -> Result<u64> {
+        if len > u32::MAX as usize {
+            return Err(anyhow!("Row of {} bytes is too large", len));
+        }
+
+        let inline_len = inline_len(len);
+        let used = PAGE_HEADER_LEN + self.slot_count as usize * SLOT_LEN;
+        if used + SLOT_LEN + inline_len > self.free_end as usize {
+            *self = AppendCursor::fresh(self.end());
+        }
+
+        let id = row_id(self.page, self.slot_count);
+        self.slot_count += 1;
+        self.free_end -= inline_len as u16;
+        self.end = self.end() + overflow_page_count(len);
+        Ok(id)
+    }
+}
+
+/// The bytes a record of `len` bytes takes on its data page.
+fn inline_len(len: usize) -> usize {
+    if len > MAX_INLINE_LEN {
+        OVERFLOW_POINTER_LEN
+    } else {
+        len
+    }
+}
+
+fn overflow_page_count(len: usize) -> u32 {
+    if len > MAX_INLINE_LEN {
+        len.div_ceil(OVERFLOW_DATA_LEN) as u32
+    } else {
+        0
+    }
+}
+
+fn is_overflow_page(page: &[u8]) -> bool {
+    read_u16(page, 2) == 0
+}
+
+type PageKey = (PathBuf, u32);
+
+/// A cached page and the pool clock as of its last use.
+type CachedPage = (Rc<Vec<u8>>, u64);
+
+/// A bounded cache of heap pages shared by every table, evicting the least
+/// recently used page once full. Writes go straight through to the file, so
+/// the cache never holds anything the disk does not.
+pub struct BufferPool {
+    capacity: usize,
+    pages: HashMap<PageKey, CachedPage>,
+    /// Every use of a page, oldest first, with the clock at the time. Only
+    /// the entry matching a page's last use counts; the others are stale and
+    /// skipped, so eviction just pops from the front.
+    recency: VecDeque<(PageKey, u64)>,
+    files: HashMap<PathBuf, File>,
+    clock: u64,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        BufferPool {
+            capacity,
+            pages: HashMap::new(),
+            recency: VecDeque::new(),
+            files: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn file(&mut self, path: &Path) -> Result<&mut File> {
+        if !self.files.contains_key(path) {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            self.files.insert(path.to_path_buf(), file);
+        }
+        Ok(self.files.get_mut(path).unwrap())
+    }
+
+    pub fn page_count(&mut self, path: &Path) -> Result<u32> {
+        let len = self.file(path)?.metadata()?.len();
+        Ok(len.div_ceil(PAGE_SIZE as u64) as u32)
+    }
+
+    pub fn read_page(&mut self, path: &Path, page: u32) -> Result<Rc<Vec<u8>>> {
+        let key = (path.to_path_buf(), page);
+        if let Some((data, used)) = self.pages.get_mut(&key) {
+            self.clock += 1;
+            *used = self.clock;
+            let data = data.clone();
+            self.touch(key);
+            return Ok(data);
+        }
+
+        let file = self.file(path)?;
+        let mut data = vec![0u8; PAGE_SIZE];
+        file.seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))?;
+        file.read_exact(&mut data)?;
+
+        let data = Rc::new(data);
+        self.insert(key, data.clone());
+        Ok(data)
+    }
+
+    pub fn write_page(&mut self, path: &Path, page: u32, data: Vec<u8>) -> Result<()> {
+        let file = self.file(path)?;
+        file.seek(SeekFrom::Start(page as u64 * PAGE_SIZE as u64))?;
+        file.write_all(&data)?;
+
+        self.insert((path.to_path_buf(), page), Rc::new(data));
+        Ok(())
+    }
+
+    /// Cuts the file down to its first `pages` pages.
+    pub fn truncate(&mut self, path: &Path, pages: u32) -> Result<()> {
+        self.file(path)?.set_len(pages as u64 * PAGE_SIZE as u64)?;
+        self.pages
+            .retain(|(page_path, page), _| page_path != path || *page < pages);
+        Ok(())
+    }
+
+    pub fn sync(&mut self, path: &Path) -> Result<()> {
+        self.file(path)?.sync_data()?;
+        Ok(())
+    }
+
+    /// Forgets everything cached for `path`, for when the file is replaced
+    /// or removed behind the pool's back.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.files.remove(path);
+        self.pages.retain(|(page_path, _), _| page_path != path);
+    }
+
+    fn insert(&mut self, key: PageKey, data: Rc<Vec<u8>>) {
+        if !self.pages.contains_key(&key) {
+            while self.pages.len() >= self.capacity {
+                let Some((oldest, used)) = self.recency.pop_front() else {
+                    break;
+                };
+                if self
+                    .pages
+                    .get(&oldest)
+                    .is_some_and(|(_, last)| *last == used)
+                {
+                    self.pages.remove(&oldest);
+                }
+            }
+        }
+
+        self.clock += 1;
+        self.pages.insert(key.clone(), (data, self.clock));
+        self.touch(key);
+    }
+
+    /// Records a use of `key` at the current clock. Stale entries are
+    /// dropped in one pass once they outnumber the cached pages, which keeps
+    /// the queue bounded at a constant cost per use.
+    fn touch(&mut self, key: PageKey) {
+        self.recency.push_back((key, self.clock));
+        if self.recency.len() > 2 * self.capacity.max(self.pages.len()) + 16 {
+            let pages = &self.pages;
+            self.recency
+                .retain(|(key, used)| pages.get(key).is_some_and(|(_, last)| last == used));
+        }
+    }
+}
+
+/// Creates an empty heap file at `path`, replacing whatever was there.
+pub fn create(pool: &mut BufferPool, path: &Path) -> Result<()> {
+    pool.invalidate(path);
+    File::create(path)?;
+
+    let mut header = vec![0u8; PAGE_SIZE];
+    header[..8].copy_from_slice(MAGIC);
+    header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+    pool.write_page(path, 0, header)?;
+    pool.sync(path)
+}
+
+/// Whether `path` is a heap file, as opposed to one of the older row formats.
+pub fn is_heap_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Reads the header page and checks that this build understands the file.
+fn check_header(pool: &mut BufferPool, path: &Path) -> Result<()> {
+    let header = pool.read_page(path, 0)?;
+    if &header[..8] != MAGIC {
+        return Err(anyhow!("{} is not a heap file", path.display()));
+    }
+
+    let version = u32::from_le_bytes(header[8..12].try_into()?);
+    if version != VERSION {
+        return Err(anyhow!(
+            "{} has unsupported heap format version {}",
+            path.display(),
+            version
+        ));
+    }
+    Ok(())
+}
+
+pub fn append_cursor(pool: &mut BufferPool, path: &Path) -> Result<AppendCursor> {
+    let page_count = pool.page_count(path)?;
+
+    // Step back over the overflow pages of rows on the last data page.
+    let mut last = page_count.saturating_sub(1);
+    while last > 0 {
+        let data = pool.read_page(path, last)?;
+        if !is_overflow_page(&data) {
+            return Ok(AppendCursor {
+                page: last,
+                slot_count: read_u16(&data, 0),
+                free_end: read_u16(&data, 2),
+                end: page_count,
+            });
+        }
+        last -= 1;
+    }
+
+    Ok(AppendCursor::fresh(page_count.max(1)))
+}
+
+/// Appends `rows` starting at `at` and returns their row ids. The file is
+/// first put back into the state `at` describes, dropping anything a
+/// previous, interrupted attempt wrote past it, so replaying the same append
+/// gives the same ids. Rows already on the starting page are left alone.
+pub fn append(
+    pool: &mut BufferPool,
+    path: &Path,
+    at: AppendCursor,
+    rows: &[Vec<Value>],
+) -> Result<Vec<u64>> {
+    pool.truncate(path, at.end())?;
+    let mut page = if at.slot_count == 0 {
+        new_page()
+    } else {
+        pool.read_page(path, at.page)?.to_vec()
+    };
+
+    let mut cursor = at;
+    let mut page_number = at.page;
+    let mut row_ids = Vec::with_capacity(rows.len());
+    for row in rows {
+        let record = encode_values(row);
+        let id = cursor.place(record.len())?;
+        let (id_page, slot) = split_row_id(id);
+
+        if id_page != page_number {
+            pool.write_page(path, page_number, std::mem::replace(&mut page, new_page()))?;
+            page_number = id_page;
+        }
+
+        let (stored, len) = match overflow_page_count(record.len()) {
+            0 => (record, 0),
+            pages => {
+                let first = cursor.end - pages;
+                write_overflow(pool, path, first, &record)?;
+
+                let mut pointer = Vec::with_capacity(OVERFLOW_POINTER_LEN);
+                pointer.extend_from_slice(&first.to_le_bytes());
+                pointer.extend_from_slice(&(record.len() as u32).to_le_bytes());
+                (pointer, OVERFLOW_FLAG)
+            }
+        };
+
+        let offset = cursor.free_end as usize;
+        page[offset..offset + stored.len()].copy_from_slice(&stored);
+
+        let slot_pos = PAGE_HEADER_LEN + slot as usize * SLOT_LEN;
+        write_u16(&mut page, slot_pos, offset as u16);
+        write_u16(&mut page, slot_pos + 2, stored.len() as u16 | len);
+        write_u16(&mut page, 0, cursor.slot_count);
+        write_u16(&mut page, 2, cursor.free_end);
+
+        row_ids.push(id);
+    }
+    pool.write_page(path, page_number, page)?;
+
+    Ok(row_ids)
+}
+
+/// Writes `record` over the chain of overflow pages starting at `first`.
+fn write_overflow(pool: &mut BufferPool, path: &Path, first: u32, record: &[u8]) -> Result<()> {
+    let chunks = record.chunks(OVERFLOW_DATA_LEN);
+    let last = first + chunks.len() as u32 - 1;
+    for (page_number, chunk) in (first..).zip(chunks) {
+        let next = if page_number == last {
+            0
+        } else {
+            page_number + 1
+        };
+
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[4..8].copy_from_slice(&next.to_le_bytes());
+        page[OVERFLOW_HEADER_LEN..OVERFLOW_HEADER_LEN + chunk.len()].copy_from_slice(chunk);
+        pool.write_page(path, page_number, page)?;
+    }
+    Ok(())
+}
+
+/// Reads a record of `len` bytes back from the overflow chain at `first`.
+fn read_overflow(pool: &mut BufferPool, path: &Path, first: u32, len: usize) -> Result<Vec<u8>> {
+    let mut record = Vec::with_capacity(len);
+    let mut page_number = first;
+    while record.len() < len {
+        if page_number == 0 {
+            return Err(anyhow!("Corrupt page: overflow chain ends early"));
+        }
+        let page = pool.read_page(path, page_number)?;
+        let take = (len - record.len()).min(OVERFLOW_DATA_LEN);
+        record.extend_from_slice(&page[OVERFLOW_HEADER_LEN..OVERFLOW_HEADER_LEN + take]);
+        page_number = u32::from_le_bytes(page[4..8].try_into()?);
+    }
+    Ok(record)
+}
+
+/// Marks the row as deleted. Its space, and that of any overflow pages, is
+/// not reused, so the ids of other rows stay put.
+pub fn delete(pool: &mut BufferPool, path: &Path, id: u64) -> Result<()> {
+    let (page_number, slot) = split_row_id(id);
+    let mut page = pool.read_page(path, page_number)?.to_vec();
+    if slot >= read_u16(&page, 0) {
+        return Ok(());
+    }
+
+    write_u16(&mut page, PAGE_HEADER_LEN + slot as usize * SLOT_LEN, 0);
+    pool.write_page(path, page_number, page)
+}
+
+/// Reads a single row, or `None` if it has been deleted.
+pub fn read(pool: &mut BufferPool, path: &Path, id: u64) -> Result<Option<Vec<Value>>> {
+    let (page_number, slot) = split_row_id(id);
+    if page_number == 0 || page_number >= pool.page_count(path)? {
+        return Ok(None);
+    }
+
+    let page = pool.read_page(path, page_number)?;
+    read_slot(pool, path, &page, slot)
+}
+
+fn read_slot(
+    pool: &mut BufferPool,
+    path: &Path,
+    page: &[u8],
+    slot: u16,
+) -> Result<Option<Vec<Value>>> {
+    if slot >= read_u16(page, 0) {
+        return Ok(None);
+    }
+
+    let slot_pos = PAGE_HEADER_LEN + slot as usize * SLOT_LEN;
+    let offset = read_u16(page, slot_pos) as usize;
+    let len = read_u16(page, slot_pos + 2);
+    let overflow = len & OVERFLOW_FLAG != 0;
+    let len = (len & !OVERFLOW_FLAG) as usize;
+    if offset == 0 {
+        return Ok(None);
+    }
+    if offset + len > PAGE_SIZE {
+        return Err(anyhow!(
+            "Corrupt page: slot {} runs past the page end",
+            slot
+        ));
+    }
+
+    let stored = &page[offset..offset + len];
+    if !overflow {
+        return Ok(Some(decode_values(stored)?));
+    }
+    if len != OVERFLOW_POINTER_LEN {
+        return Err(anyhow!(
+            "Corrupt page: bad overflow pointer in slot {}",
+            slot
+        ));
+    }
+    let first = u32::from_le_bytes(stored[..4].try_into()?);
+    let record_len = u32::from_le_bytes(stored[4..].try_into()?) as usize;
+    Ok(Some(decode_values(&read_overflow(
+        pool, path, first, record_len,
+    )?)?))
+}
+
+/// Feeds every live row and its id to `visit` in row id order, reading each
+/// page once. Stops early once `visit` returns `false`.
+pub fn scan(
+    pool: &RefCell<BufferPool>,
+    path: &Path,
+    mut visit: impl FnMut(u64, Vec<Value>) -> Result<bool>,
+) -> Result<()> {
+    for item in Scan::new(pool, path)? {
+        let (id, row) = item?;
+        if !visit(id, row)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Iterates over the live rows of a heap file and their ids in row id
+/// order. Each page is fetched from the pool once, when the scan reaches it.
+pub struct Scan<'a> {
+    pool: &'a RefCell<BufferPool>,
+    path: PathBuf,
+    page_count: u32,
+    page_number: u32,
+    page: Option<Rc<Vec<u8>>>,
+    slot: u16,
+}
+
+impl<'a> Scan<'a> {
+    pub fn new(pool: &'a RefCell<BufferPool>, path: &Path) -> Result<Self> {
+        let page_count = {
+            let mut pool = pool.borrow_mut();
+            check_header(&mut pool, path)?;
+            pool.page_count(path)?
+        };
+
+        Ok(Scan {
+            pool,
+            path: path.to_path_buf(),
+            page_count,
+            page_number: 0,
+            page: None,
+            slot: 0,
+        })
+    }
+
+    fn next_row(&mut self) -> Result<Option<(u64, Vec<Value>)>> {
+        loop {
+            let page = match &self.page {
+                Some(page) => page,
+                None => {
+                    if self.page_number + 1 >= self.page_count {
+                        return Ok(None);
+                    }
+                    self.page_number += 1;
+                    self.slot = 0;
+                    // The pool is only borrowed for the read itself, so the
+                    // caller is free to use it between rows.
+                    let page = self
+                        .pool
+                        .borrow_mut()
+                        .read_page(&self.path, self.page_number)?;
+                    self.page.insert(page)
+                }
+            };
+
+            if self.slot >= read_u16(page, 0) {
+                self.page = None;
+                continue;
+            }
+
+            let slot = self.slot;
+            self.slot += 1;
+            let row = read_slot(&mut self.pool.borrow_mut(), &self.path, page, slot)?;
+            if let Some(row) = row {
+                return Ok(Some((row_id(self.page_number, slot), row)));
+            }
+        }
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = Result<(u64, Vec<Value>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+fn new_page() -> Vec<u8> {
+    let mut page = vec![0u8; PAGE_SIZE];
+    write_u16(&mut page, 2, PAGE_SIZE as u16);
+    page
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn write_u16(data: &mut [u8], pos: usize, value: u16) {
+    data[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    fn row(i: i64, len: usize) -> Vec<Value> {
+        vec![Value::Integer(i), Value::Text("x".repeat(len))]
+    }
+
+    fn append_rows(pool: &mut BufferPool, path: &Path, rows: &[Vec<Value>]) -> Vec<u64> {
+        let at = append_cursor(pool, path).unwrap();
+        append(pool, path, at, rows).unwrap()
+    }
+
+    fn live_ids(pool: BufferPool, path: &Path) -> Vec<u64> {
+        let pool = RefCell::new(pool);
+        Scan::new(&pool, path)
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn deleted_slots_are_never_handed_out_again() {
+        let dir = TempDir::new("heap-delete");
+        let path = dir.join("t.data");
+        let mut pool = BufferPool::new(16);
+        create(&mut pool, &path).unwrap();
+
+        let ids = append_rows(
+            &mut pool,
+            &path,
+            &(0..50).map(|i| row(i, 100)).collect::<Vec<_>>(),
+        );
+        assert!(split_row_id(ids[49]).0 > 1);
+
+        delete(&mut pool, &path, ids[0]).unwrap();
+        delete(&mut pool, &path, ids[49]).unwrap();
+        assert_eq!(read(&mut pool, &path, ids[0]).unwrap(), None);
+        assert_eq!(read(&mut pool, &path, ids[1]).unwrap(), Some(row(1, 100)));
+
+        // New rows go after every existing id, deleted ones included, and
+        // the surviving rows keep theirs.
+        let new_ids = append_rows(&mut pool, &path, &[row(50, 100)]);
+        assert!(new_ids[0] > ids[49]);
+
+        let mut expected = ids[1..49].to_vec();
+        expected.extend(new_ids);
+        assert_eq!(live_ids(pool, &path), expected);
+    }
+
+    #[test]
+    fn large_rows_round_trip_through_overflow_pages() {
+        let dir = TempDir::new("heap-overflow");
+        let path = dir.join("t.data");
+        let mut pool = BufferPool::new(16);
+        create(&mut pool, &path).unwrap();
+
+        let rows = vec![row(0, 10), row(1, 20_000), row(2, 10), row(3, 5000)];
+        let ids = append_rows(&mut pool, &path, &rows);
+        for (id, expected) in ids.iter().zip(&rows) {
+            assert_eq!(
+                read(&mut pool, &path, *id).unwrap().as_ref(),
+                Some(expected)
+            );
+        }
+
+        // Later rows still land after the overflow pages.
+        let more = append_rows(&mut pool, &path, &[row(4, 10)]);
+        assert_eq!(read(&mut pool, &path, more[0]).unwrap(), Some(row(4, 10)));
+        assert_eq!(live_ids(pool, &path).len(), 5);
+    }
+
+    #[test]
+    fn the_pool_evicts_the_least_recently_used_page() {
+        let dir = TempDir::new("heap-pool");
+        let path = dir.join("t.data");
+        let mut pool = BufferPool::new(3);
+        create(&mut pool, &path).unwrap();
+        for page in 1..5 {
+            pool.write_page(&path, page, vec![page as u8; PAGE_SIZE])
+                .unwrap();
+        }
+        let cached = |pool: &BufferPool, page: u32| pool.pages.contains_key(&(path.clone(), page));
+
+        // Pages 2, 3 and 4 are cached; using 2 again leaves 3 the oldest.
+        pool.read_page(&path, 2).unwrap();
+        pool.read_page(&path, 1).unwrap();
+        assert!(cached(&pool, 1) && cached(&pool, 2) && cached(&pool, 4));
+        assert!(!cached(&pool, 3));
+
+        assert_eq!(pool.read_page(&path, 3).unwrap()[0], 3);
+        assert!(!cached(&pool, 4));
+        assert_eq!(pool.pages.len(), 3);
+
+        // Repeated hits leave the recency queue bounded.
+        for _ in 0..1000 {
+            pool.read_page(&path, 1).unwrap();
+        }
+        assert!(pool.recency.len() <= 2 * 3 + 16);
+    }
+}
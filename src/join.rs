@@ -0,0 +1,358 @@
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+
+use crate::parser::{Condition, Expr, JoinKind, resolve_column};
+use crate::storage::{Column, Rows, Value};
+
+/// Joins `left` with `right` on `on`. `columns` describes the combined row:
+/// the first `left_width` columns come from `left`, the rest from `right`.
+///
+/// Equality conjuncts that compare a left column with a right column become
+/// the keys of a hash join built over `right`; the remaining conjuncts are
+/// checked for each candidate pair. Without any such equality this falls back
+/// to a nested loop. Outer joins pad the missing side with `Value::Null`.
+///
+/// `right` is held in memory, but `left` is probed one row at a time as the
+/// result is read. A RIGHT JOIN emits the unmatched right rows once `left`
+/// runs out.
+pub fn join_rows<'a>(
+    left: Rows<'a>,
+    right: Vec<Vec<Value>>,
+    left_width: usize,
+    columns: &[Column],
+    kind: JoinKind,
+    on: &Condition,
+) -> Rows<'a> {
+    let mut keys = Vec::new();
+    let mut residual = Vec::new();
+    for condition in on.conjuncts() {
+        match equi_join_key(condition, columns, left_width) {
+            Some(key) => keys.push(key),
+            None => residual.push(condition.clone()),
+        }
+    }
+
+    let (left_keys, right_keys): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+
+    // Rows whose key contains NULL can never satisfy an equality, so they
+    // are left out of the hash table entirely.
+    let mut table: HashMap<Vec<Value>, Vec<usize>> = HashMap::new();
+    if !right_keys.is_empty() {
+        for (i, row) in right.iter().enumerate() {
+            if let Some(key) = join_key(row, &right_keys) {
+                table.entry(key).or_default().push(i);
+            }
+        }
+    }
+
+    Box::new(HashJoin {
+        left: Some(left),
+        right_matched: vec![false; right.len()],
+        right,
+        table,
+        left_keys,
+        residual,
+        columns: columns.to_vec(),
+        kind,
+        left_width,
+        output: VecDeque::new(),
+        unmatched: 0,
+    })
+}
+
+struct HashJoin<'a> {
+    /// The probe side, until it runs out.
+    left: Option<Rows<'a>>,
+    right: Vec<Vec<Value>>,
+    right_matched: Vec<bool>,
+    table: HashMap<Vec<Value>, Vec<usize>>,
+    /// Positions of the key columns in left rows; empty for a nested loop.
+    left_keys: Vec<usize>,
+    residual: Vec<Condition>,
+    columns: Vec<Column>,
+    kind: JoinKind,
+    left_width: usize,
+    /// Joined rows of the last probed left row not yet returned.
+    output: VecDeque<Vec<Value>>,
+    /// The next right row to consider for RIGHT JOIN padding.
+    unmatched: usize,
+}
+
+impl HashJoin<'_> {
+    /// Queues the joined rows for `left_row`.
+    fn probe(&mut self, left_row: Vec<Value>) -> Result<()> {
+        let candidates = if self.left_keys.is_empty() {
+            (0..self.right.len()).collect()
+        } else {
+            join_key(&left_row, &self.left_keys)
+                .and_then(|key| self.table.get(&key))
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let mut matched = false;
+        for i in candidates {
+            let mut row = left_row.clone();
+            row.extend(self.right[i].iter().cloned());
+
+            let mut keep = true;
+            for condition in &self.residual {
+                if !condition.evaluate(&row, &self.columns)? {
+                    keep = false;
+                    break;
+                }
+            }
+
+            if keep {
+                matched = true;
+                self.right_matched[i] = true;
+                self.output.push_back(row);
+            }
+        }
+
+        if !matched && self.kind == JoinKind::Left {
+            let right_width = self.columns.len() - self.left_width;
+            let mut row = left_row;
+            row.extend(std::iter::repeat_n(Value::Null, right_width));
+            self.output.push_back(row);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for HashJoin<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.output.pop_front() {
+                return Some(Ok(row));
+            }
+
+            let Some(left) = self.left.as_mut() else {
+                break;
+            };
+            match left.next() {
+                Some(Ok(left_row)) => {
+                    if let Err(err) = self.probe(left_row) {
+                        return Some(Err(err));
+                    }
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None => self.left = None,
+            }
+        }
+
+        if self.kind != JoinKind::Right {
+            return None;
+        }
+        while self.unmatched < self.right.len() {
+            let i = self.unmatched;
+            self.unmatched += 1;
+            if !self.right_matched[i] {
+                let mut row = vec![Value::Null; self.left_width];
+                row.extend(std::mem::take(&mut self.right[i]));
+                return Some(Ok(row));
+            }
+        }
+        None
+    }
+}
+
+/// Recognises `left_col = right_col` (in either order) and returns the
+/// column positions within the left row and the right row.
+fn equi_join_key(
+    condition: &Condition,
+    columns: &[Column],
+    left_width: usize,
+) -> Option<(usize, usize)> {
+    let Condition::Equal {
+        left: Expr::Column {
+            table: lt,
+            name: ln,
+        },
+        right: Expr::Column {
+            table: rt,
+            name: rn,
+        },
+    } = condition
+    else {
+        return None;
+    };
+
+    let a = resolve_column(columns, lt.as_deref(), ln).ok()?;
+    let b = resolve_column(columns, rt.as_deref(), rn).ok()?;
+
+    match (a < left_width, b < left_width) {
+        (true, false) => Some((a, b - left_width)),
+        (false, true) => Some((b, a - left_width)),
+        _ => None,
+    }
+}
+
+/// The hash key of `row`, or `None` if part of it is NULL: like
+/// `Condition::Equal`, a NULL key matches nothing.
+fn join_key(row: &[Value], positions: &[usize]) -> Option<Vec<Value>> {
+    let key: Vec<Value> = positions.iter().map(|&i| row[i].clone()).collect();
+    if key.iter().any(|value| matches!(value, Value::Null)) {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::tests::{TempDir, execute, open, query};
+    use crate::storage::{Storage, Value};
+
+    /// Departments 1-3 and employees, one without a department and one in a
+    /// department that does not exist.
+    fn company(dir: &TempDir) -> Storage {
+        let mut storage = open(dir);
+        query(&mut storage, "CREATE TABLE d (id INTEGER, name TEXT)");
+        query(&mut storage, "CREATE TABLE e (id INTEGER, dept INTEGER)");
+        for (id, name) in [(1, "eng"), (2, "ops"), (3, "hr")] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO d VALUES ({}, '{}')", id, name),
+            );
+        }
+        for (id, dept) in [(10, "1"), (11, "1"), (12, "2"), (13, "NULL"), (14, "9")] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO e VALUES ({}, {})", id, dept),
+            );
+        }
+        storage
+    }
+
+    /// Rows of two INTEGER-or-NULL columns, with NULL as `None`.
+    fn pairs(rows: Vec<Vec<Value>>) -> Vec<(Option<i64>, Option<i64>)> {
+        let value = |value: &Value| match value {
+            Value::Integer(i) => Some(*i),
+            Value::Null => None,
+            value => panic!("expected INTEGER or NULL, got {:?}", value),
+        };
+        rows.iter()
+            .map(|row| (value(&row[0]), value(&row[1])))
+            .collect()
+    }
+
+    #[test]
+    fn inner_join_keeps_matching_pairs_only() {
+        let dir = TempDir::new("join-inner");
+        let mut storage = company(&dir);
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e JOIN d ON e.dept = d.id ORDER BY e.id"
+            )),
+            vec![
+                (Some(10), Some(1)),
+                (Some(11), Some(1)),
+                (Some(12), Some(2))
+            ]
+        );
+        // A condition next to the key is checked for each candidate pair.
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e INNER JOIN d ON e.dept = d.id AND e.id > 10 ORDER BY e.id"
+            )),
+            vec![(Some(11), Some(1)), (Some(12), Some(2))]
+        );
+    }
+
+    #[test]
+    fn outer_joins_pad_the_missing_side_with_null() {
+        let dir = TempDir::new("join-outer");
+        let mut storage = company(&dir);
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e LEFT JOIN d ON e.dept = d.id ORDER BY e.id"
+            )),
+            vec![
+                (Some(10), Some(1)),
+                (Some(11), Some(1)),
+                (Some(12), Some(2)),
+                (Some(13), None),
+                (Some(14), None),
+            ]
+        );
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e RIGHT JOIN d ON e.dept = d.id ORDER BY d.id, e.id"
+            )),
+            vec![
+                (Some(10), Some(1)),
+                (Some(11), Some(1)),
+                (Some(12), Some(2)),
+                (None, Some(3)),
+            ]
+        );
+        // WHERE sees the padded rows, so it can pick out the unmatched ones.
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e LEFT JOIN d ON e.dept = d.id WHERE d.id IS NULL ORDER BY e.id"
+            )),
+            vec![(Some(13), None), (Some(14), None)]
+        );
+    }
+
+    #[test]
+    fn null_keys_never_match() {
+        let dir = TempDir::new("join-null");
+        let mut storage = company(&dir);
+        query(&mut storage, "INSERT INTO d VALUES (NULL, 'none')");
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT x.id, y.id FROM e x JOIN e y ON x.dept = y.dept AND x.id < y.id"
+            )),
+            vec![(Some(10), Some(11))]
+        );
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e JOIN d ON e.dept = d.id WHERE e.id = 13"
+            )),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn joins_without_an_equality_fall_back_to_a_nested_loop() {
+        let dir = TempDir::new("join-nested");
+        let mut storage = company(&dir);
+        assert_eq!(
+            pairs(query(
+                &mut storage,
+                "SELECT e.id, d.id FROM e JOIN d ON e.dept < d.id WHERE d.name = 'hr' ORDER BY e.id"
+            )),
+            vec![
+                (Some(10), Some(3)),
+                (Some(11), Some(3)),
+                (Some(12), Some(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn unqualified_columns_must_be_unambiguous() {
+        let dir = TempDir::new("join-ambiguous");
+        let mut storage = company(&dir);
+        let err = execute(&mut storage, "SELECT id FROM e JOIN d ON e.dept = d.id").unwrap_err();
+        assert_eq!(err.to_string(), "Column id is ambiguous");
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT name FROM e JOIN d ON dept = d.id WHERE e.id = 12"
+            ),
+            vec![vec![Value::Text("ops".to_string())]]
+        );
+    }
+}
@@ -1,98 +1,786 @@
-use std::collections::BTreeMap;
-
-use crate::storage::{Column, DataType, Value};
+use crate::blob;
+use crate::datetime;
+use crate::storage::{Column, DataType, ForeignKey, ReferentialAction, Value, integral_real};
 use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::ops::Bound;
 
 #[derive(Debug)]
 pub enum Statement {
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        primary_key: Vec<String>,
+        foreign_keys: Vec<ForeignKey>,
+        if_not_exists: bool,
     },
     Insert {
         table: String,
         columns: Option<Vec<String>>,
         values: Vec<Value>,
     },
-    Select {
-        table: String,
-        columns: Vec<String>,
-        conditions: Option<Vec<Condition>>,
-        order_by: Option<OrderBy>,
-        limit: Option<usize>,
-    },
+    Select(Box<Select>),
     Update {
         table: String,
         assignments: Vec<Assignment>,
-        conditions: Option<Vec<Condition>>,
+        conditions: Option<Condition>,
     },
     Delete {
         table: String,
-        conditions: Option<Vec<Condition>>,
+        conditions: Option<Condition>,
     },
     DropTable {
         name: String,
+        if_exists: bool,
+    },
+    Truncate {
+        table: String,
+        if_exists: bool,
+    },
+    AlterTable {
+        table: String,
+        if_exists: bool,
+        action: AlterTableAction,
     },
     CreateIndex {
         name: String,
         table: String,
         columns: Vec<String>,
+        if_not_exists: bool,
+    },
+    DropIndex {
+        name: String,
+        table: String,
+        if_exists: bool,
+    },
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// The column change an ALTER TABLE makes.
+#[derive(Debug)]
+pub enum AlterTableAction {
+    /// Existing rows read the new column as its default, evaluated once, so
+    /// a volatile default such as `NOW()` is refused.
+    Add {
+        column: Box<Column>,
+        if_not_exists: bool,
+    },
+    Drop {
+        name: String,
+        if_exists: bool,
+    },
+    Rename {
+        from: String,
+        to: String,
     },
 }
 
+#[derive(Debug)]
+pub struct Select {
+    pub table: TableRef,
+    pub joins: Vec<Join>,
+    pub columns: Vec<SelectItem>,
+    pub conditions: Option<Condition>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Condition>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+impl Select {
+    /// Strips the table qualifier from every column reference. Only valid for
+    /// single-table queries, whose rows carry unqualified column names.
+    pub fn unqualified(self) -> Select {
+        let table = self.table.qualifier().to_string();
+
+        Select {
+            columns: self
+                .columns
+                .into_iter()
+                .map(|item| match item {
+                    SelectItem::Wildcard => SelectItem::Wildcard,
+                    SelectItem::Expr { expr, alias } => SelectItem::Expr {
+                        expr: expr.unqualify(&table),
+                        alias,
+                    },
+                })
+                .collect(),
+            conditions: self.conditions.map(|c| c.unqualify(&table)),
+            group_by: self.group_by.iter().map(|e| e.unqualify(&table)).collect(),
+            having: self.having.map(|c| c.unqualify(&table)),
+            order_by: self
+                .order_by
+                .into_iter()
+                .map(|order_by| OrderBy {
+                    expr: order_by.expr.unqualify(&table),
+                    ..order_by
+                })
+                .collect(),
+            ..self
+        }
+    }
+
+    /// True when the query has to be answered by grouping rows rather than
+    /// returning them one by one.
+    pub fn is_aggregate(&self) -> bool {
+        !self.group_by.is_empty()
+            || self.having.is_some()
+            || self.columns.iter().any(|item| match item {
+                SelectItem::Wildcard => false,
+                SelectItem::Expr { expr, .. } => expr.contains_aggregate(),
+            })
+    }
+
+    /// The expressions to sort by, evaluated against the rows before
+    /// projection. An ORDER BY term that is a plain number refers to the
+    /// select item at that position, counting from 1 with `*` expanded to
+    /// `columns`, and a bare name that matches an alias refers to the
+    /// aliased expression; anything else is used as it is.
+    pub fn order_by_exprs(&self, columns: &[Column]) -> Result<Vec<Expr>> {
+        let mut items = Vec::new();
+        for item in &self.columns {
+            match item {
+                SelectItem::Wildcard => items.extend(columns.iter().map(|col| {
+                    let expr = Expr::Column {
+                        table: None,
+                        name: col.name.clone(),
+                    };
+                    (expr, None)
+                })),
+                SelectItem::Expr { expr, alias } => items.push((expr.clone(), alias.as_deref())),
+            }
+        }
+
+        self.order_by
+            .iter()
+            .map(|order_by| match &order_by.expr {
+                Expr::Literal(Value::Integer(position)) => usize::try_from(*position)
+                    .ok()
+                    .and_then(|position| position.checked_sub(1))
+                    .and_then(|idx| items.get(idx))
+                    .map(|(expr, _)| expr.clone())
+                    .ok_or_else(|| {
+                        anyhow!("ORDER BY position {} is not in the select list", position)
+                    }),
+                Expr::Column { table: None, name } => Ok(items
+                    .iter()
+                    .find(|(_, alias)| *alias == Some(name.as_str()))
+                    .map_or_else(|| order_by.expr.clone(), |(expr, _)| expr.clone())),
+                expr => Ok(expr.clone()),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    /// The name that qualifies this table's columns, e.g. `u` in `users u`.
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+}
+
+#[derive(Debug)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub on: Condition,
+}
+
 #[derive(Debug)]
 pub struct Assignment {
     pub column: String,
-    pub value: Value,
+    pub value: Expr,
 }
 
 #[derive(Debug)]
+pub enum SelectItem {
+    Wildcard,
+    Expr { expr: Expr, alias: Option<String> },
+}
+
+#[derive(Debug, Clone)]
 pub struct OrderBy {
-    pub column: String,
+    pub expr: Expr,
     pub direction: OrderDirection,
+    pub nulls: NullsOrder,
 }
 
-#[derive(Debug, PartialEq)]
+impl OrderBy {
+    /// Compares two values of this sort key. NULLs go where `nulls` says,
+    /// whichever the direction.
+    pub fn compare(&self, a: &Value, b: &Value) -> Ordering {
+        let nulls_first = self.nulls == NullsOrder::First;
+        match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) if nulls_first => Ordering::Less,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) if nulls_first => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            _ if self.direction == OrderDirection::Descending => b.cmp(a),
+            _ => a.cmp(b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum OrderDirection {
     Ascending,
     Descending,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NullsOrder {
+    First,
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ScalarFunction {
+    Now,
+    Extract,
+    DateTrunc,
+    GenRandomUuid,
+}
+
+impl ScalarFunction {
+    fn arity(self) -> usize {
+        match self {
+            ScalarFunction::Now | ScalarFunction::GenRandomUuid => 0,
+            ScalarFunction::Extract | ScalarFunction::DateTrunc => 2,
+        }
+    }
+
+    /// Whether the function can return a different value each call.
+    pub fn is_volatile(self) -> bool {
+        matches!(self, ScalarFunction::Now | ScalarFunction::GenRandomUuid)
+    }
+
+    fn call(self, args: &[Value]) -> Result<Value> {
+        match (self, args) {
+            (ScalarFunction::Now, []) => datetime::now(),
+            (ScalarFunction::GenRandomUuid, []) => Ok(blob::random_uuid()),
+            (ScalarFunction::Extract, [Value::Text(field), value]) => {
+                datetime::extract(field, value)
+            }
+            (ScalarFunction::DateTrunc, [Value::Null, _]) => Ok(Value::Null),
+            (ScalarFunction::DateTrunc, [Value::Text(field), value]) => {
+                datetime::date_trunc(field, value)
+            }
+            _ => Err(anyhow!("Invalid arguments to {}: {:?}", self, args)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Literal(Value),
+    Negate(Box<Expr>),
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOperator,
+        right: Box<Expr>,
+    },
+    /// An aggregate call; `arg` is `None` for `COUNT(*)`.
+    Aggregate {
+        function: AggregateFunction,
+        arg: Option<Box<Expr>>,
+    },
+    /// A scalar function call. EXTRACT keeps its field name as a text
+    /// literal in front of the value.
+    Function {
+        function: ScalarFunction,
+        args: Vec<Expr>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        data_type: DataType,
+    },
+}
+
+impl Expr {
+    pub fn evaluate(&self, row: &[Value], columns: &[Column]) -> Result<Value> {
+        match self {
+            Expr::Column { table, name } => {
+                let idx = resolve_column(columns, table.as_deref(), name)?;
+                Ok(row.get(idx).cloned().unwrap_or(Value::Null))
+            }
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Negate(expr) => match expr.evaluate(row, columns)? {
+                Value::Null => Ok(Value::Null),
+                Value::Integer(i) => i
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| anyhow!("Integer overflow")),
+                Value::Real(r) => Ok(Value::Real(-r)),
+                Value::Interval(i) => Ok(Value::Interval(i.checked_neg()?)),
+                v => Err(anyhow!("Cannot negate {:?}", v)),
+            },
+            Expr::Binary { left, op, right } => {
+                let left = left.evaluate(row, columns)?;
+                let right = right.evaluate(row, columns)?;
+                apply_arithmetic(*op, left, right)
+            }
+            Expr::Aggregate { .. } => Err(anyhow!("Aggregate functions are not allowed here")),
+            Expr::Function { function, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(row, columns))
+                    .collect::<Result<Vec<_>>>()?;
+                function.call(&args)
+            }
+            Expr::Cast { expr, data_type } => cast_value(expr.evaluate(row, columns)?, data_type),
+        }
+    }
+
+    /// Drops the qualifier from column references to `table`, so that
+    /// `users.age` can be evaluated against the unqualified columns of `users`.
+    pub fn unqualify(&self, table: &str) -> Expr {
+        self.transform(&|expr| match expr {
+            Expr::Column {
+                table: Some(qualifier),
+                name,
+            } if qualifier == table => Some(Expr::Column {
+                table: None,
+                name: name.clone(),
+            }),
+            _ => None,
+        })
+    }
+
+    pub fn contains_aggregate(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| found |= matches!(expr, Expr::Aggregate { .. }));
+        found
+    }
+
+    /// Whether evaluating the expression twice can give different values.
+    pub fn is_volatile(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| {
+            found |= matches!(expr, Expr::Function { function, .. } if function.is_volatile())
+        });
+        found
+    }
+
+    /// Calls `visit` on this expression and every sub-expression, parents first.
+    pub fn walk(&self, visit: &mut impl FnMut(&Expr)) {
+        visit(self);
+        match self {
+            Expr::Column { .. } | Expr::Literal(_) => {}
+            Expr::Negate(expr) => expr.walk(visit),
+            Expr::Binary { left, right, .. } => {
+                left.walk(visit);
+                right.walk(visit);
+            }
+            Expr::Aggregate { arg, .. } => {
+                if let Some(arg) = arg {
+                    arg.walk(visit);
+                }
+            }
+            Expr::Function { args, .. } => {
+                for arg in args {
+                    arg.walk(visit);
+                }
+            }
+            Expr::Cast { expr, .. } => expr.walk(visit),
+        }
+    }
+
+    /// Rebuilds the expression, replacing every node for which `replace`
+    /// returns `Some`. Replaced nodes are not descended into.
+    pub fn transform(&self, replace: &impl Fn(&Expr) -> Option<Expr>) -> Expr {
+        if let Some(expr) = replace(self) {
+            return expr;
+        }
+
+        match self {
+            Expr::Column { .. } | Expr::Literal(_) => self.clone(),
+            Expr::Negate(expr) => Expr::Negate(Box::new(expr.transform(replace))),
+            Expr::Binary { left, op, right } => Expr::Binary {
+                left: Box::new(left.transform(replace)),
+                op: *op,
+                right: Box::new(right.transform(replace)),
+            },
+            Expr::Aggregate { function, arg } => Expr::Aggregate {
+                function: *function,
+                arg: arg.as_ref().map(|arg| Box::new(arg.transform(replace))),
+            },
+            Expr::Function { function, args } => Expr::Function {
+                function: *function,
+                args: args.iter().map(|arg| arg.transform(replace)).collect(),
+            },
+            Expr::Cast { expr, data_type } => Expr::Cast {
+                expr: Box::new(expr.transform(replace)),
+                data_type: data_type.clone(),
+            },
+        }
+    }
+}
+
+impl Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AggregateFunction::Count => "COUNT",
+            AggregateFunction::Sum => "SUM",
+            AggregateFunction::Avg => "AVG",
+            AggregateFunction::Min => "MIN",
+            AggregateFunction::Max => "MAX",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for ScalarFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ScalarFunction::Now => "NOW",
+            ScalarFunction::Extract => "EXTRACT",
+            ScalarFunction::DateTrunc => "DATE_TRUNC",
+            ScalarFunction::GenRandomUuid => "GEN_RANDOM_UUID",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column {
+                table: Some(table),
+                name,
+            } => write!(f, "{}.{}", table, name),
+            Expr::Column { table: None, name } => write!(f, "{}", name),
+            Expr::Literal(Value::Text(s)) => write!(f, "'{}'", s),
+            Expr::Literal(value @ Value::Date(_)) => write!(f, "DATE '{}'", value),
+            Expr::Literal(value @ Value::Timestamp(_)) => write!(f, "TIMESTAMP '{}'", value),
+            Expr::Literal(value @ Value::Interval(_)) => write!(f, "INTERVAL '{}'", value),
+            Expr::Literal(Value::Blob(b)) => write!(f, "X'{}'", blob::encode_hex(b)),
+            Expr::Literal(value @ Value::Uuid(_)) => write!(f, "UUID '{}'", value),
+            Expr::Literal(value) => write!(f, "{}", value),
+            Expr::Negate(expr) => write!(f, "-{}", expr),
+            Expr::Binary { left, op, right } => {
+                let operand = |expr: &Expr| match expr {
+                    Expr::Binary { .. } => format!("({})", expr),
+                    _ => expr.to_string(),
+                };
+                write!(f, "{} {} {}", operand(left), op, operand(right))
+            }
+            Expr::Aggregate {
+                function,
+                arg: Some(arg),
+            } => write!(f, "{}({})", function, arg),
+            Expr::Aggregate {
+                function,
+                arg: None,
+            } => write!(f, "{}(*)", function),
+            Expr::Function {
+                function: ScalarFunction::Extract,
+                args,
+            } if args.len() == 2 => {
+                let field = match &args[0] {
+                    Expr::Literal(Value::Text(field)) => field.to_uppercase(),
+                    field => field.to_string(),
+                };
+                write!(f, "EXTRACT({} FROM {})", field, args[1])
+            }
+            Expr::Function { function, args } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", function, args.join(", "))
+            }
+            Expr::Cast { expr, data_type } => write!(f, "CAST({} AS {})", expr, data_type),
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = |condition: &Condition| match condition {
+            Condition::And { .. } | Condition::Or { .. } => format!("({})", condition),
+            _ => condition.to_string(),
+        };
+
+        match self {
+            Condition::Equal { left, right } => write!(f, "{} = {}", left, right),
+            Condition::NotEqual { left, right } => write!(f, "{} <> {}", left, right),
+            Condition::GreaterThan { left, right } => write!(f, "{} > {}", left, right),
+            Condition::LessThan { left, right } => write!(f, "{} < {}", left, right),
+            Condition::GreaterEqual { left, right } => write!(f, "{} >= {}", left, right),
+            Condition::LessEqual { left, right } => write!(f, "{} <= {}", left, right),
+            Condition::Between { expr, low, high } => {
+                write!(f, "{} BETWEEN {} AND {}", expr, low, high)
+            }
+            Condition::Like { expr, pattern } => write!(f, "{} LIKE '{}'", expr, pattern),
+            Condition::IsNull { expr } => write!(f, "{} IS NULL", expr),
+            Condition::IsNotNull { expr } => write!(f, "{} IS NOT NULL", expr),
+            Condition::And { left, right } => {
+                write!(f, "{} AND {}", operand(left), operand(right))
+            }
+            Condition::Or { left, right } => write!(f, "{} OR {}", operand(left), operand(right)),
+            Condition::Not { condition } => write!(f, "NOT {}", operand(condition)),
+        }
+    }
+}
+
+/// Finds the position of a column reference in `columns`.
+///
+/// Joined rows use columns named `qualifier.column`; an unqualified reference
+/// matches such a column as long as only one table provides that name.
+pub fn resolve_column(columns: &[Column], table: Option<&str>, name: &str) -> Result<usize> {
+    if let Some(table) = table {
+        let qualified = format!("{}.{}", table, name);
+        return columns
+            .iter()
+            .position(|col| col.name == qualified)
+            .ok_or_else(|| anyhow!("Column {} not found", qualified));
+    }
+
+    if let Some(idx) = columns.iter().position(|col| col.name == name) {
+        return Ok(idx);
+    }
+
+    let mut matches = columns.iter().enumerate().filter(|(_, col)| {
+        col.name
+            .strip_suffix(name)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+    });
+    match (matches.next(), matches.next()) {
+        (Some((idx, _)), None) => Ok(idx),
+        (Some(_), Some(_)) => Err(anyhow!("Column {} is ambiguous", name)),
+        (None, _) => Err(anyhow!("Column {} not found", name)),
+    }
+}
+
+pub fn apply_arithmetic(op: BinaryOperator, left: Value, right: Value) -> Result<Value> {
+    match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match op {
+                BinaryOperator::Add => a.checked_add(b),
+                BinaryOperator::Subtract => a.checked_sub(b),
+                BinaryOperator::Multiply => a.checked_mul(b),
+                BinaryOperator::Divide | BinaryOperator::Modulo if b == 0 => {
+                    return Err(anyhow!("Division by zero"));
+                }
+                BinaryOperator::Divide => a.checked_div(b),
+                BinaryOperator::Modulo => a.checked_rem(b),
+            };
+            result
+                .map(Value::Integer)
+                .ok_or_else(|| anyhow!("Integer overflow"))
+        }
+        (Value::Integer(a), Value::Real(b)) => apply_real_arithmetic(op, a as f64, b),
+        (Value::Real(a), Value::Integer(b)) => apply_real_arithmetic(op, a, b as f64),
+        (Value::Real(a), Value::Real(b)) => apply_real_arithmetic(op, a, b),
+        (left, right) if matches!(op, BinaryOperator::Add | BinaryOperator::Subtract) => {
+            datetime::add(&left, &right, op == BinaryOperator::Subtract)?
+                .ok_or_else(|| anyhow!("Cannot apply {:?} to {:?} and {:?}", op, left, right))
+        }
+        (Value::Interval(a), Value::Integer(b)) | (Value::Integer(b), Value::Interval(a))
+            if op == BinaryOperator::Multiply =>
+        {
+            Ok(Value::Interval(a.checked_mul(b)?))
+        }
+        (Value::Interval(a), Value::Real(b)) | (Value::Real(b), Value::Interval(a))
+            if op == BinaryOperator::Multiply =>
+        {
+            Ok(Value::Interval(a.scale(b)?))
+        }
+        (Value::Interval(a), Value::Integer(b)) if op == BinaryOperator::Divide => {
+            divide_interval(a, b as f64)
+        }
+        (Value::Interval(a), Value::Real(b)) if op == BinaryOperator::Divide => {
+            divide_interval(a, b)
+        }
+        (a, b) => Err(anyhow!("Cannot apply {:?} to {:?} and {:?}", op, a, b)),
+    }
+}
+
+/// Converts `value` to `data_type` for `CAST`. NULL casts to every type,
+/// every value casts to TEXT, and TEXT casts to any type it can be parsed as.
+/// A REAL cast to INTEGER is rounded to the nearest integer.
+pub fn cast_value(value: Value, data_type: &DataType) -> Result<Value> {
+    let cannot = |value: &Value| {
+        anyhow!(
+            "Cannot cast {} to {}",
+            Expr::Literal(value.clone()),
+            data_type
+        )
+    };
+
+    Ok(match (&value, data_type) {
+        (Value::Null, _) => Value::Null,
+        (_, DataType::Text) => Value::Text(value.to_string()),
+        (Value::Text(s), _) => cast_text(s, data_type).ok_or_else(|| cannot(&value))?,
+        (Value::Integer(_), DataType::Integer)
+        | (Value::Real(_), DataType::Real)
+        | (Value::Boolean(_), DataType::Boolean)
+        | (Value::Date(_), DataType::Date)
+        | (Value::Timestamp(_), DataType::Timestamp)
+        | (Value::Interval(_), DataType::Interval)
+        | (Value::Blob(_), DataType::Blob)
+        | (Value::Uuid(_), DataType::Uuid) => value,
+        (Value::Integer(i), DataType::Real) => Value::Real(*i as f64),
+        (Value::Integer(i), DataType::Boolean) => Value::Boolean(*i != 0),
+        (Value::Real(r), DataType::Integer) => match integral_real(r.round()) {
+            Some(i) => Value::Integer(i),
+            None => return Err(anyhow!("{}: out of range", cannot(&value))),
+        },
+        (Value::Boolean(b), DataType::Integer) => Value::Integer(*b as i64),
+        (Value::Date(d), DataType::Timestamp) => datetime::date_to_timestamp(*d),
+        (Value::Timestamp(t), DataType::Date) => datetime::timestamp_to_date(*t),
+        (Value::Blob(b), DataType::Uuid) => Value::Uuid(
+            b.as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("{}: a UUID is 16 bytes", cannot(&value)))?,
+        ),
+        (Value::Uuid(u), DataType::Blob) => Value::Blob(u.to_vec()),
+        _ => return Err(cannot(&value)),
+    })
+}
+
+fn cast_text(text: &str, data_type: &DataType) -> Option<Value> {
+    let trimmed = text.trim();
+    match data_type {
+        DataType::Integer => trimmed.parse().ok().map(Value::Integer),
+        // Rust also parses `NaN` and `inf`, which no REAL column can hold.
+        DataType::Real => trimmed
+            .parse()
+            .ok()
+            .filter(|f: &f64| f.is_finite())
+            .map(Value::Real),
+        DataType::Boolean => match trimmed.to_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "on" | "1" => Some(Value::Boolean(true)),
+            "false" | "f" | "no" | "n" | "off" | "0" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        DataType::Date => datetime::parse_date(trimmed).ok(),
+        DataType::Timestamp => datetime::parse_timestamp(trimmed).ok(),
+        DataType::Interval => datetime::parse_interval(trimmed).ok(),
+        DataType::Uuid => blob::parse_uuid(trimmed).ok(),
+        // BLOB text is either `\x` and hex digits, as BLOBs print, or the
+        // text's own bytes.
+        DataType::Blob => match text.strip_prefix("\\x") {
+            Some(hex) => blob::parse_blob(hex).ok(),
+            None => Some(Value::Blob(text.as_bytes().to_vec())),
+        },
+        DataType::Text => Some(Value::Text(text.to_string())),
+    }
+}
+
+fn divide_interval(interval: datetime::Interval, divisor: f64) -> Result<Value> {
+    if divisor == 0.0 {
+        return Err(anyhow!("Division by zero"));
+    }
+    Ok(Value::Interval(interval.scale(1.0 / divisor)?))
+}
+
+fn apply_real_arithmetic(op: BinaryOperator, a: f64, b: f64) -> Result<Value> {
+    if matches!(op, BinaryOperator::Divide | BinaryOperator::Modulo) && b == 0.0 {
+        return Err(anyhow!("Division by zero"));
+    }
+
+    Ok(Value::Real(match op {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Modulo => a % b,
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Condition {
     Equal {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
     },
     NotEqual {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
     },
     GreaterThan {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
     },
     LessThan {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
     },
     GreaterEqual {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
     },
     LessEqual {
-        column: String,
-        value: Value,
+        left: Expr,
+        right: Expr,
+    },
+    Between {
+        expr: Expr,
+        low: Expr,
+        high: Expr,
     },
     Like {
-        column: String,
+        expr: Expr,
         pattern: String,
     },
     IsNull {
-        column: String,
+        expr: Expr,
     },
     IsNotNull {
-        column: String,
+        expr: Expr,
     },
     And {
         left: Box<Condition>,
@@ -102,50 +790,229 @@ pub enum Condition {
         left: Box<Condition>,
         right: Box<Condition>,
     },
+    Not {
+        condition: Box<Condition>,
+    },
 }
 
 impl Condition {
-    pub fn evaluate(&self, row: &Vec<Value>, columns: &Vec<Column>) -> bool {
-        let get_column_index = |col_name: &str| -> Option<usize> {
-            columns.iter().position(|col| col.name == col_name)
+    pub fn evaluate(&self, row: &[Value], columns: &[Column]) -> Result<bool> {
+        // NULL is neither equal, unequal, smaller nor larger than anything,
+        // itself included, so comparisons with a NULL operand never hold and
+        // joins never match NULL keys.
+        let compare = |left: &Expr, right: &Expr| -> Result<Option<(Value, Value)>> {
+            let (left, right) = (left.evaluate(row, columns)?, right.evaluate(row, columns)?);
+            Ok((left != Value::Null && right != Value::Null).then_some((left, right)))
         };
 
+        Ok(match self {
+            Condition::Equal { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left == right)
+            }
+            Condition::NotEqual { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left != right)
+            }
+            Condition::GreaterThan { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left > right)
+            }
+            Condition::LessThan { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left < right)
+            }
+            Condition::GreaterEqual { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left >= right)
+            }
+            Condition::LessEqual { left, right } => {
+                compare(left, right)?.is_some_and(|(left, right)| left <= right)
+            }
+            Condition::Between { expr, low, high } => {
+                let value = expr.evaluate(row, columns)?;
+                value != Value::Null
+                    && compare(low, high)?.is_some_and(|(low, high)| low <= value && value <= high)
+            }
+            Condition::Like { expr, pattern } => {
+                expr.evaluate(row, columns)?.to_string().contains(pattern)
+            }
+            Condition::IsNull { expr } => matches!(expr.evaluate(row, columns)?, Value::Null),
+            Condition::IsNotNull { expr } => !matches!(expr.evaluate(row, columns)?, Value::Null),
+            Condition::And { left, right } => {
+                left.evaluate(row, columns)? && right.evaluate(row, columns)?
+            }
+            Condition::Or { left, right } => {
+                left.evaluate(row, columns)? || right.evaluate(row, columns)?
+            }
+            Condition::Not { condition } => !condition.evaluate(row, columns)?,
+        })
+    }
+
+    /// Calls `visit` on every expression in the condition tree.
+    pub fn walk_exprs(&self, visit: &mut impl FnMut(&Expr)) {
         match self {
-            Condition::Equal { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] == *value)
+            Condition::Equal { left, right }
+            | Condition::NotEqual { left, right }
+            | Condition::GreaterThan { left, right }
+            | Condition::LessThan { left, right }
+            | Condition::GreaterEqual { left, right }
+            | Condition::LessEqual { left, right } => {
+                left.walk(visit);
+                right.walk(visit);
             }
-            Condition::NotEqual { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] != *value)
+            Condition::Between { expr, low, high } => {
+                expr.walk(visit);
+                low.walk(visit);
+                high.walk(visit);
             }
-            Condition::GreaterThan { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] > *value)
+            Condition::Like { expr, .. }
+            | Condition::IsNull { expr }
+            | Condition::IsNotNull { expr } => expr.walk(visit),
+            Condition::And { left, right } | Condition::Or { left, right } => {
+                left.walk_exprs(visit);
+                right.walk_exprs(visit);
             }
-            Condition::LessThan { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] < *value)
+            Condition::Not { condition } => condition.walk_exprs(visit),
+        }
+    }
+
+    /// Rebuilds the condition with `Expr::transform` applied to every expression.
+    pub fn transform_exprs(&self, replace: &impl Fn(&Expr) -> Option<Expr>) -> Condition {
+        let both = |left: &Expr, right: &Expr| (left.transform(replace), right.transform(replace));
+
+        match self {
+            Condition::Equal { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::Equal { left, right }
             }
-            Condition::GreaterEqual { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] >= *value)
+            Condition::NotEqual { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::NotEqual { left, right }
             }
-            Condition::LessEqual { column, value } => {
-                get_column_index(column).map_or(false, |idx| row[idx] <= *value)
+            Condition::GreaterThan { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::GreaterThan { left, right }
             }
-            Condition::Like { column, pattern } => {
-                get_column_index(column).map_or(false, |idx| row[idx].to_string().contains(pattern))
+            Condition::LessThan { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::LessThan { left, right }
             }
-            Condition::IsNull { column } => {
-                get_column_index(column).map_or(false, |idx| matches!(row[idx], Value::Null))
+            Condition::GreaterEqual { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::GreaterEqual { left, right }
             }
-            Condition::IsNotNull { column } => {
-                get_column_index(column).map_or(false, |idx| !matches!(row[idx], Value::Null))
+            Condition::LessEqual { left, right } => {
+                let (left, right) = both(left, right);
+                Condition::LessEqual { left, right }
             }
+            Condition::Between { expr, low, high } => Condition::Between {
+                expr: expr.transform(replace),
+                low: low.transform(replace),
+                high: high.transform(replace),
+            },
+            Condition::Like { expr, pattern } => Condition::Like {
+                expr: expr.transform(replace),
+                pattern: pattern.clone(),
+            },
+            Condition::IsNull { expr } => Condition::IsNull {
+                expr: expr.transform(replace),
+            },
+            Condition::IsNotNull { expr } => Condition::IsNotNull {
+                expr: expr.transform(replace),
+            },
+            Condition::And { left, right } => Condition::And {
+                left: Box::new(left.transform_exprs(replace)),
+                right: Box::new(right.transform_exprs(replace)),
+            },
+            Condition::Or { left, right } => Condition::Or {
+                left: Box::new(left.transform_exprs(replace)),
+                right: Box::new(right.transform_exprs(replace)),
+            },
+            Condition::Not { condition } => Condition::Not {
+                condition: Box::new(condition.transform_exprs(replace)),
+            },
+        }
+    }
+
+    /// Condition counterpart of `Expr::unqualify`.
+    pub fn unqualify(&self, table: &str) -> Condition {
+        self.transform_exprs(&|expr| match expr {
+            Expr::Column { .. } => Some(expr.unqualify(table)),
+            _ => None,
+        })
+    }
+
+    /// Splits the condition into the terms of its top-level AND chain.
+    pub fn conjuncts(&self) -> Vec<&Condition> {
+        match self {
             Condition::And { left, right } => {
-                left.evaluate(row, columns) && right.evaluate(row, columns)
+                let mut terms = left.conjuncts();
+                terms.extend(right.conjuncts());
+                terms
             }
-            Condition::Or { left, right } => {
-                left.evaluate(row, columns) || right.evaluate(row, columns)
+            _ => vec![self],
+        }
+    }
+
+    /// Returns `(column, value)` when the condition is `column = literal`,
+    /// in either order.
+    pub fn column_equality(&self) -> Option<(&str, &Value)> {
+        match self {
+            Condition::Equal {
+                left:
+                    Expr::Column {
+                        table: None,
+                        name: column,
+                    },
+                right: Expr::Literal(value),
             }
+            | Condition::Equal {
+                left: Expr::Literal(value),
+                right:
+                    Expr::Column {
+                        table: None,
+                        name: column,
+                    },
+            } => Some((column, value)),
+            _ => None,
         }
     }
+
+    /// Returns the column and the lower and upper bounds the condition puts
+    /// on it when it compares an unqualified column with literals, e.g.
+    /// `age > 30`, `30 <= age` or `age BETWEEN 30 AND 40`.
+    pub fn column_range(&self) -> Option<(&str, Bound<&Value>, Bound<&Value>)> {
+        use Bound::{Excluded, Included, Unbounded};
+
+        let (column, value, flipped) = match self {
+            Condition::Between {
+                expr: Expr::Column { table: None, name },
+                low: Expr::Literal(low),
+                high: Expr::Literal(high),
+            } => return Some((name, Included(low), Included(high))),
+            Condition::GreaterThan { left, right }
+            | Condition::LessThan { left, right }
+            | Condition::GreaterEqual { left, right }
+            | Condition::LessEqual { left, right } => match (left, right) {
+                (Expr::Column { table: None, name }, Expr::Literal(value)) => (name, value, false),
+                (Expr::Literal(value), Expr::Column { table: None, name }) => (name, value, true),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        // With the literal on the left, `30 < age` bounds age from below.
+        let range = match (self, flipped) {
+            (Condition::GreaterThan { .. }, false) | (Condition::LessThan { .. }, true) => {
+                (Excluded(value), Unbounded)
+            }
+            (Condition::GreaterEqual { .. }, false) | (Condition::LessEqual { .. }, true) => {
+                (Included(value), Unbounded)
+            }
+            (Condition::LessThan { .. }, false) | (Condition::GreaterThan { .. }, true) => {
+                (Unbounded, Excluded(value))
+            }
+            _ => (Unbounded, Included(value)),
+        };
+
+        Some((column, range.0, range.1))
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -157,7 +1024,7 @@ pub enum TokenType {
     StringLiteral,
     NumericLiteral,
     Comment,
-    EOF,
+    Eof,
 }
 
 #[derive(Debug)]
@@ -174,22 +1041,43 @@ pub struct Parser {
 
 impl Parser {
     pub fn new(input: String) -> Result<Self> {
-        let tokens = Parser::tokenize(input)?;
+        let tokens = Parser::tokenize(input)?
+            .into_iter()
+            .filter(|token| token.token_type != TokenType::Comment)
+            .collect();
         Ok(Parser { tokens, current: 0 })
     }
 
     pub fn parse(&mut self) -> Result<Statement> {
         let token = self.peek()?;
 
-        match token.value.to_uppercase().as_str() {
+        let statement = match token.value.to_uppercase().as_str() {
             "CREATE" => self.parse_create(),
             "INSERT" => self.parse_insert(),
             "SELECT" => self.parse_select(),
             "UPDATE" => self.parse_update(),
             "DELETE" => self.parse_delete(),
             "DROP" => self.parse_drop(),
+            "ALTER" => self.parse_alter(),
+            "TRUNCATE" => self.parse_truncate(),
+            "BEGIN" | "COMMIT" | "ROLLBACK" => self.parse_transaction(),
             _ => Err(anyhow!("Unknown statement: {}", token.value)),
+        }?;
+
+        if self.peek()?.value == ";" {
+            self.advance()?;
+        }
+
+        let token = self.peek()?;
+        if token.token_type != TokenType::Eof {
+            return Err(anyhow!(
+                "Unexpected '{}' at position {}",
+                token.value,
+                token.position
+            ));
         }
+
+        Ok(statement)
     }
 
     fn tokenize(input: String) -> Result<Vec<Token>> {
@@ -251,9 +1139,15 @@ impl Parser {
             "IS",
             "TRUE",
             "FALSE",
+            "BEGIN",
+            "COMMIT",
+            "ROLLBACK",
+            "TRANSACTION",
         ];
 
-        let operators = ["=", "<>", ">=", "<=", ">", "<", "+", "-", "*", "/", "%"];
+        let operators = [
+            "=", "<>", "!=", ">=", "<=", ">", "<", "+", "-", "*", "/", "%",
+        ];
         let punctuation = ["(", ")", ",", ";", "."];
 
         while position < input.len() {
@@ -312,13 +1206,13 @@ impl Parser {
                 continue;
             }
 
-            if remainder.chars().next().unwrap().is_digit(10) {
+            if remainder.chars().next().unwrap().is_ascii_digit() {
                 let mut end = 0;
                 let mut has_dot = false;
 
                 while end < remainder.len() {
                     let c = remainder.chars().nth(end).unwrap();
-                    if c.is_digit(10) {
+                    if c.is_ascii_digit() {
                         end += 1;
                     } else if c == '.' && !has_dot {
                         has_dot = true;
@@ -406,7 +1300,7 @@ impl Parser {
         }
 
         tokens.push(Token {
-            token_type: TokenType::EOF,
+            token_type: TokenType::Eof,
             value: "".to_string(),
             position,
         });
@@ -438,7 +1332,12 @@ impl Parser {
             self.current += 1;
             Ok(token)
         } else {
-            Err(anyhow!("Expected '{}', got '{}'", expected, token.value))
+            Err(anyhow!(
+                "Expected '{}', got '{}' at position {}",
+                expected,
+                token.value,
+                token.position
+            ))
         }
     }
 
@@ -452,9 +1351,10 @@ impl Parser {
             Ok(token)
         } else {
             Err(anyhow!(
-                "Expected token of type {:?}, got {:?}",
+                "Expected token of type {:?}, got {:?} at position {}",
                 expected_types,
-                token.token_type
+                token.token_type,
+                token.position
             ))
         }
     }
@@ -463,6 +1363,7 @@ impl Parser {
         self.consume("CREATE")?;
         if self.peek()?.value.to_uppercase() == "INDEX" {
             self.advance()?;
+            let if_not_exists = self.parse_if_exists(true)?;
             let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
             self.consume("ON")?;
             let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
@@ -476,38 +1377,46 @@ impl Parser {
                     self.consume(",")?;
                 }
             }
+            self.consume(")")?;
 
             return Ok(Statement::CreateIndex {
                 name,
                 table,
                 columns,
+                if_not_exists,
             });
         }
 
         self.consume("TABLE")?;
+        let if_not_exists = self.parse_if_exists(true)?;
         let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
         self.consume("(")?;
 
         let mut columns = Vec::new();
+        let mut primary_key = Vec::new();
+        let mut foreign_keys = Vec::new();
         loop {
-            let col_name = self.consume_any(&[TokenType::Identifier])?.value.clone();
-            let data_type_token = self
-                .consume_any(&[TokenType::Keyword, TokenType::Identifier])?
-                .value
-                .clone();
-
-            let data_type = match data_type_token.to_uppercase().as_str() {
-                "INTEGER" | "INT" => DataType::Integer,
-                "TEXT" | "VARCHAR" | "CHAR" | "STRING" => DataType::Text,
-                "BOOLEAN" | "BOOL" => DataType::Boolean,
-                "REAL" | "FLOAT" | "DOUBLE" => DataType::Real,
-                _ => return Err(anyhow!("Unknown data type: {}", data_type_token)),
-            };
-
-            columns.push(Column {
-                name: col_name,
-                data_type,
-            });
+            match self.peek()?.value.to_uppercase().as_str() {
+                "PRIMARY" => {
+                    self.advance()?;
+                    self.consume("KEY")?;
+                    if !primary_key.is_empty() {
+                        return Err(anyhow!("Table {} has more than one PRIMARY KEY", name));
+                    }
+                    primary_key = self.parse_column_list()?;
+                }
+                "FOREIGN" => {
+                    self.advance()?;
+                    self.consume("KEY")?;
+                    let fk_columns = self.parse_column_list()?;
+                    foreign_keys.push(self.parse_references(fk_columns)?);
+                }
+                _ => columns.push(self.parse_column_def(
+                    &name,
+                    &mut primary_key,
+                    &mut foreign_keys,
+                )?),
+            }
 
             let token = self.peek()?.value.clone();
             if token == ")" {
@@ -520,7 +1429,148 @@ impl Parser {
             }
         }
 
-        Ok(Statement::CreateTable { name, columns })
+        Ok(Statement::CreateTable {
+            name,
+            columns,
+            primary_key,
+            foreign_keys,
+            if_not_exists,
+        })
+    }
+
+    /// Consumes `IF EXISTS`, or `IF NOT EXISTS` when `not` is set, and
+    /// returns whether it was there.
+    fn parse_if_exists(&mut self, not: bool) -> Result<bool> {
+        if self.peek()?.value.to_uppercase() != "IF" {
+            return Ok(false);
+        }
+        self.advance()?;
+        if not {
+            self.consume("NOT")?;
+        }
+        self.consume("EXISTS")?;
+        Ok(true)
+    }
+
+    /// Parses `name TYPE` followed by any column constraints. NOT NULL,
+    /// DEFAULT and CHECK are kept on the column; a column-level PRIMARY KEY
+    /// is recorded in `primary_key` and a REFERENCES clause in
+    /// `foreign_keys`.
+    fn parse_column_def(
+        &mut self,
+        table: &str,
+        primary_key: &mut Vec<String>,
+        foreign_keys: &mut Vec<ForeignKey>,
+    ) -> Result<Column> {
+        let col_name = self.consume_any(&[TokenType::Identifier])?.value.clone();
+        let data_type = self.parse_data_type()?;
+
+        let mut not_null = false;
+        let mut default = None;
+        let mut check = None;
+        loop {
+            match self.peek()?.value.to_uppercase().as_str() {
+                "NOT" => {
+                    self.advance()?;
+                    self.consume("NULL")?;
+                    not_null = true;
+                }
+                "NULL" => {
+                    self.advance()?;
+                }
+                "DEFAULT" => {
+                    self.advance()?;
+                    default = Some(self.parse_expression(0)?);
+                }
+                "CHECK" => {
+                    self.advance()?;
+                    self.consume("(")?;
+                    check = Some(self.parse_conditions()?);
+                    self.consume(")")?;
+                }
+                "PRIMARY" => {
+                    self.advance()?;
+                    self.consume("KEY")?;
+                    if !primary_key.is_empty() {
+                        return Err(anyhow!("Table {} has more than one PRIMARY KEY", table));
+                    }
+                    primary_key.push(col_name.clone());
+                }
+                "REFERENCES" => {
+                    foreign_keys.push(self.parse_references(vec![col_name.clone()])?);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(Column {
+            name: col_name,
+            data_type,
+            not_null,
+            default,
+            check,
+            missing: None,
+        })
+    }
+
+    /// Parses `REFERENCES parent [(columns)] [ON DELETE action]` for a
+    /// foreign key on `columns`. Without a column list the key refers to the
+    /// parent's primary key.
+    fn parse_references(&mut self, columns: Vec<String>) -> Result<ForeignKey> {
+        self.consume("REFERENCES")?;
+        let parent = self.consume_any(&[TokenType::Identifier])?.value.clone();
+
+        let parent_columns = if self.peek()?.value == "(" {
+            self.parse_column_list()?
+        } else {
+            Vec::new()
+        };
+
+        let mut on_delete = ReferentialAction::Restrict;
+        if self.peek()?.value.to_uppercase() == "ON" {
+            self.advance()?;
+            self.consume("DELETE")?;
+            let token = self.advance()?.value.to_uppercase();
+            on_delete = match token.as_str() {
+                "CASCADE" => ReferentialAction::Cascade,
+                "RESTRICT" => ReferentialAction::Restrict,
+                "SET" => {
+                    self.consume("NULL")?;
+                    ReferentialAction::SetNull
+                }
+                "NO" => {
+                    self.consume("ACTION")?;
+                    ReferentialAction::Restrict
+                }
+                _ => return Err(anyhow!("Unknown ON DELETE action: {}", token)),
+            };
+        }
+
+        Ok(ForeignKey {
+            columns,
+            parent,
+            parent_columns,
+            on_delete,
+        })
+    }
+
+    /// Parses a parenthesised, comma-separated list of column names.
+    fn parse_column_list(&mut self) -> Result<Vec<String>> {
+        self.consume("(")?;
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.consume_any(&[TokenType::Identifier])?.value.clone());
+
+            let token = self.peek()?.value.clone();
+            if token == ")" {
+                self.advance()?;
+                return Ok(columns);
+            } else if token == "," {
+                self.advance()?;
+            } else {
+                return Err(anyhow!("Expected ',' or ')', got '{}'", token));
+            }
+        }
     }
 
     fn parse_insert(&mut self) -> Result<Statement> {
@@ -559,7 +1609,7 @@ impl Parser {
             self.consume("(")?;
 
             loop {
-                let value = self.parse_value()?;
+                let value = self.parse_expression(0)?.evaluate(&[], &[])?;
                 all_values.push(value);
 
                 let token = self.peek()?.value.clone();
@@ -595,20 +1645,20 @@ impl Parser {
         let mut columns = Vec::new();
 
         loop {
-            let token = self.peek()?;
-            if token.value == "*" {
+            if self.peek()?.value == "*" {
                 self.advance()?;
-                columns.push("*".to_string());
-                break;
-            }
-
-            if token.token_type == TokenType::Identifier {
-                columns.push(self.advance()?.value.clone());
+                columns.push(SelectItem::Wildcard);
             } else {
-                return Err(anyhow!(
-                    "Expected column name or '*', got '{}'",
-                    token.value
-                ));
+                let expr = self.parse_expression(0)?;
+                let alias = if self.peek()?.value.to_uppercase() == "AS" {
+                    self.advance()?;
+                    Some(self.consume_any(&[TokenType::Identifier])?.value.clone())
+                } else if self.peek()?.token_type == TokenType::Identifier {
+                    Some(self.advance()?.value.clone())
+                } else {
+                    None
+                };
+                columns.push(SelectItem::Expr { expr, alias });
             }
 
             let next = self.peek()?;
@@ -622,7 +1672,12 @@ impl Parser {
         }
 
         self.consume("FROM")?;
-        let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
+        let table = self.parse_table_ref()?;
+
+        let mut joins = Vec::new();
+        while let Some(join) = self.parse_join()? {
+            joins.push(join);
+        }
 
         let mut conditions = None;
         if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "WHERE" {
@@ -630,54 +1685,120 @@ impl Parser {
             conditions = Some(self.parse_conditions()?);
         }
 
-        let mut order_by = None;
-        if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "ORDER" {
+        let mut group_by = Vec::new();
+        if self.peek()?.value.to_uppercase() == "GROUP" {
             self.advance()?;
             self.consume("BY")?;
-            let column = self.consume_any(&[TokenType::Identifier])?.value.clone();
-
-            let direction = if self.current < self.tokens.len() {
-                match self.peek()?.value.to_uppercase().as_str() {
-                    "ASC" => {
-                        self.advance()?;
-                        OrderDirection::Ascending
-                    }
-                    "DESC" => {
-                        self.advance()?;
-                        OrderDirection::Descending
-                    }
-                    _ => OrderDirection::Ascending,
+            loop {
+                group_by.push(self.parse_expression(0)?);
+                if self.peek()?.value != "," {
+                    break;
                 }
-            } else {
-                OrderDirection::Ascending
-            };
+                self.advance()?;
+            }
+        }
 
-            order_by = Some(OrderBy { column, direction });
+        let mut having = None;
+        if self.peek()?.value.to_uppercase() == "HAVING" {
+            self.advance()?;
+            having = Some(self.parse_conditions()?);
+        }
+
+        let mut order_by = Vec::new();
+        if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "ORDER" {
+            self.advance()?;
+            self.consume("BY")?;
+            loop {
+                order_by.push(self.parse_order_by_term()?);
+                if self.current >= self.tokens.len() || self.peek()?.value != "," {
+                    break;
+                }
+                self.advance()?;
+            }
         }
 
         let mut limit = None;
         if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "LIMIT" {
             self.advance()?;
-            let limit_token = self
-                .consume_any(&[TokenType::NumericLiteral])?
-                .value
-                .clone();
-            limit = Some(
-                limit_token
-                    .parse::<usize>()
-                    .map_err(|_| anyhow!("Invalid LIMIT value"))?,
-            );
+            limit = Some(self.parse_count("LIMIT")?);
         }
 
-        Ok(Statement::Select {
+        let mut offset = None;
+        if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "OFFSET" {
+            self.advance()?;
+            offset = Some(self.parse_count("OFFSET")?);
+        }
+
+        Ok(Statement::Select(Box::new(Select {
             table,
+            joins,
             columns,
-            conditions: conditions.unwrap_or(None),
+            conditions,
+            group_by,
+            having,
             order_by,
             limit,
+            offset,
+        })))
+    }
+
+    /// Parses one ORDER BY term: an expression, an optional ASC or DESC and
+    /// an optional NULLS FIRST or NULLS LAST. Without the latter, NULLs sort
+    /// as if they were smaller than every other value.
+    fn parse_order_by_term(&mut self) -> Result<OrderBy> {
+        let expr = self.parse_expression(0)?;
+
+        let mut direction = OrderDirection::Ascending;
+        if self.current < self.tokens.len() {
+            match self.peek()?.value.to_uppercase().as_str() {
+                "ASC" => {
+                    self.advance()?;
+                }
+                "DESC" => {
+                    self.advance()?;
+                    direction = OrderDirection::Descending;
+                }
+                _ => {}
+            }
+        }
+
+        let mut nulls = match direction {
+            OrderDirection::Ascending => NullsOrder::First,
+            OrderDirection::Descending => NullsOrder::Last,
+        };
+        if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "NULLS" {
+            self.advance()?;
+            let placement = self.advance()?.value.to_uppercase();
+            nulls = match placement.as_str() {
+                "FIRST" => NullsOrder::First,
+                "LAST" => NullsOrder::Last,
+                _ => {
+                    return Err(anyhow!(
+                        "Expected FIRST or LAST after NULLS, got '{}'",
+                        placement
+                    ));
+                }
+            };
+        }
+
+        Ok(OrderBy {
+            expr,
+            direction,
+            nulls,
         })
     }
 
+    /// Parses the row count after LIMIT or OFFSET.
+    fn parse_count(&mut self, clause: &str) -> Result<usize> {
+        let token = self
+            .consume_any(&[TokenType::NumericLiteral])?
+            .value
+            .clone();
+        token
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid {} value", clause))
+    }
+
     fn parse_update(&mut self) -> Result<Statement> {
         self.consume("UPDATE")?;
         let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
@@ -688,7 +1809,7 @@ impl Parser {
         loop {
             let column = self.consume_any(&[TokenType::Identifier])?.value.clone();
             self.consume("=")?;
-            let value = self.parse_value()?;
+            let value = self.parse_expression(0)?;
 
             assignments.push(Assignment { column, value });
 
@@ -707,7 +1828,7 @@ impl Parser {
         Ok(Statement::Update {
             table,
             assignments,
-            conditions: conditions.unwrap_or(None),
+            conditions,
         })
     }
 
@@ -719,7 +1840,7 @@ impl Parser {
         let mut conditions = None;
         if self.current < self.tokens.len() && self.peek()?.value.to_uppercase() == "WHERE" {
             self.advance()?;
-            conditions = self.parse_conditions()?;
+            conditions = Some(self.parse_conditions()?);
         }
 
         Ok(Statement::Delete { table, conditions })
@@ -727,47 +1848,193 @@ impl Parser {
 
     fn parse_drop(&mut self) -> Result<Statement> {
         self.consume("DROP")?;
+        if self.peek()?.value.to_uppercase() == "INDEX" {
+            self.advance()?;
+            let if_exists = self.parse_if_exists(false)?;
+            let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
+            self.consume("ON")?;
+            let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
+
+            return Ok(Statement::DropIndex {
+                name,
+                table,
+                if_exists,
+            });
+        }
+
         self.consume("TABLE")?;
+        let if_exists = self.parse_if_exists(false)?;
         let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
 
-        Ok(Statement::DropTable { name })
+        Ok(Statement::DropTable { name, if_exists })
     }
 
-    fn parse_conditions(&mut self) -> Result<Option<Vec<Condition>>> {
-        let condition = self.parse_condition()?;
-        let mut conditions = vec![condition];
+    fn parse_truncate(&mut self) -> Result<Statement> {
+        self.consume("TRUNCATE")?;
+        if self.peek()?.value.to_uppercase() == "TABLE" {
+            self.advance()?;
+        }
+        let if_exists = self.parse_if_exists(false)?;
+        let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
 
-        while self.current < self.tokens.len() {
-            if self.peek()?.value.to_uppercase() == "AND"
-                || self.peek()?.value.to_uppercase() == "OR"
-            {
-                let operator = self.advance()?.value.to_uppercase();
-                let right_condition = self.parse_condition()?;
+        Ok(Statement::Truncate { table, if_exists })
+    }
 
-                let left_condition = conditions.pop().unwrap();
-                let combined = if operator == "AND" {
-                    Condition::And {
-                        left: Box::new(left_condition.unwrap()),
-                        right: Box::new(right_condition.unwrap()),
-                    }
-                } else {
-                    Condition::Or {
-                        left: Box::new(left_condition.unwrap()),
-                        right: Box::new(right_condition.unwrap()),
-                    }
-                };
+    fn parse_alter(&mut self) -> Result<Statement> {
+        self.consume("ALTER")?;
+        self.consume("TABLE")?;
+        let if_exists = self.parse_if_exists(false)?;
+        let table = self.consume_any(&[TokenType::Identifier])?.value.clone();
 
-                conditions.push(Some(combined));
-            } else {
+        let verb = self.advance()?.value.to_uppercase();
+        if self.peek()?.value.to_uppercase() == "COLUMN" {
+            self.advance()?;
+        }
+
+        let action = match verb.as_str() {
+            "ADD" => {
+                let if_not_exists = self.parse_if_exists(true)?;
+                let mut primary_key = Vec::new();
+                let mut foreign_keys = Vec::new();
+                let column = self.parse_column_def(&table, &mut primary_key, &mut foreign_keys)?;
+                if !primary_key.is_empty() || !foreign_keys.is_empty() {
+                    return Err(anyhow!(
+                        "ALTER TABLE ADD COLUMN does not support PRIMARY KEY or REFERENCES"
+                    ));
+                }
+                AlterTableAction::Add {
+                    column: Box::new(column),
+                    if_not_exists,
+                }
+            }
+            "DROP" => {
+                let if_exists = self.parse_if_exists(false)?;
+                let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
+                AlterTableAction::Drop { name, if_exists }
+            }
+            "RENAME" => {
+                let from = self.consume_any(&[TokenType::Identifier])?.value.clone();
+                self.consume("TO")?;
+                let to = self.consume_any(&[TokenType::Identifier])?.value.clone();
+                AlterTableAction::Rename { from, to }
+            }
+            _ => return Err(anyhow!("Expected ADD, DROP or RENAME, got '{}'", verb)),
+        };
+
+        Ok(Statement::AlterTable {
+            table,
+            if_exists,
+            action,
+        })
+    }
+
+    fn parse_transaction(&mut self) -> Result<Statement> {
+        let statement = match self.advance()?.value.to_uppercase().as_str() {
+            "BEGIN" => Statement::Begin,
+            "COMMIT" => Statement::Commit,
+            _ => Statement::Rollback,
+        };
+
+        if self.peek()?.value.to_uppercase() == "TRANSACTION" {
+            self.advance()?;
+        }
+
+        Ok(statement)
+    }
+
+    fn parse_conditions(&mut self) -> Result<Condition> {
+        self.parse_condition_expression(0)
+    }
+
+    /// Precedence climbing over OR (lowest) and AND; NOT and parenthesised
+    /// groups are handled by `parse_condition_operand`.
+    fn parse_condition_expression(&mut self, min_precedence: u8) -> Result<Condition> {
+        let mut left = self.parse_condition_operand()?;
+
+        loop {
+            let operator = self.peek()?.value.to_uppercase();
+            let precedence = match operator.as_str() {
+                "OR" => 1,
+                "AND" => 2,
+                _ => break,
+            };
+            if precedence < min_precedence {
                 break;
             }
+            self.advance()?;
+
+            let right = self.parse_condition_expression(precedence + 1)?;
+            left = if operator == "AND" {
+                Condition::And {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            } else {
+                Condition::Or {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }
+            };
         }
 
-        Ok(Some(conditions.into_iter().filter_map(|c| c).collect()))
+        Ok(left)
     }
 
-    fn parse_condition(&mut self) -> Result<Option<Condition>> {
-        let column = self.consume_any(&[TokenType::Identifier])?.value.clone();
+    fn parse_condition_operand(&mut self) -> Result<Condition> {
+        let token = self.peek()?;
+        if token.token_type == TokenType::Keyword && token.value.to_uppercase() == "NOT" {
+            self.advance()?;
+            let condition = self.parse_condition_operand()?;
+            return Ok(Condition::Not {
+                condition: Box::new(condition),
+            });
+        }
+
+        // A parenthesis followed by an operator once it closes opens an
+        // arithmetic operand, as in `(a + 1) > 2`, rather than a grouped
+        // condition.
+        if token.token_type == TokenType::Punctuation
+            && token.value == "("
+            && !self.group_is_operand()
+        {
+            self.advance()?;
+            let condition = self.parse_condition_expression(0)?;
+            self.consume(")")?;
+            return Ok(condition);
+        }
+
+        self.parse_condition()
+    }
+
+    /// Whether the parenthesis at the current position is followed, once it
+    /// closes, by an operator that continues an expression or comparison.
+    fn group_is_operand(&self) -> bool {
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(self.current) {
+            if token.token_type != TokenType::Punctuation {
+                continue;
+            }
+            match token.value.as_str() {
+                "(" => depth += 1,
+                ")" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return self.tokens.get(i + 1).is_some_and(|next| {
+                    next.token_type == TokenType::Operator
+                        || (next.token_type == TokenType::Keyword
+                            && matches!(
+                                next.value.to_uppercase().as_str(),
+                                "IS" | "BETWEEN" | "LIKE" | "NOT"
+                            ))
+                });
+            }
+        }
+        false
+    }
+
+    fn parse_condition(&mut self) -> Result<Condition> {
+        let left = self.parse_expression(0)?;
 
         if self.peek()?.value.to_uppercase() == "IS" {
             self.advance()?;
@@ -775,53 +2042,326 @@ impl Parser {
             if self.peek()?.value.to_uppercase() == "NOT" {
                 self.advance()?;
                 self.consume("NULL")?;
-                return Ok(Some(Condition::IsNotNull { column }));
+                return Ok(Condition::IsNotNull { expr: left });
             } else if self.peek()?.value.to_uppercase() == "NULL" {
                 self.advance()?;
-                return Ok(Some(Condition::IsNull { column }));
+                return Ok(Condition::IsNull { expr: left });
             } else {
                 return Err(anyhow!("Expected NULL after IS"));
             }
         }
 
-        let operator = self.advance()?.value.clone();
-        let value_token = self.advance()?;
+        let negated = self.peek()?.value.to_uppercase() == "NOT";
+        if negated {
+            self.advance()?;
+        }
 
-        let value = match value_token.token_type {
-            TokenType::NumericLiteral => {
-                if value_token.value.contains('.') {
-                    Value::Real(value_token.value.parse::<f64>().unwrap())
-                } else {
-                    Value::Integer(value_token.value.parse::<i64>().unwrap())
+        let operator = self.advance()?.value.to_uppercase();
+
+        if operator == "BETWEEN" {
+            let low = self.parse_expression(0)?;
+            self.consume("AND")?;
+            let high = self.parse_expression(0)?;
+
+            let between = Condition::Between {
+                expr: left,
+                low,
+                high,
+            };
+            return Ok(if negated {
+                Condition::Not {
+                    condition: Box::new(between),
                 }
+            } else {
+                between
+            });
+        }
+
+        if negated {
+            return Err(anyhow!("Expected BETWEEN after NOT"));
+        }
+
+        if operator == "LIKE" {
+            return match self.parse_value()? {
+                Value::Text(pattern) => Ok(Condition::Like {
+                    expr: left,
+                    pattern,
+                }),
+                _ => Err(anyhow!("LIKE pattern must be a string")),
+            };
+        }
+
+        if !matches!(
+            operator.as_str(),
+            "=" | "<>" | "!=" | ">" | "<" | ">=" | "<="
+        ) {
+            return Err(anyhow!("Unknown operator: {}", operator));
+        }
+
+        let right = self.parse_expression(0)?;
+
+        match operator.as_str() {
+            "=" => Ok(Condition::Equal { left, right }),
+            "<>" | "!=" => Ok(Condition::NotEqual { left, right }),
+            ">" => Ok(Condition::GreaterThan { left, right }),
+            "<" => Ok(Condition::LessThan { left, right }),
+            ">=" => Ok(Condition::GreaterEqual { left, right }),
+            "<=" => Ok(Condition::LessEqual { left, right }),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Precedence climbing over the arithmetic operators; `*`, `/` and `%`
+    /// bind tighter than `+` and `-`.
+    fn parse_expression(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut left = self.parse_expression_operand()?;
+
+        loop {
+            let token = self.peek()?;
+            if token.token_type != TokenType::Operator {
+                break;
+            }
+
+            let (op, precedence) = match token.value.as_str() {
+                "+" => (BinaryOperator::Add, 1),
+                "-" => (BinaryOperator::Subtract, 1),
+                "*" => (BinaryOperator::Multiply, 2),
+                "/" => (BinaryOperator::Divide, 2),
+                "%" => (BinaryOperator::Modulo, 2),
+                _ => break,
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.advance()?;
+
+            let right = self.parse_expression(precedence + 1)?;
+            left = Expr::Binary {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_expression_operand(&mut self) -> Result<Expr> {
+        let token = self.peek()?;
+
+        match token.token_type {
+            TokenType::Operator if token.value == "-" => {
+                self.advance()?;
+                Ok(match self.parse_expression_operand()? {
+                    Expr::Literal(Value::Integer(i)) => Expr::Literal(Value::Integer(-i)),
+                    Expr::Literal(Value::Real(r)) => Expr::Literal(Value::Real(-r)),
+                    operand => Expr::Negate(Box::new(operand)),
+                })
+            }
+            TokenType::Operator if token.value == "+" => {
+                self.advance()?;
+                self.parse_expression_operand()
+            }
+            TokenType::Punctuation if token.value == "(" => {
+                self.advance()?;
+                let expr = self.parse_expression(0)?;
+                self.consume(")")?;
+                Ok(expr)
+            }
+            TokenType::Identifier => {
+                let name = token.value.to_uppercase();
+                match self.tokens.get(self.current + 1) {
+                    Some(next) if next.token_type == TokenType::StringLiteral => {
+                        let value = match name.as_str() {
+                            "DATE" => datetime::parse_date(&next.value)?,
+                            "TIMESTAMP" => datetime::parse_timestamp(&next.value)?,
+                            "INTERVAL" => datetime::parse_interval(&next.value)?,
+                            "UUID" => blob::parse_uuid(&next.value)?,
+                            "X" if next.position == token.position + 1 => {
+                                blob::parse_blob(&next.value)?
+                            }
+                            _ => return self.parse_column_ref(),
+                        };
+                        self.current += 2;
+                        Ok(Expr::Literal(value))
+                    }
+                    Some(next) if next.value == "(" => {
+                        self.advance()?;
+                        self.parse_function(&name)
+                    }
+                    _ => self.parse_column_ref(),
+                }
+            }
+            TokenType::Keyword => {
+                let function = match token.value.to_uppercase().as_str() {
+                    "COUNT" => AggregateFunction::Count,
+                    "SUM" => AggregateFunction::Sum,
+                    "AVG" => AggregateFunction::Avg,
+                    "MIN" => AggregateFunction::Min,
+                    "MAX" => AggregateFunction::Max,
+                    _ => return Ok(Expr::Literal(self.parse_value()?)),
+                };
+                self.advance()?;
+                self.parse_aggregate(function)
             }
-            TokenType::StringLiteral => Value::Text(value_token.value.clone()),
-            TokenType::Keyword if value_token.value.to_uppercase() == "NULL" => Value::Null,
-            TokenType::Keyword if value_token.value.to_uppercase() == "TRUE" => {
-                Value::Boolean(true)
+            _ => Ok(Expr::Literal(self.parse_value()?)),
+        }
+    }
+
+    /// Parses `column` or `table.column`.
+    fn parse_column_ref(&mut self) -> Result<Expr> {
+        let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
+        if self.peek()?.value != "." {
+            return Ok(Expr::Column { table: None, name });
+        }
+
+        self.advance()?;
+        let column = self.consume_any(&[TokenType::Identifier])?.value.clone();
+        Ok(Expr::Column {
+            table: Some(name),
+            name: column,
+        })
+    }
+
+    fn parse_table_ref(&mut self) -> Result<TableRef> {
+        let name = self.consume_any(&[TokenType::Identifier])?.value.clone();
+
+        let alias = if self.peek()?.value.to_uppercase() == "AS" {
+            self.advance()?;
+            Some(self.consume_any(&[TokenType::Identifier])?.value.clone())
+        } else if self.peek()?.token_type == TokenType::Identifier {
+            Some(self.advance()?.value.clone())
+        } else {
+            None
+        };
+
+        Ok(TableRef { name, alias })
+    }
+
+    /// Parses `[INNER | LEFT [OUTER] | RIGHT [OUTER]] JOIN table ON condition`,
+    /// returning `None` when the next token does not start a join.
+    fn parse_join(&mut self) -> Result<Option<Join>> {
+        let kind = match self.peek()?.value.to_uppercase().as_str() {
+            "JOIN" => JoinKind::Inner,
+            "INNER" => {
+                self.advance()?;
+                JoinKind::Inner
             }
-            TokenType::Keyword if value_token.value.to_uppercase() == "FALSE" => {
-                Value::Boolean(false)
+            "LEFT" | "RIGHT" => {
+                let kind = if self.advance()?.value.to_uppercase() == "LEFT" {
+                    JoinKind::Left
+                } else {
+                    JoinKind::Right
+                };
+                if self.peek()?.value.to_uppercase() == "OUTER" {
+                    self.advance()?;
+                }
+                kind
             }
-            _ => Value::Text(value_token.value.clone()),
+            _ => return Ok(None),
         };
 
-        match operator.as_str() {
-            "=" => Ok(Some(Condition::Equal { column, value })),
-            "<>" | "!=" => Ok(Some(Condition::NotEqual { column, value })),
-            ">" => Ok(Some(Condition::GreaterThan { column, value })),
-            "<" => Ok(Some(Condition::LessThan { column, value })),
-            ">=" => Ok(Some(Condition::GreaterEqual { column, value })),
-            "<=" => Ok(Some(Condition::LessEqual { column, value })),
-            "LIKE" => Ok(Some(Condition::Like {
-                column,
-                pattern: match value {
-                    Value::Text(pattern) => pattern,
-                    _ => return Err(anyhow!("LIKE pattern must be a string")),
-                },
-            })),
-            _ => Err(anyhow!("Unknown operator: {}", operator)),
+        self.consume("JOIN")?;
+        let table = self.parse_table_ref()?;
+        self.consume("ON")?;
+        let on = self.parse_conditions()?;
+
+        Ok(Some(Join { kind, table, on }))
+    }
+
+    /// Parses the argument list of a scalar function whose name has just
+    /// been consumed. EXTRACT takes `(field FROM expr)` rather than a list.
+    fn parse_function(&mut self, name: &str) -> Result<Expr> {
+        let function = match name {
+            "NOW" => ScalarFunction::Now,
+            "EXTRACT" => ScalarFunction::Extract,
+            "DATE_TRUNC" => ScalarFunction::DateTrunc,
+            "CAST" => return self.parse_cast(),
+            "GEN_RANDOM_UUID" => ScalarFunction::GenRandomUuid,
+            _ => return Err(anyhow!("Unknown function: {}", name)),
+        };
+        self.consume("(")?;
+
+        let mut args = Vec::new();
+        if function == ScalarFunction::Extract {
+            let field = self
+                .consume_any(&[TokenType::Identifier, TokenType::StringLiteral])?
+                .value
+                .clone();
+            args.push(Expr::Literal(Value::Text(field)));
+            self.consume("FROM")?;
+            args.push(self.parse_expression(0)?);
+        } else if self.peek()?.value != ")" {
+            loop {
+                args.push(self.parse_expression(0)?);
+                if self.peek()?.value != "," {
+                    break;
+                }
+                self.advance()?;
+            }
         }
+        self.consume(")")?;
+
+        if args.len() != function.arity() {
+            return Err(anyhow!(
+                "{} takes {} argument(s), got {}",
+                function,
+                function.arity(),
+                args.len()
+            ));
+        }
+        Ok(Expr::Function { function, args })
+    }
+
+    fn parse_data_type(&mut self) -> Result<DataType> {
+        let name = self
+            .consume_any(&[TokenType::Keyword, TokenType::Identifier])?
+            .value
+            .clone();
+
+        Ok(match name.to_uppercase().as_str() {
+            "INTEGER" | "INT" => DataType::Integer,
+            "TEXT" | "VARCHAR" | "CHAR" | "STRING" => DataType::Text,
+            "BOOLEAN" | "BOOL" => DataType::Boolean,
+            "REAL" | "FLOAT" | "DOUBLE" => DataType::Real,
+            "DATE" => DataType::Date,
+            "TIMESTAMP" | "DATETIME" => DataType::Timestamp,
+            "INTERVAL" => DataType::Interval,
+            "BLOB" | "BYTEA" => DataType::Blob,
+            "UUID" => DataType::Uuid,
+            _ => return Err(anyhow!("Unknown data type: {}", name)),
+        })
+    }
+
+    /// Parses `(expr AS type)` after `CAST`.
+    fn parse_cast(&mut self) -> Result<Expr> {
+        self.consume("(")?;
+        let expr = self.parse_expression(0)?;
+        self.consume("AS")?;
+        let data_type = self.parse_data_type()?;
+        self.consume(")")?;
+
+        Ok(Expr::Cast {
+            expr: Box::new(expr),
+            data_type,
+        })
+    }
+
+    fn parse_aggregate(&mut self, function: AggregateFunction) -> Result<Expr> {
+        self.consume("(")?;
+
+        let arg = if self.peek()?.value == "*" {
+            if function != AggregateFunction::Count {
+                return Err(anyhow!("Only COUNT accepts '*'"));
+            }
+            self.advance()?;
+            None
+        } else {
+            Some(Box::new(self.parse_expression(0)?))
+        };
+
+        self.consume(")")?;
+        Ok(Expr::Aggregate { function, arg })
     }
 
     fn parse_value(&mut self) -> Result<Value> {
@@ -864,3 +2404,96 @@ impl Parser {
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The WHERE clause of `SELECT * FROM t WHERE <condition>`.
+    fn condition(condition: &str) -> Condition {
+        let sql = format!("SELECT * FROM t WHERE {}", condition);
+        match Parser::new(sql).unwrap().parse().unwrap() {
+            Statement::Select(select) => select.conditions.unwrap(),
+            statement => panic!("expected a SELECT, got {:?}", statement),
+        }
+    }
+
+    /// The single expression of `SELECT <expr> FROM t`.
+    fn expression(expr: &str) -> Expr {
+        let sql = format!("SELECT {} FROM t", expr);
+        match Parser::new(sql).unwrap().parse().unwrap() {
+            Statement::Select(mut select) => match select.columns.pop() {
+                Some(SelectItem::Expr { expr, .. }) => expr,
+                item => panic!("expected an expression, got {:?}", item),
+            },
+            statement => panic!("expected a SELECT, got {:?}", statement),
+        }
+    }
+
+    // Nested binary expressions and AND/OR operands print in parentheses,
+    // so the printed form shows how the input was grouped.
+
+    #[test]
+    fn arithmetic_binds_by_precedence_then_left_to_right() {
+        for (input, grouped) in [
+            ("1 + 2 * 3", "1 + (2 * 3)"),
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("a - b - c", "(a - b) - c"),
+            ("a / b * c", "(a / b) * c"),
+            ("a % 2 + -b", "(a % 2) + -b"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("((a))", "a"),
+        ] {
+            assert_eq!(expression(input).to_string(), grouped, "{}", input);
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        for (input, grouped) in [
+            ("a = 1 OR b = 2 AND c = 3", "a = 1 OR (b = 2 AND c = 3)"),
+            ("(a = 1 OR b = 2) AND c = 3", "(a = 1 OR b = 2) AND c = 3"),
+            ("a = 1 AND b = 2 OR c = 3", "(a = 1 AND b = 2) OR c = 3"),
+            ("a = 1 OR b = 2 OR c = 3", "(a = 1 OR b = 2) OR c = 3"),
+        ] {
+            assert_eq!(condition(input).to_string(), grouped, "{}", input);
+        }
+        assert_eq!(
+            condition("NOT a = 1 AND b = 2"),
+            condition("(NOT a = 1) AND b = 2")
+        );
+    }
+
+    #[test]
+    fn a_parenthesis_followed_by_an_operator_opens_an_operand() {
+        for (input, grouped) in [
+            ("(a + 1) * 2 > b", "(a + 1) * 2 > b"),
+            ("(a + 1) > 2", "a + 1 > 2"),
+            ("(a) IS NULL", "a IS NULL"),
+            ("(a) NOT BETWEEN 1 AND 2", "NOT a BETWEEN 1 AND 2"),
+            ("((a = 1))", "a = 1"),
+            (
+                "((a + 1) > 2 OR b < 1) AND c = 1",
+                "(a + 1 > 2 OR b < 1) AND c = 1",
+            ),
+        ] {
+            assert_eq!(condition(input).to_string(), grouped, "{}", input);
+        }
+    }
+
+    #[test]
+    fn unbalanced_or_incomplete_expressions_are_refused() {
+        for input in ["(a = 1", "a = (1 + 2", "a = 1 + * 2", "a = 1)"] {
+            let sql = format!("SELECT * FROM t WHERE {}", input);
+            let parsed = Parser::new(sql).and_then(|mut parser| parser.parse());
+            assert!(parsed.is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn bang_equal_is_not_equal() {
+        assert_eq!(condition("a != 1"), condition("a <> 1"));
+        assert_eq!(condition("(a)!=(b + 1)"), condition("a <> b + 1"));
+        assert!(Parser::new("SELECT * FROM t WHERE a ! 1".to_string()).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{Result, anyhow};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::datetime::Interval;
+use crate::storage::Value;
+
+const MAGIC: &[u8; 8] = b"SCYROWS\0";
+const VERSION: u32 = 1;
+
+/// Length of the record stream header: the magic followed by the format
+/// version.
+const HEADER_LEN: u64 = 12;
+
+/// Flag byte in front of every live record in a record stream.
+const LIVE: u8 = 0x01;
+
+/// Flag byte that marks a deleted row, both in record streams and in the
+/// JSON-line files that came before them.
+pub const TOMBSTONE: u8 = b'#';
+
+const TAG_NULL: u8 = 0;
+const TAG_INTEGER: u8 = 1;
+const TAG_TEXT: u8 = 2;
+const TAG_BOOLEAN: u8 = 3;
+const TAG_REAL: u8 = 4;
+const TAG_DATE: u8 = 5;
+const TAG_TIMESTAMP: u8 = 6;
+const TAG_INTERVAL: u8 = 7;
+const TAG_BLOB: u8 = 8;
+const TAG_UUID: u8 = 9;
+
+// Row encoding:
+//
+//   payload := value_count:u32 value*
+//   value   := tag:u8 len:u32 bytes
+//
+// All integers are little-endian. Integers and reals are stored as their
+// eight raw bytes, so a REAL reads back bit for bit as it was written.
+// Dates are four bytes of days, timestamps eight bytes of microseconds and
+// intervals their months, days and microseconds at eight bytes each.
+// BLOBs and UUIDs are stored as their raw bytes.
+//
+// Before heap files, tables were stored as a plain stream of such payloads:
+//
+//   header  := MAGIC version:u32
+//   record  := flag:u8 payload_len:u32 payload
+//
+// `RowReader` still reads that layout so old tables can be converted.
+
+/// Whether `path` is a record stream. Tables written before that format
+/// existed hold one JSON array per line instead.
+pub fn is_row_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == MAGIC),
+        Err(_) => Ok(false),
+    }
+}
+
+pub fn encode_values(values: &[Value]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+
+    for value in values {
+        let (tag, bytes) = match value {
+            Value::Null => (TAG_NULL, Vec::new()),
+            Value::Integer(i) => (TAG_INTEGER, i.to_le_bytes().to_vec()),
+            Value::Text(s) => (TAG_TEXT, s.as_bytes().to_vec()),
+            Value::Boolean(b) => (TAG_BOOLEAN, vec![*b as u8]),
+            Value::Real(r) => (TAG_REAL, r.to_bits().to_le_bytes().to_vec()),
+            Value::Date(d) => (TAG_DATE, d.to_le_bytes().to_vec()),
+            Value::Timestamp(t) => (TAG_TIMESTAMP, t.to_le_bytes().to_vec()),
+            Value::Interval(i) => (
+                TAG_INTERVAL,
+                [i.months, i.days, i.micros]
+                    .iter()
+                    .flat_map(|part| part.to_le_bytes())
+                    .collect(),
+            ),
+            Value::Blob(b) => (TAG_BLOB, b.clone()),
+            Value::Uuid(u) => (TAG_UUID, u.to_vec()),
+        };
+        buf.push(tag);
+        buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&bytes);
+    }
+
+    buf
+}
+
+pub fn decode_values(mut buf: &[u8]) -> Result<Vec<Value>> {
+    let count = take_u32(&mut buf)? as usize;
+
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        let tag = take(&mut buf, 1)?[0];
+        let len = take_u32(&mut buf)? as usize;
+        let bytes = take(&mut buf, len)?;
+
+        values.push(match (tag, len) {
+            (TAG_NULL, 0) => Value::Null,
+            (TAG_INTEGER, 8) => Value::Integer(i64::from_le_bytes(bytes.try_into()?)),
+            (TAG_TEXT, _) => Value::Text(String::from_utf8(bytes.to_vec())?),
+            (TAG_BOOLEAN, 1) => Value::Boolean(bytes[0] != 0),
+            (TAG_REAL, 8) => Value::Real(f64::from_bits(u64::from_le_bytes(bytes.try_into()?))),
+            (TAG_DATE, 4) => Value::Date(i32::from_le_bytes(bytes.try_into()?)),
+            (TAG_TIMESTAMP, 8) => Value::Timestamp(i64::from_le_bytes(bytes.try_into()?)),
+            (TAG_INTERVAL, 24) => {
+                let part = |i: usize| -> Result<i64> {
+                    Ok(i64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into()?))
+                };
+                Value::Interval(Interval {
+                    months: part(0)?,
+                    days: part(1)?,
+                    micros: part(2)?,
+                })
+            }
+            (TAG_BLOB, _) => Value::Blob(bytes.to_vec()),
+            (TAG_UUID, 16) => Value::Uuid(bytes.try_into()?),
+            _ => {
+                return Err(anyhow!(
+                    "Corrupt row: bad value tag {} of length {}",
+                    tag,
+                    len
+                ));
+            }
+        });
+    }
+
+    Ok(values)
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if buf.len() < len {
+        return Err(anyhow!(
+            "Corrupt row: value runs past the end of the record"
+        ));
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(buf, 4)?.try_into()?))
+}
+
+/// Reads one record from the current position. Returns its total length and
+/// its values, or `None` for the values if it is a tombstone; returns `None`
+/// altogether at the end of the file.
+fn read_record(reader: &mut impl Read) -> Result<Option<(u64, Option<Vec<Value>>)>> {
+    let mut head = [0u8; 5];
+    match reader.read_exact(&mut head[..1]) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    reader.read_exact(&mut head[1..])?;
+
+    let len = u32::from_le_bytes(head[1..].try_into()?) as usize;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+
+    let row = match head[0] {
+        LIVE => Some(decode_values(&payload)?),
+        TOMBSTONE => None,
+        flag => return Err(anyhow!("Corrupt row: unknown record flag {}", flag)),
+    };
+
+    Ok(Some((5 + len as u64, row)))
+}
+
+/// Reads the records of a record stream in order, tombstones included.
+pub struct RowReader {
+    reader: BufReader<File>,
+    position: u64,
+}
+
+impl RowReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+
+        let mut header = [0u8; HEADER_LEN as usize];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| anyhow!("{} is missing its row format header", path.display()))?;
+        if &header[..8] != MAGIC {
+            return Err(anyhow!("{} is not a binary row file", path.display()));
+        }
+        let version = u32::from_le_bytes(header[8..].try_into()?);
+        if version != VERSION {
+            return Err(anyhow!(
+                "{} has unsupported row format version {}",
+                path.display(),
+                version
+            ));
+        }
+
+        Ok(RowReader {
+            reader,
+            position: HEADER_LEN,
+        })
+    }
+
+    /// Returns the offset and values of the next record, with `None` as the
+    /// values of a deleted row, or `None` once the file is exhausted.
+    pub fn next_record(&mut self) -> Result<Option<(u64, Option<Vec<Value>>)>> {
+        let offset = self.position;
+        match read_record(&mut self.reader)? {
+            Some((len, row)) => {
+                self.position += len;
+                Ok(Some((offset, row)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the next live row and its offset, skipping tombstones.
+    pub fn next_row(&mut self) -> Result<Option<(u64, Vec<Value>)>> {
+        while let Some((offset, row)) = self.next_record()? {
+            if let Some(row) = row {
+                return Ok(Some((offset, row)));
+            }
+        }
+        Ok(None)
+    }
+}
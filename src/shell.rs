@@ -0,0 +1,453 @@
+use anyhow::{Result, anyhow};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use std::io::BufRead;
+
+use crate::parser::{Parser, Statement};
+use crate::storage::{Storage, Value, primary_key_index};
+
+/// Number of leading result rows the column widths are fitted to. Later rows
+/// are printed as they arrive, and a longer cell among them simply pushes
+/// the rest of its line to the right.
+const WIDTH_SAMPLE_ROWS: usize = 100;
+
+const PROMPT: &str = "scythe> ";
+const CONTINUATION_PROMPT: &str = "   ...> ";
+
+const HELP: &str = "\
+.help              Show this message
+.indexes [TABLE]   List indexes, optionally only those on TABLE
+.quit              Exit the shell
+.schema [TABLE]    Show the CREATE statements for TABLE, or for every table
+.sortmem [BYTES]   Show or set the memory ORDER BY may use before spilling to disk
+.tables            List tables";
+
+/// Reads SQL statements and dot-commands and runs them against a `Storage`.
+///
+/// Statements end with `;` and may span several lines. Dot-commands take a
+/// single line and are only recognised at the start of a statement.
+pub struct Shell {
+    storage: Storage,
+    buffer: String,
+    done: bool,
+    /// Set once any statement or dot-command has failed.
+    failed: bool,
+}
+
+impl Shell {
+    pub fn new(storage: Storage) -> Self {
+        Shell {
+            storage,
+            buffer: String::new(),
+            done: false,
+            failed: false,
+        }
+    }
+
+    pub fn run_interactive(&mut self) -> Result<()> {
+        let mut editor = DefaultEditor::new()?;
+
+        while !self.done {
+            let prompt = if self.buffer.trim().is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        editor.add_history_entry(line.as_str())?;
+                    }
+                    self.handle_line(&line);
+                }
+                Err(ReadlineError::Interrupted) => self.buffer.clear(),
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs every statement read from `reader`, carrying on past failed
+    /// ones. A trailing statement without a terminating `;` is still
+    /// executed. Returns `false` if any statement failed.
+    pub fn run_script(&mut self, reader: impl BufRead) -> Result<bool> {
+        for line in reader.lines() {
+            self.handle_line(&line?);
+            if self.done {
+                return Ok(!self.failed);
+            }
+        }
+
+        let remainder = std::mem::take(&mut self.buffer);
+        if !remainder.trim().is_empty() {
+            let result = self.execute(&remainder);
+            self.report(result);
+        }
+
+        Ok(!self.failed)
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        if self.buffer.trim().is_empty() && line.trim_start().starts_with('.') {
+            self.buffer.clear();
+            let result = self.execute_dot_command(line.trim());
+            self.report(result);
+            return;
+        }
+
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+
+        let (statements, consumed) = split_statements(&self.buffer);
+        let statements: Vec<String> = statements.into_iter().map(str::to_string).collect();
+        self.buffer.drain(..consumed);
+
+        for statement in statements {
+            let result = self.execute(&statement);
+            self.report(result);
+        }
+    }
+
+    fn report(&mut self, result: Result<()>) {
+        if let Err(err) = result {
+            eprintln!("Error: {}", err);
+            self.failed = true;
+        }
+    }
+
+    fn execute(&mut self, sql: &str) -> Result<()> {
+        let mut parser = Parser::new(sql.to_string())?;
+
+        match parser.parse()? {
+            Statement::CreateTable {
+                name,
+                columns,
+                primary_key,
+                foreign_keys,
+                if_not_exists,
+            } => {
+                if self.storage.create_table(
+                    &name,
+                    columns,
+                    primary_key,
+                    foreign_keys,
+                    if_not_exists,
+                )? {
+                    println!("Created table {}", name);
+                } else {
+                    println!("Table {} already exists, skipping", name);
+                }
+            }
+            Statement::Insert {
+                table,
+                columns,
+                values,
+            } => {
+                self.storage.insert_row(&table, columns, values)?;
+                println!("Inserted 1 row");
+            }
+            Statement::Select(select) => {
+                let names = self.storage.column_names(&select)?;
+                print_rows(&names, self.storage.get_rows(*select)?)?;
+            }
+            Statement::Update {
+                table,
+                assignments,
+                conditions,
+            } => {
+                let updated = self.storage.update_rows(&table, assignments, conditions)?;
+                println!("Updated {} row(s)", updated);
+            }
+            Statement::Delete { table, conditions } => {
+                let deleted = self.storage.delete_rows(&table, conditions)?;
+                println!("Deleted {} row(s)", deleted);
+            }
+            Statement::DropTable { name, if_exists } => {
+                if self.storage.drop_table(&name, if_exists)? {
+                    println!("Dropped table {}", name);
+                } else {
+                    println!("Table {} does not exist, skipping", name);
+                }
+            }
+            Statement::Truncate { table, if_exists } => {
+                if self.storage.truncate_table(&table, if_exists)? {
+                    println!("Truncated table {}", table);
+                } else {
+                    println!("Table {} does not exist, skipping", table);
+                }
+            }
+            Statement::AlterTable {
+                table,
+                if_exists,
+                action,
+            } => {
+                if self.storage.alter_table(&table, if_exists, action)? {
+                    println!("Altered table {}", table);
+                } else {
+                    println!("Table {} unchanged, skipping", table);
+                }
+            }
+            Statement::CreateIndex {
+                name,
+                table,
+                columns,
+                if_not_exists,
+            } => {
+                if self
+                    .storage
+                    .create_index(&table, &name, columns, if_not_exists)?
+                {
+                    println!("Created index {}", name);
+                } else {
+                    println!("Index {} already exists, skipping", name);
+                }
+            }
+            Statement::DropIndex {
+                name,
+                table,
+                if_exists,
+            } => {
+                if self.storage.drop_index(&table, &name, if_exists)? {
+                    println!("Dropped index {}", name);
+                } else {
+                    println!("Index {} does not exist, skipping", name);
+                }
+            }
+            Statement::Begin => {
+                self.storage.begin()?;
+                println!("BEGIN");
+            }
+            Statement::Commit => {
+                self.storage.commit()?;
+                println!("COMMIT");
+            }
+            Statement::Rollback => {
+                self.storage.rollback()?;
+                println!("ROLLBACK");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_dot_command(&mut self, line: &str) -> Result<()> {
+        let mut parts = line.split_whitespace();
+        let command = parts.next().unwrap_or_default();
+        let argument = parts.next();
+
+        let metadata = self.storage.metadata();
+        match command {
+            ".help" => println!("{}", HELP),
+            ".quit" | ".exit" => self.done = true,
+            ".tables" => {
+                let mut names: Vec<_> = metadata.tables().map(|table| table.name()).collect();
+                names.sort();
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+            ".schema" => {
+                let mut tables: Vec<_> = match argument {
+                    Some(name) => vec![
+                        metadata
+                            .table(name)
+                            .ok_or_else(|| anyhow!("Table {} not found", name))?,
+                    ],
+                    None => metadata.tables().collect(),
+                };
+                tables.sort_by_key(|table| table.name());
+
+                for table in tables {
+                    let mut columns: Vec<String> = table
+                        .columns()
+                        .iter()
+                        .map(|col| {
+                            let mut def = format!("{} {}", col.name, col.data_type);
+                            if col.not_null {
+                                def.push_str(" NOT NULL");
+                            }
+                            if let Some(default) = &col.default {
+                                def.push_str(&format!(" DEFAULT {}", default));
+                            }
+                            if let Some(check) = &col.check {
+                                def.push_str(&format!(" CHECK ({})", check));
+                            }
+                            def
+                        })
+                        .collect();
+                    if !table.primary_key().is_empty() {
+                        columns.push(format!("PRIMARY KEY ({})", table.primary_key().join(", ")));
+                    }
+                    for fk in table.foreign_keys() {
+                        columns.push(format!(
+                            "FOREIGN KEY ({}) REFERENCES {} ({}) ON DELETE {}",
+                            fk.columns.join(", "),
+                            fk.parent,
+                            fk.parent_columns.join(", "),
+                            fk.on_delete
+                        ));
+                    }
+                    println!("CREATE TABLE {} ({});", table.name(), columns.join(", "));
+
+                    // The primary key's index comes with the table.
+                    let pkey = primary_key_index(table.name());
+                    for index in table.indexes().iter().filter(|idx| idx.name() != pkey) {
+                        println!(
+                            "CREATE INDEX {} ON {} ({});",
+                            index.name(),
+                            table.name(),
+                            index.columns().join(", ")
+                        );
+                    }
+                }
+            }
+            ".indexes" => {
+                let mut tables: Vec<_> = metadata
+                    .tables()
+                    .filter(|table| argument.is_none_or(|name| table.name() == name))
+                    .collect();
+                tables.sort_by_key(|table| table.name());
+
+                for table in tables {
+                    for index in table.indexes() {
+                        println!(
+                            "{} ON {} ({}){}",
+                            index.name(),
+                            table.name(),
+                            index.columns().join(", "),
+                            if index.is_unique() { " UNIQUE" } else { "" }
+                        );
+                    }
+                }
+            }
+            ".sortmem" => match argument {
+                Some(bytes) => {
+                    let bytes = bytes
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid byte count {}", bytes))?;
+                    self.storage.set_sort_memory(bytes);
+                }
+                None => println!("{}", self.storage.sort_memory()),
+            },
+            _ => return Err(anyhow!("Unknown command {}; try .help", command)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits `input` into the complete `;`-terminated statements it contains,
+/// ignoring semicolons inside string literals and `--` comments. Returns the
+/// statements and the number of bytes they cover.
+fn split_statements(input: &str) -> (Vec<&str>, usize) {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut in_comment = false;
+
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if in_comment {
+            in_comment = c != '\n';
+            continue;
+        }
+
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '-') if chars.peek().is_some_and(|&(_, next)| next == '-') => {
+                in_comment = true;
+            }
+            (None, ';') => {
+                let statement = input[start..i].trim();
+                if !statement.is_empty() {
+                    statements.push(statement);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    (statements, start)
+}
+
+/// Prints a result table. Only the first `WIDTH_SAMPLE_ROWS` rows are held
+/// in memory, to size the columns; the rest are printed as the cursor
+/// yields them.
+fn print_rows(names: &[String], rows: impl Iterator<Item = Result<Vec<Value>>>) -> Result<()> {
+    let render =
+        |row: Vec<Value>| -> Vec<String> { row.iter().map(|value| value.to_string()).collect() };
+
+    let mut rows = rows.map(|row| row.map(render));
+    let sample = rows
+        .by_ref()
+        .take(WIDTH_SAMPLE_ROWS)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut widths: Vec<usize> = names.iter().map(|name| name.chars().count()).collect();
+    for row in &sample {
+        for (i, cell) in row.iter().enumerate() {
+            if i < widths.len() {
+                widths[i] = widths[i].max(cell.chars().count());
+            }
+        }
+    }
+
+    let format_row = |cells: &[String]| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    };
+
+    println!("{}", format_row(names).trim_end());
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|&width| "-".repeat(width))
+            .collect::<Vec<_>>()
+            .join("-+-")
+    );
+
+    let mut count = 0;
+    for row in sample.into_iter().map(Ok).chain(rows) {
+        println!("{}", format_row(&row?).trim_end());
+        count += 1;
+    }
+    println!("({} row{})", count, if count == 1 { "" } else { "s" });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{TempDir, integers, open, query};
+
+    #[test]
+    fn a_script_fails_if_any_statement_does() {
+        let dir = TempDir::new("shell-script");
+        let mut shell = Shell::new(open(&dir));
+        let script =
+            "CREATE TABLE t (a INTEGER);\nSELECT * FROM missing;\nINSERT INTO t VALUES (1)";
+        assert!(!shell.run_script(script.as_bytes()).unwrap());
+        // Later statements still ran.
+        assert_eq!(
+            integers(query(&mut shell.storage, "SELECT a FROM t")),
+            vec![1]
+        );
+
+        let mut shell = Shell::new(open(&dir));
+        assert!(shell.run_script("SELECT * FROM t;\n".as_bytes()).unwrap());
+    }
+}
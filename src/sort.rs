@@ -0,0 +1,554 @@
+use anyhow::{Result, anyhow};
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicU64};
+
+use crate::row::{decode_values, encode_values};
+use crate::storage::Value;
+
+/// Memory a sort may use for buffered rows unless told otherwise.
+pub const DEFAULT_MEMORY_BUDGET: usize = 64 * 1024 * 1024;
+
+/// Most runs merged at once. With more than this, runs are first merged in
+/// groups into longer runs, which keeps the number of open files bounded.
+const MERGE_FAN_IN: usize = 64;
+
+/// Numbers the run files of every sort in this process, so concurrent sorts
+/// never pick the same file name.
+static NEXT_RUN: AtomicU64 = AtomicU64::new(0);
+
+/// A row together with the values it is sorted by.
+pub type Keyed = (Vec<Value>, Vec<Value>);
+
+type Compare<'a> = Rc<dyn Fn(&[Value], &[Value]) -> Ordering + 'a>;
+
+type Input<'a> = Box<dyn Iterator<Item = Result<Keyed>> + 'a>;
+
+/// Sorts rows by their keys and yields them in order, as an external merge
+/// sort. Nothing is read from `input` until the first row is asked for.
+/// Rows are buffered until they take up `memory_budget` bytes; the buffer is
+/// then sorted and written out as a run to a temporary file in `spill_dir`,
+/// and the runs are merged back together as rows are taken. When everything
+/// fits in the budget no file is written at all. Rows with equal keys keep
+/// their input order.
+pub struct Sort<'a> {
+    input: Option<Input<'a>>,
+    compare: Compare<'a>,
+    spill_dir: PathBuf,
+    memory_budget: usize,
+    output: Output,
+}
+
+enum Output {
+    Memory(std::vec::IntoIter<Keyed>),
+    Merge(Merge),
+}
+
+impl<'a> Sort<'a> {
+    pub fn new(
+        input: impl Iterator<Item = Result<Keyed>> + 'a,
+        compare: impl Fn(&[Value], &[Value]) -> Ordering + 'a,
+        spill_dir: &Path,
+        memory_budget: usize,
+    ) -> Self {
+        Sort::with_compare(Box::new(input), Rc::new(compare), spill_dir, memory_budget)
+    }
+
+    fn with_compare(
+        input: Input<'a>,
+        compare: Compare<'a>,
+        spill_dir: &Path,
+        memory_budget: usize,
+    ) -> Self {
+        Sort {
+            input: Some(input),
+            compare,
+            spill_dir: spill_dir.to_path_buf(),
+            memory_budget,
+            output: Output::Memory(Vec::new().into_iter()),
+        }
+    }
+
+    fn consume(&mut self, input: Input<'a>) -> Result<()> {
+        let mut buffer = Vec::new();
+        let mut buffered = 0;
+        let mut runs = Vec::new();
+        for row in input {
+            let row = row?;
+            buffered += keyed_size(&row);
+            buffer.push(row);
+
+            if buffered >= self.memory_budget {
+                buffer.sort_by(|(a, _), (b, _)| (self.compare)(a, b));
+                runs.push(Run::write(&self.spill_dir, buffer.drain(..).map(Ok))?);
+                buffered = 0;
+            }
+        }
+
+        if runs.is_empty() {
+            buffer.sort_by(|(a, _), (b, _)| (self.compare)(a, b));
+            self.output = Output::Memory(buffer.into_iter());
+            return Ok(());
+        }
+
+        if !buffer.is_empty() {
+            buffer.sort_by(|(a, _), (b, _)| (self.compare)(a, b));
+            runs.push(Run::write(&self.spill_dir, buffer.drain(..).map(Ok))?);
+        }
+
+        // Merging neighbouring runs keeps earlier input rows in earlier runs,
+        // which is what keeps the sort stable.
+        while runs.len() > MERGE_FAN_IN {
+            let mut merged = Vec::new();
+            let mut rest = runs.into_iter();
+            loop {
+                let group: Vec<Run> = rest.by_ref().take(MERGE_FAN_IN).collect();
+                if group.is_empty() {
+                    break;
+                }
+
+                let mut merge = Merge::new(group, &self.compare)?;
+                let rows = std::iter::from_fn(|| merge.next(&self.compare).transpose());
+                merged.push(Run::write(&self.spill_dir, rows)?);
+            }
+            runs = merged;
+        }
+
+        self.output = Output::Merge(Merge::new(runs, &self.compare)?);
+        Ok(())
+    }
+}
+
+impl Iterator for Sort<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(input) = self.input.take()
+            && let Err(err) = self.consume(input)
+        {
+            return Some(Err(err));
+        }
+
+        match &mut self.output {
+            Output::Memory(rows) => rows.next().map(|(_, row)| Ok(row)),
+            Output::Merge(merge) => merge
+                .next(&self.compare)
+                .map(|row| row.map(|(_, row)| row))
+                .transpose(),
+        }
+    }
+}
+
+/// Yields the first `limit` rows in key order without sorting the rest.
+/// A bounded heap holds the best rows seen so far, with the worst of them on
+/// top to be evicted when a better row comes along, so memory stays at
+/// `limit` rows however large the input is. Should those rows outgrow
+/// `memory_budget`, it falls back to a full `Sort`. Rows with equal keys keep
+/// their input order, as with `Sort`.
+pub struct TopN<'a> {
+    input: Option<Input<'a>>,
+    compare: Compare<'a>,
+    limit: usize,
+    spill_dir: PathBuf,
+    memory_budget: usize,
+    output: std::vec::IntoIter<Keyed>,
+    fallback: Option<std::iter::Take<Sort<'a>>>,
+}
+
+impl<'a> TopN<'a> {
+    pub fn new(
+        input: impl Iterator<Item = Result<Keyed>> + 'a,
+        compare: impl Fn(&[Value], &[Value]) -> Ordering + 'a,
+        limit: usize,
+        spill_dir: &Path,
+        memory_budget: usize,
+    ) -> Self {
+        TopN {
+            input: Some(Box::new(input)),
+            compare: Rc::new(compare),
+            limit,
+            spill_dir: spill_dir.to_path_buf(),
+            memory_budget,
+            output: Vec::new().into_iter(),
+            fallback: None,
+        }
+    }
+
+    fn consume(&mut self, mut input: Input<'a>) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+
+        // Entries carry their input position, which breaks ties between
+        // equal keys in favour of the earlier row.
+        let mut heap: Vec<(Keyed, usize)> = Vec::new();
+        let mut buffered = 0;
+        let compare = self.compare.clone();
+        let worse = |a: &(Keyed, usize), b: &(Keyed, usize)| {
+            compare(&a.0.0, &b.0.0).then(a.1.cmp(&b.1)) == Ordering::Greater
+        };
+
+        let mut position = 0;
+        while let Some(row) = input.next() {
+            let entry = (row?, position);
+            position += 1;
+
+            if heap.len() < self.limit {
+                buffered += keyed_size(&entry.0);
+                heap.push(entry);
+                sift_up(&mut heap, worse);
+
+                if buffered >= self.memory_budget {
+                    // Too big to keep in memory: sort everything instead,
+                    // starting with the rows taken so far in input order.
+                    heap.sort_by_key(|(_, position)| *position);
+                    let rows = heap.into_iter().map(|(row, _)| Ok(row)).chain(input);
+                    let sort = Sort::with_compare(
+                        Box::new(rows),
+                        self.compare.clone(),
+                        &self.spill_dir,
+                        self.memory_budget,
+                    );
+                    self.fallback = Some(sort.take(self.limit));
+                    return Ok(());
+                }
+            } else if worse(&heap[0], &entry) {
+                buffered -= keyed_size(&heap[0].0);
+                buffered += keyed_size(&entry.0);
+                heap[0] = entry;
+                sift_down(&mut heap, worse);
+            }
+        }
+
+        heap.sort_by(|a, b| compare(&a.0.0, &b.0.0).then(a.1.cmp(&b.1)));
+        self.output = heap
+            .into_iter()
+            .map(|(row, _)| row)
+            .collect::<Vec<_>>()
+            .into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for TopN<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(input) = self.input.take()
+            && let Err(err) = self.consume(input)
+        {
+            return Some(Err(err));
+        }
+
+        match &mut self.fallback {
+            Some(sort) => sort.next(),
+            None => self.output.next().map(|(_, row)| Ok(row)),
+        }
+    }
+}
+
+/// Restores the heap order after a push, given `above(a, b)` telling
+/// whether `a` belongs above `b`.
+fn sift_up<T>(heap: &mut [T], above: impl Fn(&T, &T) -> bool) {
+    let mut i = heap.len() - 1;
+    while i > 0 {
+        let parent = (i - 1) / 2;
+        if !above(&heap[i], &heap[parent]) {
+            break;
+        }
+        heap.swap(i, parent);
+        i = parent;
+    }
+}
+
+/// Restores the heap order after the top has been replaced.
+fn sift_down<T>(heap: &mut [T], above: impl Fn(&T, &T) -> bool) {
+    let mut i = 0;
+    loop {
+        let mut top = i;
+        for child in [2 * i + 1, 2 * i + 2] {
+            if child < heap.len() && above(&heap[child], &heap[top]) {
+                top = child;
+            }
+        }
+        if top == i {
+            break;
+        }
+        heap.swap(i, top);
+        i = top;
+    }
+}
+
+/// Rough number of bytes a buffered row takes up in memory.
+fn keyed_size((key, row): &Keyed) -> usize {
+    let values = key.iter().chain(row).map(|value| match value {
+        Value::Text(s) => std::mem::size_of::<Value>() + s.len(),
+        Value::Blob(b) => std::mem::size_of::<Value>() + b.len(),
+        _ => std::mem::size_of::<Value>(),
+    });
+    std::mem::size_of::<Keyed>() + values.sum::<usize>()
+}
+
+/// A k-way merge of sorted runs. A heap of run numbers, ordered by each
+/// run's current row, tells which run to take from next.
+struct Merge {
+    runs: Vec<Run>,
+    heap: Vec<usize>,
+}
+
+impl Merge {
+    fn new(mut runs: Vec<Run>, compare: &Compare) -> Result<Self> {
+        for run in &mut runs {
+            run.open()?;
+        }
+
+        let mut merge = Merge {
+            heap: Vec::with_capacity(runs.len()),
+            runs,
+        };
+        for i in 0..merge.runs.len() {
+            if merge.runs[i].head.is_some() {
+                merge.heap.push(i);
+                sift_up(&mut merge.heap, |&a, &b| {
+                    comes_before(&merge.runs, a, b, compare)
+                });
+            }
+        }
+        Ok(merge)
+    }
+
+    fn next(&mut self, compare: &Compare) -> Result<Option<Keyed>> {
+        let Some(&i) = self.heap.first() else {
+            return Ok(None);
+        };
+
+        let row = self.runs[i].head.take().unwrap();
+        self.runs[i].advance()?;
+        if self.runs[i].head.is_none() {
+            self.heap.swap_remove(0);
+        }
+        if !self.heap.is_empty() {
+            sift_down(&mut self.heap, |&a, &b| {
+                comes_before(&self.runs, a, b, compare)
+            });
+        }
+        Ok(Some(row))
+    }
+}
+
+/// Whether run `a`'s current row comes out before run `b`'s. Ties go to the
+/// earlier run, which holds the earlier input rows.
+fn comes_before(runs: &[Run], a: usize, b: usize, compare: &Compare) -> bool {
+    let (Some((key_a, _)), Some((key_b, _))) = (&runs[a].head, &runs[b].head) else {
+        return false;
+    };
+    compare(key_a, key_b).then(a.cmp(&b)) == Ordering::Less
+}
+
+/// A sorted run spilled to disk, read back one row at a time once opened.
+/// The file is removed when the run is dropped.
+struct Run {
+    path: PathBuf,
+    reader: Option<BufReader<File>>,
+    head: Option<Keyed>,
+}
+
+// Run file layout: one entry per row, in sorted order.
+//
+//   entry := key_len:u32 key row_len:u32 row
+//
+// `key` and `row` are encoded like the rows of a heap file. Run files are
+// named `sort-<pid>-<n>.tmp`; any left behind by a crash are removed by
+// `remove_stale_runs`.
+
+impl Run {
+    fn write(dir: &Path, rows: impl Iterator<Item = Result<Keyed>>) -> Result<Run> {
+        let number = NEXT_RUN.fetch_add(1, atomic::Ordering::Relaxed);
+        let path = dir.join(format!("sort-{}-{}.tmp", std::process::id(), number));
+        let file = File::create(&path)?;
+
+        let run = Run {
+            path,
+            reader: None,
+            head: None,
+        };
+
+        let mut writer = BufWriter::new(file);
+        for row in rows {
+            let (key, row) = row?;
+            write_values(&mut writer, &key)?;
+            write_values(&mut writer, &row)?;
+        }
+        writer.flush()?;
+
+        Ok(run)
+    }
+
+    fn open(&mut self) -> Result<()> {
+        self.reader = Some(BufReader::new(File::open(&self.path)?));
+        self.advance()
+    }
+
+    fn advance(&mut self) -> Result<()> {
+        let reader = self
+            .reader
+            .as_mut()
+            .ok_or_else(|| anyhow!("Sort run read before it was opened"))?;
+
+        self.head = match read_values(reader)? {
+            Some(key) => {
+                let row = read_values(reader)?
+                    .ok_or_else(|| anyhow!("Sort run ends in the middle of a row"))?;
+                Some((key, row))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Removes run files that a sort interrupted by a crash left in `dir`.
+pub fn remove_stale_runs(dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("sort-") && name.ends_with(".tmp") {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn write_values(writer: &mut impl Write, values: &[Value]) -> Result<()> {
+    let bytes = encode_values(values);
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+fn read_values(reader: &mut impl Read) -> Result<Option<Vec<Value>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(decode_values(&bytes)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::TempDir;
+
+    /// Rows keyed by `i % 10` and numbered by their input position.
+    fn input(count: i64) -> impl Iterator<Item = Result<Keyed>> {
+        (0..count).map(|i| {
+            let key = vec![Value::Integer(i % 10)];
+            Ok((key.clone(), vec![key[0].clone(), Value::Integer(i)]))
+        })
+    }
+
+    fn sorted(dir: &Path, count: i64, memory_budget: usize) -> Vec<(i64, i64)> {
+        Sort::new(input(count), |a, b| a.cmp(b), dir, memory_budget)
+            .map(|row| match row.unwrap()[..] {
+                [Value::Integer(key), Value::Integer(i)] => (key, i),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    /// The stable order: by key, then by input position.
+    fn expected(count: i64) -> Vec<(i64, i64)> {
+        let mut rows: Vec<(i64, i64)> = (0..count).map(|i| (i % 10, i)).collect();
+        rows.sort_by_key(|&(key, _)| key);
+        rows
+    }
+
+    fn run_files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn sorts_in_memory_without_spilling() {
+        let dir = TempDir::new("sort-memory");
+        let mut sort = Sort::new(input(500), |a, b| a.cmp(b), &dir, DEFAULT_MEMORY_BUDGET);
+        sort.next();
+        assert_eq!(run_files(&dir), 0);
+        drop(sort);
+
+        assert_eq!(sorted(&dir, 500, DEFAULT_MEMORY_BUDGET), expected(500));
+    }
+
+    #[test]
+    fn spilled_runs_merge_back_stably() {
+        let dir = TempDir::new("sort-spill");
+        let row_size = keyed_size(&input(1).next().unwrap().unwrap());
+
+        let mut sort = Sort::new(input(500), |a, b| a.cmp(b), &dir, row_size * 50);
+        sort.next();
+        assert_eq!(run_files(&dir), 10);
+        drop(sort);
+        assert_eq!(run_files(&dir), 0);
+
+        assert_eq!(sorted(&dir, 500, row_size * 50), expected(500));
+    }
+
+    #[test]
+    fn more_runs_than_the_fan_in_merge_in_passes() {
+        let dir = TempDir::new("sort-passes");
+        let row_size = keyed_size(&input(1).next().unwrap().unwrap());
+
+        // One run per three rows gives more runs than one merge takes.
+        let count = (MERGE_FAN_IN * 3 * 2) as i64;
+        assert_eq!(sorted(&dir, count, row_size * 3), expected(count));
+        assert_eq!(run_files(&dir), 0);
+    }
+
+    fn top(dir: &Path, count: i64, limit: usize, memory_budget: usize) -> Vec<(i64, i64)> {
+        TopN::new(input(count), |a, b| a.cmp(b), limit, dir, memory_budget)
+            .map(|row| match row.unwrap()[..] {
+                [Value::Integer(key), Value::Integer(i)] => (key, i),
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn top_n_yields_the_first_rows_of_the_stable_order() {
+        let dir = TempDir::new("top-n");
+        for limit in [0, 1, 7, 50, 499, 500, 600] {
+            let first: Vec<_> = expected(500).into_iter().take(limit).collect();
+            assert_eq!(
+                top(&dir, 500, limit, DEFAULT_MEMORY_BUDGET),
+                first,
+                "{}",
+                limit
+            );
+        }
+        assert_eq!(run_files(&dir), 0);
+    }
+
+    #[test]
+    fn top_n_past_its_budget_falls_back_to_a_sort() {
+        let dir = TempDir::new("top-n-fallback");
+        let row_size = keyed_size(&input(1).next().unwrap().unwrap());
+
+        let first: Vec<_> = expected(500).into_iter().take(200).collect();
+        assert_eq!(top(&dir, 500, 200, row_size * 50), first);
+        assert_eq!(run_files(&dir), 0);
+    }
+}
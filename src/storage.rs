@@ -1,12 +1,28 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display};
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Bound;
 use std::path::{Path, PathBuf};
 
-use crate::parser::{Condition, OrderBy, OrderDirection};
+use crate::aggregate::{HashAggregator, check_grouping, collect_aggregates, substitute_aggregates};
+use crate::blob;
+use crate::btree::{self, BTree};
+use crate::datetime::{self, Interval};
+use crate::heap::{self, AppendCursor, BufferPool};
+use crate::join::join_rows;
+use crate::parser::{
+    AlterTableAction, Assignment, Condition, Expr, OrderBy, Select, SelectItem, TableRef,
+    resolve_column,
+};
+use crate::row::{self, RowReader};
+use crate::sort::{self, Sort, TopN};
+use crate::wal::{WalRecord, WriteAheadLog};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DatabaseMetadata {
@@ -19,6 +35,12 @@ pub struct TableMetadata {
     columns: Vec<Column>,
     row_count: usize,
     indexes: Vec<Index>,
+    /// Columns of the primary key, empty if the table has none. The key is
+    /// backed by the unique index named by `primary_key_index`.
+    #[serde(default)]
+    primary_key: Vec<String>,
+    #[serde(default)]
+    foreign_keys: Vec<ForeignKey>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -26,12 +48,242 @@ pub struct Index {
     name: String,
     columns: Vec<String>,
     file_path: String,
+    /// Whether no two rows may share a key, NULLs aside.
+    #[serde(default)]
+    unique: bool,
+}
+
+impl DatabaseMetadata {
+    pub fn tables(&self) -> impl Iterator<Item = &TableMetadata> {
+        self.tables.values()
+    }
+
+    pub fn table(&self, name: &str) -> Option<&TableMetadata> {
+        self.tables.get(name)
+    }
+
+    fn table_mut(&mut self, name: &str) -> Result<&mut TableMetadata> {
+        self.tables
+            .get_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))
+    }
+
+    /// Adds `column` to the end of `table_name`, unless a replayed log record
+    /// already did.
+    fn add_column(&mut self, table_name: &str, column: &Column) -> Result<()> {
+        let table = self.table_mut(table_name)?;
+        if !table.columns.iter().any(|col| col.name == column.name) {
+            table.columns.push(column.clone());
+        }
+        Ok(())
+    }
+
+    /// Removes column `name` from `table_name`, along with every index on
+    /// it; an index on (a, b) does not quietly become one on (b) alone.
+    /// Returns the dropped indexes.
+    fn drop_column(&mut self, table_name: &str, name: &str) -> Result<Vec<Index>> {
+        let table = self.table_mut(table_name)?;
+        table.columns.retain(|col| col.name != name);
+
+        let (dropped, kept): (Vec<Index>, Vec<Index>) = std::mem::take(&mut table.indexes)
+            .into_iter()
+            .partition(|idx| idx.columns.iter().any(|col_name| col_name == name));
+        table.indexes = kept;
+
+        Ok(dropped)
+    }
+
+    /// Renames column `from` of `table_name` everywhere it is referred to:
+    /// in the table's constraints and indexes and in foreign keys of other
+    /// tables.
+    fn rename_column(&mut self, table_name: &str, from: &str, to: &str) -> Result<()> {
+        let rename = |col_name: &mut String| {
+            if col_name == from {
+                *col_name = to.to_string();
+            }
+        };
+        let rename_ref = |expr: &Expr| match expr {
+            Expr::Column { table, name } if name == from => Some(Expr::Column {
+                table: table.clone(),
+                name: to.to_string(),
+            }),
+            _ => None,
+        };
+
+        let table = self.table_mut(table_name)?;
+        for col in &mut table.columns {
+            rename(&mut col.name);
+            col.check = col
+                .check
+                .as_ref()
+                .map(|check| check.transform_exprs(&rename_ref));
+        }
+        for index in &mut table.indexes {
+            index.columns.iter_mut().for_each(rename);
+        }
+        table.primary_key.iter_mut().for_each(rename);
+        for fk in &mut table.foreign_keys {
+            fk.columns.iter_mut().for_each(rename);
+        }
+
+        for table in self.tables.values_mut() {
+            for fk in &mut table.foreign_keys {
+                if fk.parent == table_name {
+                    fk.parent_columns.iter_mut().for_each(rename);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TableMetadata {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    pub fn primary_key(&self) -> &[String] {
+        &self.primary_key
+    }
+
+    pub fn foreign_keys(&self) -> &[ForeignKey] {
+        &self.foreign_keys
+    }
+}
+
+/// Name of the index created to back the primary key of `table_name`.
+pub fn primary_key_index(table_name: &str) -> String {
+    format!("{}_pkey", table_name)
+}
+
+impl TableMetadata {
+    fn new(
+        name: &str,
+        columns: &[Column],
+        primary_key: &[String],
+        foreign_keys: &[ForeignKey],
+    ) -> Self {
+        TableMetadata {
+            name: name.to_string(),
+            columns: columns.to_vec(),
+            row_count: 0,
+            indexes: Vec::new(),
+            primary_key: primary_key.to_vec(),
+            foreign_keys: foreign_keys.to_vec(),
+        }
+    }
+
+    /// The values `index` stores for `row`, padded like `complete_row` if the
+    /// row is too short.
+    fn index_key(&self, index: &Index, row: &[Value]) -> Result<Vec<Value>> {
+        self.column_values(&index.columns, row)
+    }
+
+    /// The values of the named columns in `row`, padded like `complete_row`
+    /// if the row is too short.
+    fn column_values(&self, col_names: &[String], row: &[Value]) -> Result<Vec<Value>> {
+        col_names
+            .iter()
+            .map(|col_name| {
+                let col_idx = self
+                    .columns
+                    .iter()
+                    .position(|col| &col.name == col_name)
+                    .ok_or_else(|| anyhow::anyhow!("Column {} not found", col_name))?;
+                Ok(row
+                    .get(col_idx)
+                    .cloned()
+                    .unwrap_or_else(|| self.columns[col_idx].missing_value()))
+            })
+            .collect()
+    }
+
+    /// Pads a row written before some of the columns were added with the
+    /// values those columns read as for it.
+    fn complete_row(&self, mut row: Vec<Value>) -> Vec<Value> {
+        if row.len() < self.columns.len() {
+            let added = &self.columns[row.len()..];
+            row.extend(added.iter().map(Column::missing_value));
+        }
+        row
+    }
+}
+
+impl Index {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Column {
     pub name: String,
     pub data_type: DataType,
+    #[serde(default)]
+    pub not_null: bool,
+    /// Value given to the column when an INSERT leaves it out.
+    #[serde(default)]
+    pub default: Option<Expr>,
+    /// Must hold for every row in which the column is not NULL.
+    #[serde(default)]
+    pub check: Option<Condition>,
+    /// What rows written before the column was added read as for it, if not
+    /// NULL.
+    #[serde(default)]
+    pub missing: Option<Value>,
+}
+
+impl Column {
+    fn missing_value(&self) -> Value {
+        self.missing.clone().unwrap_or(Value::Null)
+    }
+}
+
+/// A foreign key: the values of `columns` in every row must match the
+/// `parent_columns` of some row in `parent`, unless one of them is NULL.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ForeignKey {
+    pub columns: Vec<String>,
+    pub parent: String,
+    /// Empty in a freshly parsed key that refers to the parent's primary
+    /// key; filled in when the table is created.
+    pub parent_columns: Vec<String>,
+    pub on_delete: ReferentialAction,
+}
+
+/// What deleting a parent row does to the rows that reference it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ReferentialAction {
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+impl Display for ReferentialAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReferentialAction::Restrict => write!(f, "RESTRICT"),
+            ReferentialAction::Cascade => write!(f, "CASCADE"),
+            ReferentialAction::SetNull => write!(f, "SET NULL"),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -40,15 +292,43 @@ pub enum DataType {
     Text,
     Boolean,
     Real,
+    Date,
+    Timestamp,
+    Interval,
+    Blob,
+    Uuid,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Value {
     Null,
     Integer(i64),
     Text(String),
     Boolean(bool),
     Real(f64),
+    /// Days since 1970-01-01.
+    Date(i32),
+    /// Microseconds since 1970-01-01 00:00:00, without a time zone.
+    Timestamp(i64),
+    Interval(Interval),
+    Blob(Vec<u8>),
+    Uuid([u8; 16]),
+}
+
+impl Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Integer => write!(f, "INTEGER"),
+            DataType::Text => write!(f, "TEXT"),
+            DataType::Boolean => write!(f, "BOOLEAN"),
+            DataType::Real => write!(f, "REAL"),
+            DataType::Date => write!(f, "DATE"),
+            DataType::Timestamp => write!(f, "TIMESTAMP"),
+            DataType::Interval => write!(f, "INTERVAL"),
+            DataType::Blob => write!(f, "BLOB"),
+            DataType::Uuid => write!(f, "UUID"),
+        }
+    }
 }
 
 impl Display for Value {
@@ -58,18 +338,33 @@ impl Display for Value {
             Value::Text(s) => write!(f, "{}", s),
             Value::Boolean(b) => write!(f, "{}", b),
             Value::Real(r) => write!(f, "{}", r),
+            Value::Date(d) => write!(f, "{}", datetime::format_date(*d)),
+            Value::Timestamp(t) => write!(f, "{}", datetime::format_timestamp(*t)),
+            Value::Interval(i) => write!(f, "{}", i),
+            Value::Blob(b) => write!(f, "{}", blob::format_blob(b)),
+            Value::Uuid(u) => write!(f, "{}", blob::format_uuid(u)),
             Value::Null => write!(f, "NULL"),
         }
     }
 }
 
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
             (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
             (Value::Real(a), Value::Real(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_integer_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => {
+                compare_integer_real(*b, *a).map(Ordering::reverse)
+            }
             (Value::Text(a), Value::Text(b)) => a.partial_cmp(b),
             (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.partial_cmp(b),
+            (Value::Interval(a), Value::Interval(b)) => a.partial_cmp(b),
+            (Value::Blob(a), Value::Blob(b)) => a.partial_cmp(b),
+            (Value::Uuid(a), Value::Uuid(b)) => a.partial_cmp(b),
             (Value::Null, Value::Null) => Some(std::cmp::Ordering::Equal),
             (Value::Null, _) => Some(std::cmp::Ordering::Less),
             (_, Value::Null) => Some(std::cmp::Ordering::Greater),
@@ -84,17 +379,128 @@ impl Ord for Value {
     }
 }
 
+/// Values are equal when they compare equal, so `10 = 10.0` holds.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
 impl Eq for Value {}
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct RowId {
-    pub offset: u64,
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // A REAL equal to an INTEGER must hash like it.
+        if let Value::Real(r) = self
+            && let Some(i) = integral_real(*r)
+        {
+            return Value::Integer(i).hash(state);
+        }
+
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Null => {}
+            Value::Integer(i) => i.hash(state),
+            Value::Text(s) => s.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Real(r) => r.to_bits().hash(state),
+            Value::Date(d) => d.hash(state),
+            Value::Timestamp(t) => t.hash(state),
+            Value::Interval(i) => i.hash(state),
+            Value::Blob(b) => b.hash(state),
+            Value::Uuid(u) => u.hash(state),
+        }
+    }
+}
+
+/// An index lookup chosen by `Storage::find_usable_index`: equality values
+/// for the leading `prefix.len()` index columns and an optional range on the
+/// column after them.
+struct IndexScan {
+    index: String,
+    prefix: Vec<Value>,
+    lower: Bound<Value>,
+    upper: Bound<Value>,
+}
+
+/// A stream of rows, each read or computed when it is asked for.
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Vec<Value>>> + 'a>;
+
+/// A stream of the live rows of one table along with their row ids.
+type TableRows<'a> = Box<dyn Iterator<Item = Result<(u64, Vec<Value>)>> + 'a>;
+
+/// The rows of a query, produced one at a time as the caller advances it;
+/// see `Storage::get_rows`. The cursor borrows the storage, so it has to be
+/// dropped before the next write.
+pub struct RowCursor<'a> {
+    rows: Rows<'a>,
+}
+
+impl Iterator for RowCursor<'_> {
+    type Item = Result<Vec<Value>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
 }
 
+/// Number of heap pages the buffer pool keeps in memory, across all tables.
+const BUFFER_POOL_PAGES: usize = 256;
+
+/// Number of rows copied at a time when a table's data file is rewritten.
+const REWRITE_CHUNK_ROWS: usize = 1024;
+
 pub struct Storage {
     metadata: DatabaseMetadata,
     db_dir: PathBuf,
-    page_size: usize,
+    pool: RefCell<BufferPool>,
+    wal: WriteAheadLog,
+    transaction: Option<Transaction>,
+    /// Bytes of rows an ORDER BY may hold in memory before it spills sorted
+    /// runs to disk.
+    sort_memory: usize,
+}
+
+/// Writes made since BEGIN. They are staged here and in `Storage::metadata`
+/// and only reach the log and the data files on COMMIT.
+struct Transaction {
+    /// The metadata as of BEGIN, put back on COMMIT before the staged
+    /// records are applied for real, or on ROLLBACK.
+    committed: DatabaseMetadata,
+    records: Vec<WalRecord>,
+    tables: HashMap<String, PendingTable>,
+}
+
+/// The staged row changes of one table. Staged rows already carry the row id
+/// they will get, so later records in the same transaction can refer to them
+/// just like to committed rows.
+struct PendingTable {
+    /// Rows of the data file with an id at or past this one are not visible
+    /// to the transaction; it is zero for tables created by the transaction.
+    base_id: u64,
+    cursor: AppendCursor,
+    inserted: Vec<(u64, Vec<Value>)>,
+    deleted: HashSet<u64>,
+}
+
+impl PendingTable {
+    fn new(cursor: AppendCursor) -> Self {
+        PendingTable {
+            base_id: cursor.next_row_id(),
+            cursor,
+            inserted: Vec::new(),
+            deleted: HashSet::new(),
+        }
+    }
+
+    /// State for a table created by the transaction: nothing on disk is
+    /// visible and the first row goes on the first data page.
+    fn created() -> Self {
+        PendingTable {
+            base_id: 0,
+            ..PendingTable::new(AppendCursor::empty())
+        }
+    }
 }
 
 impl Storage {
@@ -124,18 +530,48 @@ impl Storage {
             }
         };
 
-        Ok(Storage {
+        let wal = WriteAheadLog::open(&db_dir.join("wal.log"))?;
+
+        let mut storage = Storage {
             metadata,
             db_dir,
-            page_size: 1000,
-        })
+            pool: RefCell::new(BufferPool::new(BUFFER_POOL_PAGES)),
+            wal,
+            transaction: None,
+            sort_memory: sort::DEFAULT_MEMORY_BUDGET,
+        };
+        storage.recover()?;
+        sort::remove_stale_runs(&storage.db_dir)?;
+        storage.convert_tables()?;
+        storage.migrate_indexes()?;
+
+        Ok(storage)
+    }
+
+    pub fn metadata(&self) -> &DatabaseMetadata {
+        &self.metadata
+    }
+
+    pub fn sort_memory(&self) -> usize {
+        self.sort_memory
+    }
+
+    pub fn set_sort_memory(&mut self, bytes: usize) {
+        self.sort_memory = bytes;
     }
 
+    /// Writes the metadata to a sibling file and renames it into place, so a
+    /// crash never leaves a truncated `metadata.json` behind.
     fn save_metadata(&self) -> Result<()> {
         let metadata_path = self.db_dir.join("metadata.json");
-        let mut file = File::create(metadata_path)?;
+        let tmp_path = self.db_dir.join("metadata.json.tmp");
+
+        let mut file = File::create(&tmp_path)?;
         let json = serde_json::to_string_pretty(&self.metadata)?;
         file.write_all(json.as_bytes())?;
+        file.sync_data()?;
+
+        fs::rename(tmp_path, metadata_path)?;
         Ok(())
     }
 
@@ -148,546 +584,3457 @@ impl Storage {
             .join(format!("{}_{}.idx", table_name, index_name))
     }
 
-    pub fn create_table(&mut self, name: &str, columns: Vec<Column>) -> Result<()> {
-        if self.metadata.tables.contains_key(name) {
-            return Err(anyhow::anyhow!("Table already exists"));
+    /// Logs `records` as one batch, applies them and clears the log again.
+    /// Once `append` returns, a crash at any later point is finished off by
+    /// `recover` the next time the database is opened. Inside a transaction
+    /// the records are only staged until COMMIT.
+    fn write(&mut self, records: Vec<WalRecord>) -> Result<()> {
+        if self.transaction.is_some() {
+            return self.stage(records);
         }
 
-        let table = TableMetadata {
-            name: name.to_string(),
-            columns,
-            row_count: 0,
-            indexes: Vec::new(),
-        };
-
-        let table_path = self.table_path(name);
-        File::create(table_path)?;
-
-        self.metadata.tables.insert(name.to_string(), table);
+        self.wal.append(&records)?;
+        for record in &records {
+            self.apply(record, true)?;
+        }
         self.save_metadata()?;
+        self.wal.clear()
+    }
 
+    pub fn begin(&mut self) -> Result<()> {
+        if self.transaction.is_some() {
+            return Err(anyhow::anyhow!("A transaction is already in progress"));
+        }
+
+        self.transaction = Some(Transaction {
+            committed: self.metadata.clone(),
+            records: Vec::new(),
+            tables: HashMap::new(),
+        });
         Ok(())
     }
 
-    pub fn insert_row(
-        &mut self,
-        table_name: &str,
-        columns: Option<Vec<String>>,
-        values: Vec<Value>,
-    ) -> Result<()> {
-        let table_path = self.table_path(table_name);
-
-        let table_metadata = self
-            .metadata
-            .tables
-            .get_mut(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+    /// Writes everything staged since BEGIN as a single logged batch, so the
+    /// whole transaction survives a crash or none of it does.
+    pub fn commit(&mut self) -> Result<()> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("No transaction is in progress"))?;
+
+        self.metadata = transaction.committed;
+        if transaction.records.is_empty() {
+            return Ok(());
+        }
+        self.write(transaction.records)
+    }
 
-        let values = if let Some(col_names) = columns {
-            if col_names.len() != values.len() {
-                return Err(anyhow::anyhow!(
-                    "Number of column names does not match number of values"
-                ));
-            }
+    pub fn rollback(&mut self) -> Result<()> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("No transaction is in progress"))?;
 
-            let mut col_map = HashMap::new();
-            for (i, col_name) in col_names.iter().enumerate() {
-                col_map.insert(col_name.clone(), values[i].clone());
-            }
+        self.metadata = transaction.committed;
+        Ok(())
+    }
 
-            let mut ordered_values = Vec::with_capacity(table_metadata.columns.len());
-            for col in &table_metadata.columns {
-                if let Some(value) = col_map.get(&col.name) {
-                    ordered_values.push(value.clone());
-                } else {
-                    ordered_values.push(Value::Null);
+    /// Applies `records` to the transaction's view of the database without
+    /// touching any file. Metadata changes go straight into `self.metadata`;
+    /// row changes are tracked per table in `PendingTable`.
+    fn stage(&mut self, records: Vec<WalRecord>) -> Result<()> {
+        for record in &records {
+            match record {
+                WalRecord::CreateTable {
+                    name,
+                    columns,
+                    primary_key,
+                    foreign_keys,
+                } => {
+                    self.metadata.tables.insert(
+                        name.clone(),
+                        TableMetadata::new(name, columns, primary_key, foreign_keys),
+                    );
+                    self.transaction
+                        .as_mut()
+                        .unwrap()
+                        .tables
+                        .insert(name.clone(), PendingTable::created());
                 }
-            }
+                WalRecord::DropTable { name } => {
+                    self.metadata.tables.remove(name);
+                    self.transaction.as_mut().unwrap().tables.remove(name);
+                }
+                WalRecord::CreateIndex {
+                    table,
+                    name,
+                    columns,
+                    unique,
+                } => {
+                    // The index file is only built on COMMIT, so reads of this
+                    // table have to go through the pending state from now on.
+                    self.pending_table_mut(table)?;
+
+                    let index_path = self.index_path(table, name);
+                    let table_metadata = self
+                        .metadata
+                        .tables
+                        .get_mut(table)
+                        .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+                    table_metadata.indexes.push(Index {
+                        name: name.clone(),
+                        columns: columns.clone(),
+                        file_path: index_path.to_string_lossy().to_string(),
+                        unique: *unique,
+                    });
+                }
+                WalRecord::DropIndex { table, name } => {
+                    if let Some(table_metadata) = self.metadata.tables.get_mut(table) {
+                        table_metadata.indexes.retain(|idx| &idx.name != name);
+                    }
+                }
+                WalRecord::Truncate { table } => {
+                    // Nothing on disk is visible any more, and rows added
+                    // after this go where they would in a new file.
+                    self.transaction
+                        .as_mut()
+                        .unwrap()
+                        .tables
+                        .insert(table.clone(), PendingTable::created());
+                    if let Some(table_metadata) = self.metadata.tables.get_mut(table) {
+                        table_metadata.row_count = 0;
+                    }
+                }
+                WalRecord::AddColumn { table, column } => {
+                    self.metadata.add_column(table, column)?;
+                }
+                WalRecord::DropColumn { table, name } => {
+                    // The data file is rewritten on COMMIT, giving every row
+                    // a new id, so the rows are staged the way they will be
+                    // laid out then. Index files are dropped on COMMIT too.
+                    let col_idx = self
+                        .table_metadata(table)?
+                        .columns
+                        .iter()
+                        .position(|col| &col.name == name)
+                        .ok_or_else(|| anyhow::anyhow!("Column {} not found", name))?;
+                    let rows = self
+                        .table_rows(table)?
+                        .map(|item| {
+                            item.map(|(_, mut row)| {
+                                row.remove(col_idx);
+                                row
+                            })
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    self.metadata.drop_column(table, name)?;
+
+                    let mut pending = PendingTable::created();
+                    for row in rows {
+                        let id = pending.cursor.place(row::encode_values(&row).len())?;
+                        pending.inserted.push((id, row));
+                    }
+                    self.transaction
+                        .as_mut()
+                        .unwrap()
+                        .tables
+                        .insert(table.clone(), pending);
+                }
+                WalRecord::RenameColumn { table, from, to } => {
+                    self.metadata.rename_column(table, from, to)?;
+                }
+                WalRecord::Insert { table, rows, .. } => {
+                    let pending = self.pending_table_mut(table)?;
+                    for row in rows {
+                        let id = pending.cursor.place(row::encode_values(row).len())?;
+                        pending.inserted.push((id, row.clone()));
+                    }
 
-            ordered_values
-        } else {
-            if table_metadata.columns.len() != values.len() {
-                return Err(anyhow::anyhow!(
-                    "Number of values does not match number of columns"
-                ));
-            }
-            values
-        };
+                    if let Some(table_metadata) = self.metadata.tables.get_mut(table) {
+                        table_metadata.row_count += rows.len();
+                    }
+                }
+                WalRecord::Delete { table, row_ids } => {
+                    let pending = self.pending_table_mut(table)?;
+                    for &id in row_ids {
+                        if id < pending.base_id {
+                            pending.deleted.insert(id);
+                        } else {
+                            pending.inserted.retain(|(inserted, _)| *inserted != id);
+                        }
+                    }
 
-        for (i, value) in values.iter().enumerate() {
-            match (value, &table_metadata.columns[i].data_type) {
-                (Value::Null, _) => {}
-                (Value::Integer(_), DataType::Integer) => {}
-                (Value::Text(_), DataType::Text) => {}
-                (Value::Boolean(_), DataType::Boolean) => {}
-                (Value::Real(_), DataType::Real) => {}
-                (v, dt) => {
-                    return Err(anyhow::anyhow!(
-                        "Type mismatch: {:?} is not compatible with {:?}",
-                        v,
-                        dt
-                    ));
+                    if let Some(table_metadata) = self.metadata.tables.get_mut(table) {
+                        table_metadata.row_count =
+                            table_metadata.row_count.saturating_sub(row_ids.len());
+                    }
                 }
             }
         }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&table_path)?;
-
-        let position = file.seek(SeekFrom::End(0))?;
-
-        let row_json = serde_json::to_string(&values)?;
-        writeln!(file, "{}", row_json)?;
+        self.transaction.as_mut().unwrap().records.extend(records);
+        Ok(())
+    }
 
-        let indexes: Vec<_> = table_metadata
-            .indexes
-            .iter()
-            .map(|idx| (idx.name.clone(), idx.file_path.clone()))
-            .collect();
-        table_metadata.row_count += 1;
-        self.save_metadata()?;
+    fn pending_table(&self, table_name: &str) -> Option<&PendingTable> {
+        self.transaction
+            .as_ref()
+            .and_then(|transaction| transaction.tables.get(table_name))
+    }
 
-        for (index_name, _) in indexes {
-            self.update_index(table_name, &index_name, &values, position)?;
+    /// The staged state of `table_name`. It starts from the data file's
+    /// append cursor, which is read only the first time the transaction
+    /// touches the table.
+    fn pending_table_mut(&mut self, table_name: &str) -> Result<&mut PendingTable> {
+        let staged = self
+            .transaction
+            .as_ref()
+            .unwrap()
+            .tables
+            .contains_key(table_name);
+        if !staged {
+            let cursor = self.committed_cursor(table_name)?;
+            self.transaction
+                .as_mut()
+                .unwrap()
+                .tables
+                .insert(table_name.to_string(), PendingTable::new(cursor));
         }
+        Ok(self
+            .transaction
+            .as_mut()
+            .unwrap()
+            .tables
+            .get_mut(table_name)
+            .unwrap())
+    }
 
-        Ok(())
+    /// Where the next row appended to `table_name` will go, taking rows
+    /// staged by the current transaction into account.
+    fn append_cursor(&self, table_name: &str) -> Result<AppendCursor> {
+        match self.pending_table(table_name) {
+            Some(pending) => Ok(pending.cursor),
+            None => self.committed_cursor(table_name),
+        }
     }
 
-    fn update_index(
-        &self,
-        table_name: &str,
-        index_name: &str,
-        values: &[Value],
-        position: u64,
-    ) -> Result<()> {
-        let table_metadata = self
-            .metadata
-            .tables
-            .get(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+    /// The append cursor of the data file as it is on disk, or that of an
+    /// empty file if there is none yet.
+    fn committed_cursor(&self, table_name: &str) -> Result<AppendCursor> {
+        let table_path = self.table_path(table_name);
+        if !table_path.exists() {
+            return Ok(AppendCursor::empty());
+        }
+        heap::append_cursor(&mut self.pool.borrow_mut(), &table_path)
+    }
 
-        let index = table_metadata
-            .indexes
-            .iter()
-            .find(|idx| idx.name == index_name)
-            .ok_or_else(|| anyhow::anyhow!("Index not found"))?;
+    /// Replays whatever the log still holds. A batch that is in the log was
+    /// only partly applied, if at all, so every record is applied again and
+    /// the row counts and indexes of the tables it touched are rebuilt from
+    /// their data files.
+    fn recover(&mut self) -> Result<()> {
+        let records = self.wal.pending()?;
+
+        let mut touched = Vec::new();
+        for record in &records {
+            self.apply(record, false)?;
+            if !touched.iter().any(|table| table == record.table()) {
+                touched.push(record.table().to_string());
+            }
+        }
 
-        let mut key_values = Vec::new();
-        for col_name in &index.columns {
-            let col_idx = table_metadata
-                .columns
+        for table_name in touched {
+            let Some(table_metadata) = self.metadata.tables.get(&table_name) else {
+                continue;
+            };
+            let index_names: Vec<_> = table_metadata
+                .indexes
                 .iter()
-                .position(|col| &col.name == col_name)
-                .ok_or_else(|| anyhow::anyhow!("Column not found in table schema"))?;
+                .map(|idx| idx.name.clone())
+                .collect();
 
-            key_values.push(values[col_idx].clone());
+            let row_count = self.count_rows(&table_name)?;
+            self.metadata.tables.get_mut(&table_name).unwrap().row_count = row_count;
+
+            for index_name in index_names {
+                self.rebuild_index(&table_name, &index_name)?;
+            }
         }
 
-        let key = serde_json::to_string(&key_values)?;
-        let row_id = RowId { offset: position };
+        if !records.is_empty() {
+            self.save_metadata()?;
+        }
+        self.wal.clear()
+    }
 
-        let index_path = Path::new(&index.file_path);
-        let mut index_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(index_path)?;
+    /// Rewrites every table still stored as JSON lines or as a plain record
+    /// stream as a heap file. Row ids change in the process, so the table's
+    /// index files are removed before the new data file is swapped in and
+    /// rebuilt by `migrate_indexes` afterwards; a crash part way through just
+    /// redoes the conversion on the next open.
+    fn convert_tables(&self) -> Result<()> {
+        for table in self.metadata.tables.values() {
+            let table_path = self.table_path(&table.name);
+            if heap::is_heap_file(&table_path)? {
+                continue;
+            }
 
-        let entry = format!("{}\t{}\n", key, position);
-        index_file.write_all(entry.as_bytes())?;
+            let mut rows = Vec::new();
+            if row::is_row_file(&table_path)? {
+                let mut reader = RowReader::open(&table_path)?;
+                while let Some((_, values)) = reader.next_row()? {
+                    rows.push(values);
+                }
+            } else {
+                let reader = BufReader::new(File::open(&table_path)?);
+                for line in reader.lines() {
+                    let line = line?;
+                    if line.is_empty() || line.as_bytes()[0] == row::TOMBSTONE {
+                        continue;
+                    }
+                    rows.push(serde_json::from_str(&line)?);
+                }
+            }
+
+            let tmp_path = self.db_dir.join(format!("{}.data.tmp", table.name));
+            let mut pool = self.pool.borrow_mut();
+            heap::create(&mut pool, &tmp_path)?;
+            if !rows.is_empty() {
+                heap::append(&mut pool, &tmp_path, AppendCursor::empty(), &rows)?;
+            }
+            pool.sync(&tmp_path)?;
+            pool.invalidate(&tmp_path);
+            pool.invalidate(&table_path);
+
+            for index in &table.indexes {
+                let index_path = Path::new(&index.file_path);
+                if index_path.exists() {
+                    fs::remove_file(index_path)?;
+                }
+            }
+            fs::rename(&tmp_path, &table_path)?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds every index still stored in the old line-based `.idx` format
+    /// as a B+tree.
+    fn migrate_indexes(&self) -> Result<()> {
+        for table in self.metadata.tables.values() {
+            for index in &table.indexes {
+                if !BTree::is_btree_file(Path::new(&index.file_path)) {
+                    self.rebuild_index(&table.name, &index.name)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies a single logged change to the data files and the in-memory
+    /// metadata, and to the index files if `update_indexes` is set. Safe to
+    /// repeat for a record that was already applied, which is what `recover`
+    /// relies on; it rebuilds the indexes itself afterwards.
+    fn apply(&mut self, record: &WalRecord, update_indexes: bool) -> Result<()> {
+        match record {
+            WalRecord::CreateTable {
+                name,
+                columns,
+                primary_key,
+                foreign_keys,
+            } => {
+                heap::create(&mut self.pool.borrow_mut(), &self.table_path(name))?;
+                self.metadata.tables.insert(
+                    name.clone(),
+                    TableMetadata::new(name, columns, primary_key, foreign_keys),
+                );
+            }
+            WalRecord::DropTable { name } => {
+                if let Some(table_metadata) = self.metadata.tables.remove(name) {
+                    for index in table_metadata.indexes {
+                        let path = Path::new(&index.file_path);
+                        if path.exists() {
+                            fs::remove_file(path)?;
+                        }
+                    }
+                }
+
+                let table_path = self.table_path(name);
+                self.pool.borrow_mut().invalidate(&table_path);
+                if table_path.exists() {
+                    fs::remove_file(table_path)?;
+                }
+            }
+            WalRecord::CreateIndex {
+                table,
+                name,
+                columns,
+                unique,
+            } => {
+                let index_path = self.index_path(table, name);
+                let table_metadata = self
+                    .metadata
+                    .tables
+                    .get_mut(table)
+                    .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+                if !table_metadata.indexes.iter().any(|idx| &idx.name == name) {
+                    table_metadata.indexes.push(Index {
+                        name: name.clone(),
+                        columns: columns.clone(),
+                        file_path: index_path.to_string_lossy().to_string(),
+                        unique: *unique,
+                    });
+                }
+
+                if update_indexes {
+                    self.rebuild_index(table, name)?;
+                }
+            }
+            WalRecord::DropIndex { table, name } => {
+                if let Some(table_metadata) = self.metadata.tables.get_mut(table) {
+                    table_metadata.indexes.retain(|idx| &idx.name != name);
+                }
+
+                let index_path = self.index_path(table, name);
+                if index_path.exists() {
+                    fs::remove_file(index_path)?;
+                }
+            }
+            WalRecord::Truncate { table } => {
+                heap::create(&mut self.pool.borrow_mut(), &self.table_path(table))?;
+
+                let table_metadata = self
+                    .metadata
+                    .tables
+                    .get_mut(table)
+                    .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+                table_metadata.row_count = 0;
+                for index in &table_metadata.indexes {
+                    BTree::create(Path::new(&index.file_path))?.sync()?;
+                }
+            }
+            WalRecord::AddColumn { table, column } => {
+                self.metadata.add_column(table, column)?;
+            }
+            WalRecord::DropColumn { table, name } => {
+                let table_path = self.table_path(table);
+                let new_path = self.db_dir.join(format!("{}.data.new", table));
+
+                // The rewritten file only replaces the data file once the
+                // saved metadata no longer lists the column. A crash before
+                // that starts the copy over; one after it finds the metadata
+                // updated and just finishes the swap.
+                let table_metadata = self.table_metadata(table)?;
+                if let Some(col_idx) = table_metadata
+                    .columns
+                    .iter()
+                    .position(|col| &col.name == name)
+                {
+                    self.copy_without_column(table, col_idx, &new_path)?;
+                    for index in self.metadata.drop_column(table, name)? {
+                        let path = Path::new(&index.file_path);
+                        if path.exists() {
+                            fs::remove_file(path)?;
+                        }
+                    }
+                    self.save_metadata()?;
+                }
+
+                if new_path.exists() {
+                    let mut pool = self.pool.borrow_mut();
+                    pool.invalidate(&new_path);
+                    pool.invalidate(&table_path);
+                    drop(pool);
+                    fs::rename(&new_path, &table_path)?;
+
+                    // Every row has a new id now.
+                    if update_indexes {
+                        let index_names: Vec<_> = self
+                            .table_metadata(table)?
+                            .indexes
+                            .iter()
+                            .map(|idx| idx.name.clone())
+                            .collect();
+                        for index_name in index_names {
+                            self.rebuild_index(table, &index_name)?;
+                        }
+                    }
+                }
+            }
+            WalRecord::RenameColumn { table, from, to } => {
+                self.metadata.rename_column(table, from, to)?;
+            }
+            WalRecord::Insert { table, at, rows } => {
+                let table_path = self.table_path(table);
+                let mut pool = self.pool.borrow_mut();
+                let row_ids = heap::append(&mut pool, &table_path, *at, rows)?;
+                pool.sync(&table_path)?;
+                drop(pool);
+
+                let table_metadata = self
+                    .metadata
+                    .tables
+                    .get_mut(table)
+                    .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+                table_metadata.row_count += rows.len();
+
+                if update_indexes {
+                    for index in &table_metadata.indexes {
+                        let mut tree = BTree::open(Path::new(&index.file_path))?;
+                        for (row, &id) in rows.iter().zip(&row_ids) {
+                            tree.insert(table_metadata.index_key(index, row)?, id)?;
+                        }
+                        tree.sync()?;
+                    }
+                }
+            }
+            WalRecord::Delete { table, row_ids } => {
+                let table_path = self.table_path(table);
+                let mut pool = self.pool.borrow_mut();
+
+                // Index entries are keyed by value, so the rows have to be
+                // read before they are deleted.
+                let mut deleted_rows = Vec::new();
+                if update_indexes {
+                    for &id in row_ids {
+                        if let Some(row) = heap::read(&mut pool, &table_path, id)? {
+                            deleted_rows.push((id, row));
+                        }
+                    }
+                }
+
+                for &id in row_ids {
+                    heap::delete(&mut pool, &table_path, id)?;
+                }
+                pool.sync(&table_path)?;
+                drop(pool);
+
+                let table_metadata = self
+                    .metadata
+                    .tables
+                    .get_mut(table)
+                    .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+                table_metadata.row_count = table_metadata.row_count.saturating_sub(row_ids.len());
+
+                if update_indexes {
+                    for index in &table_metadata.indexes {
+                        let mut tree = BTree::open(Path::new(&index.file_path))?;
+                        for (id, row) in &deleted_rows {
+                            tree.remove(&table_metadata.index_key(index, row)?, *id)?;
+                        }
+                        tree.sync()?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the live rows of `table_name` to a new heap file at `path`,
+    /// leaving out the value of column `col_idx`. Rows are copied a chunk at
+    /// a time, so the table never has to fit in memory.
+    fn copy_without_column(&self, table_name: &str, col_idx: usize, path: &Path) -> Result<()> {
+        let table_metadata = self.table_metadata(table_name)?;
+        heap::create(&mut self.pool.borrow_mut(), path)?;
+
+        let mut rows = heap::Scan::new(&self.pool, &self.table_path(table_name))?.peekable();
+        let mut at = AppendCursor::empty();
+        while rows.peek().is_some() {
+            let chunk = rows
+                .by_ref()
+                .take(REWRITE_CHUNK_ROWS)
+                .map(|item| {
+                    item.map(|(_, row)| {
+                        let mut row = table_metadata.complete_row(row);
+                        row.remove(col_idx);
+                        row
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut pool = self.pool.borrow_mut();
+            heap::append(&mut pool, path, at, &chunk)?;
+            at = heap::append_cursor(&mut pool, path)?;
+        }
+        self.pool.borrow_mut().sync(path)
+    }
+
+    fn count_rows(&self, table_name: &str) -> Result<usize> {
+        let mut count = 0;
+        heap::scan(&self.pool, &self.table_path(table_name), |_, _| {
+            count += 1;
+            Ok(true)
+        })?;
+
+        Ok(count)
+    }
+
+    /// Creates table `name`. Returns `false`, doing nothing, if it already
+    /// exists and `if_not_exists` is set.
+    pub fn create_table(
+        &mut self,
+        name: &str,
+        columns: Vec<Column>,
+        primary_key: Vec<String>,
+        foreign_keys: Vec<ForeignKey>,
+        if_not_exists: bool,
+    ) -> Result<bool> {
+        if self.metadata.tables.contains_key(name) {
+            if if_not_exists {
+                return Ok(false);
+            }
+            return Err(anyhow::anyhow!("Table already exists"));
+        }
+
+        for (i, col_name) in primary_key.iter().enumerate() {
+            if !columns.iter().any(|col| &col.name == col_name) {
+                return Err(anyhow::anyhow!(
+                    "PRIMARY KEY column {} not found in table {}",
+                    col_name,
+                    name
+                ));
+            }
+            if primary_key[..i].contains(col_name) {
+                return Err(anyhow::anyhow!(
+                    "Column {} appears twice in the PRIMARY KEY of table {}",
+                    col_name,
+                    name
+                ));
+            }
+        }
+
+        for col in &columns {
+            check_column_constraints(name, col, &columns)?;
+        }
+
+        let foreign_keys = foreign_keys
+            .into_iter()
+            .map(|fk| self.resolve_foreign_key(name, &columns, &primary_key, fk))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut records = vec![WalRecord::CreateTable {
+            name: name.to_string(),
+            columns,
+            primary_key: primary_key.clone(),
+            foreign_keys,
+        }];
+        if !primary_key.is_empty() {
+            records.push(WalRecord::CreateIndex {
+                table: name.to_string(),
+                name: primary_key_index(name),
+                columns: primary_key,
+                unique: true,
+            });
+        }
+
+        self.write(records)?;
+        Ok(true)
+    }
+
+    /// Checks a foreign key declared on a new table against the table it
+    /// refers to, which may be the new table itself, and fills in the
+    /// referenced columns if the key left them out.
+    fn resolve_foreign_key(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        primary_key: &[String],
+        mut fk: ForeignKey,
+    ) -> Result<ForeignKey> {
+        let (parent_columns, parent_key) = if fk.parent == table_name {
+            (columns, primary_key)
+        } else {
+            let parent = self.table_metadata(&fk.parent)?;
+            (&parent.columns[..], &parent.primary_key[..])
+        };
+
+        if fk.parent_columns.is_empty() {
+            if parent_key.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Table {} has no PRIMARY KEY for table {} to reference",
+                    fk.parent,
+                    table_name
+                ));
+            }
+            fk.parent_columns = parent_key.to_vec();
+        }
+
+        if fk.columns.len() != fk.parent_columns.len() {
+            return Err(anyhow::anyhow!(
+                "Foreign key ({}) on table {} has {} column(s) but references {} in table {}",
+                fk.columns.join(", "),
+                table_name,
+                fk.columns.len(),
+                fk.parent_columns.len(),
+                fk.parent
+            ));
+        }
+
+        for (col_name, parent_col_name) in fk.columns.iter().zip(&fk.parent_columns) {
+            let col = columns
+                .iter()
+                .find(|col| &col.name == col_name)
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Column {} not found in table {}", col_name, table_name)
+                })?;
+            let parent_col = parent_columns
+                .iter()
+                .find(|col| &col.name == parent_col_name)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Column {} not found in table {}",
+                        parent_col_name,
+                        fk.parent
+                    )
+                })?;
+            if col.data_type != parent_col.data_type {
+                return Err(anyhow::anyhow!(
+                    "Foreign key column {}.{} is {} but references {}.{} of type {}",
+                    table_name,
+                    col_name,
+                    col.data_type,
+                    fk.parent,
+                    parent_col_name,
+                    parent_col.data_type
+                ));
+            }
+        }
+
+        // Parent rows are looked up through a unique index on exactly the
+        // referenced columns, which also guarantees there is only one.
+        let indexed = if fk.parent == table_name {
+            fk.parent_columns == primary_key
+        } else {
+            self.table_metadata(&fk.parent)?
+                .indexes
+                .iter()
+                .any(|idx| idx.unique && idx.columns == fk.parent_columns)
+        };
+        if !indexed {
+            return Err(anyhow::anyhow!(
+                "Columns ({}) of table {} are not a PRIMARY KEY and cannot be referenced",
+                fk.parent_columns.join(", "),
+                fk.parent
+            ));
+        }
+
+        if fk.on_delete == ReferentialAction::SetNull
+            && let Some(col_name) = fk.columns.iter().find(|col| primary_key.contains(col))
+        {
+            return Err(anyhow::anyhow!(
+                "ON DELETE SET NULL cannot apply to PRIMARY KEY column {} of table {}",
+                col_name,
+                table_name
+            ));
+        }
+
+        Ok(fk)
+    }
+
+    pub fn insert_row(
+        &mut self,
+        table_name: &str,
+        columns: Option<Vec<String>>,
+        values: Vec<Value>,
+    ) -> Result<()> {
+        let table_metadata = self
+            .metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+        let values = if let Some(col_names) = columns {
+            if col_names.len() != values.len() {
+                return Err(anyhow::anyhow!(
+                    "Number of column names does not match number of values"
+                ));
+            }
+
+            let mut col_map = HashMap::new();
+            for (i, col_name) in col_names.iter().enumerate() {
+                col_map.insert(col_name.clone(), values[i].clone());
+            }
+
+            let mut ordered_values = Vec::with_capacity(table_metadata.columns.len());
+            for col in &table_metadata.columns {
+                if let Some(value) = col_map.get(&col.name) {
+                    ordered_values.push(value.clone());
+                } else if let Some(default) = &col.default {
+                    ordered_values.push(default.evaluate(&[], &[])?);
+                } else {
+                    ordered_values.push(Value::Null);
+                }
+            }
+
+            ordered_values
+        } else {
+            if table_metadata.columns.len() != values.len() {
+                return Err(anyhow::anyhow!(
+                    "Number of values does not match number of columns"
+                ));
+            }
+            values
+        };
+
+        let values = values
+            .into_iter()
+            .zip(&table_metadata.columns)
+            .map(|(value, col)| coerce_value(value, &col.data_type))
+            .collect::<Result<Vec<_>>>()?;
+        check_constraints(table_metadata, &values)?;
+
+        let rows = vec![values];
+        self.check_unique(table_name, &rows, &HashSet::new())?;
+        self.check_references(table_name, &rows, &HashSet::new())?;
+
+        let at = self.append_cursor(table_name)?;
+
+        self.write(vec![WalRecord::Insert {
+            table: table_name.to_string(),
+            at,
+            rows,
+        }])
+    }
+
+    /// Fails if writing `rows` to `table_name` would give two rows the same
+    /// key in one of its unique indexes, or leave a primary key column NULL.
+    /// The rows in `replaced` are about to be deleted, so their keys may be
+    /// reused.
+    fn check_unique(
+        &self,
+        table_name: &str,
+        rows: &[Vec<Value>],
+        replaced: &HashSet<u64>,
+    ) -> Result<()> {
+        let table_metadata = self.table_metadata(table_name)?;
+
+        for col_name in &table_metadata.primary_key {
+            let col_idx = table_metadata
+                .columns
+                .iter()
+                .position(|col| &col.name == col_name)
+                .ok_or_else(|| anyhow::anyhow!("Column {} not found", col_name))?;
+            if rows.iter().any(|row| row[col_idx] == Value::Null) {
+                return Err(anyhow::anyhow!(
+                    "Constraint violation on table {}: PRIMARY KEY column {} cannot be NULL",
+                    table_name,
+                    col_name
+                ));
+            }
+        }
+
+        for index in table_metadata.indexes.iter().filter(|idx| idx.unique) {
+            let mut keys = HashSet::new();
+            for row in rows {
+                let key = table_metadata.index_key(index, row)?;
+                // NULL never equals anything, so it cannot collide.
+                if key.contains(&Value::Null) {
+                    continue;
+                }
+                if !keys.insert(key.clone())
+                    || self.key_exists(table_name, index, &key, replaced)?
+                {
+                    return Err(duplicate_key_error(table_metadata, index, &key));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fails if one of `rows` has a foreign key that matches no row of the
+    /// table it references. The rows in `replaced` are about to be deleted,
+    /// so they cannot be referenced.
+    fn check_references(
+        &self,
+        table_name: &str,
+        rows: &[Vec<Value>],
+        replaced: &HashSet<u64>,
+    ) -> Result<()> {
+        let table_metadata = self.table_metadata(table_name)?;
+        let no_rows = HashSet::new();
+
+        for fk in &table_metadata.foreign_keys {
+            let parent = self.table_metadata(&fk.parent)?;
+            let index = parent
+                .indexes
+                .iter()
+                .find(|idx| idx.unique && idx.columns == fk.parent_columns)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Index on ({}) of table {} not found",
+                        fk.parent_columns.join(", "),
+                        fk.parent
+                    )
+                })?;
+            let self_referencing = fk.parent == table_name;
+
+            for row in rows {
+                let key = table_metadata.column_values(&fk.columns, row)?;
+                if key.contains(&Value::Null) {
+                    continue;
+                }
+
+                // A row may refer to itself or to another row written with it.
+                if self_referencing
+                    && rows.iter().any(|other| {
+                        parent
+                            .column_values(&fk.parent_columns, other)
+                            .is_ok_and(|k| k == key)
+                    })
+                {
+                    continue;
+                }
+
+                let skip = if self_referencing { replaced } else { &no_rows };
+                if !self.key_exists(&fk.parent, index, &key, skip)? {
+                    let values: Vec<String> = key.iter().map(|value| value.to_string()).collect();
+                    return Err(anyhow::anyhow!(
+                        "Constraint violation on table {}: foreign key ({}) = ({}) has no matching row in table {}",
+                        table_name,
+                        fk.columns.join(", "),
+                        values.join(", "),
+                        fk.parent
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every foreign key that references `table_name`, with the name of the
+    /// table that declares it.
+    fn referencing_keys(&self, table_name: &str) -> Vec<(String, ForeignKey)> {
+        let mut keys: Vec<(String, ForeignKey)> = self
+            .metadata
+            .tables
+            .values()
+            .flat_map(|table| {
+                table
+                    .foreign_keys
+                    .iter()
+                    .filter(|fk| fk.parent == table_name)
+                    .map(|fk| (table.name.clone(), fk.clone()))
+            })
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        keys
+    }
+
+    /// Whether a live row of `table_name` outside `skip` has `key` in
+    /// `index`.
+    fn key_exists(
+        &self,
+        table_name: &str,
+        index: &Index,
+        key: &[Value],
+        skip: &HashSet<u64>,
+    ) -> Result<bool> {
+        let table_metadata = self.table_metadata(table_name)?;
+
+        // Indexes only learn about a transaction's rows when it commits, so
+        // a table it has touched is searched row by row instead.
+        if self.pending_table(table_name).is_some() {
+            for item in self.table_rows(table_name)? {
+                let (id, row) = item?;
+                if !skip.contains(&id) && table_metadata.index_key(index, &row)? == key {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+
+        let data_path = self.table_path(table_name);
+        for entry in BTree::open(Path::new(&index.file_path))?.scan(Some(key))? {
+            let (entry_key, id) = entry?;
+            if entry_key != key {
+                break;
+            }
+            if !skip.contains(&id)
+                && heap::read(&mut self.pool.borrow_mut(), &data_path, id)?.is_some()
+            {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Runs `select` and returns a cursor over its rows. Rows are read,
+    /// filtered, limited and projected one at a time as the cursor is
+    /// advanced. What is held in memory instead:
+    ///
+    /// - every joined table, as the build side of its hash join;
+    /// - one entry per group of an aggregate query, and its result rows,
+    ///   which are all computed before the first one comes out;
+    /// - the rows of an ORDER BY, which spills sorted runs to disk when
+    ///   there are many.
+    pub fn get_rows(&self, select: Select) -> Result<RowCursor<'_>> {
+        let select = if select.joins.is_empty() {
+            select.unqualified()
+        } else {
+            select
+        };
+        let columns = self.query_columns(&select)?;
+
+        if select.is_aggregate() {
+            let rows = self.get_aggregated_rows(&columns, select)?;
+            return Ok(RowCursor {
+                rows: Box::new(rows.into_iter().map(Ok)),
+            });
+        }
+
+        let mut rows = self.scan_query(&select, &columns)?;
+
+        let offset = select.offset.unwrap_or(0);
+
+        if !select.order_by.is_empty() {
+            let key_exprs = select.order_by_exprs(&columns)?;
+            let key_columns = columns.clone();
+            let keyed = rows.map(move |row| {
+                let row = row?;
+                let key = key_exprs
+                    .iter()
+                    .map(|expr| expr.evaluate(&row, &key_columns))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((key, row))
+            });
+
+            let order_by = select.order_by.clone();
+            let compare = move |a: &[Value], b: &[Value]| compare_sort_keys(&order_by, a, b);
+
+            // Only the first `offset + limit` rows in order can make it past
+            // LIMIT, so there is no need to sort the others.
+            rows = match select.limit {
+                Some(limit) => Box::new(TopN::new(
+                    keyed,
+                    compare,
+                    offset.saturating_add(limit),
+                    &self.db_dir,
+                    self.sort_memory,
+                )),
+                None => Box::new(Sort::new(keyed, compare, &self.db_dir, self.sort_memory)),
+            };
+        }
+
+        if offset > 0 {
+            // Unlike `skip`, this lets an error through instead of counting
+            // it as one of the skipped rows.
+            let mut skipped = 0;
+            rows = Box::new(rows.filter(move |row| {
+                if row.is_ok() && skipped < offset {
+                    skipped += 1;
+                    return false;
+                }
+                true
+            }));
+        }
+
+        if let Some(limit) = select.limit {
+            rows = Box::new(rows.take(limit));
+        }
+
+        if select
+            .columns
+            .iter()
+            .any(|item| !matches!(item, SelectItem::Wildcard))
+        {
+            let items = select.columns;
+            rows = Box::new(rows.map(move |row| {
+                let row = row?;
+                let mut projected_row = Vec::new();
+
+                for item in &items {
+                    match item {
+                        SelectItem::Wildcard => projected_row.extend(row.iter().cloned()),
+                        SelectItem::Expr { expr, .. } => {
+                            projected_row.push(expr.evaluate(&row, &columns)?)
+                        }
+                    }
+                }
+
+                Ok(projected_row)
+            }));
+        }
+
+        Ok(RowCursor { rows })
+    }
+
+    /// Names of the columns `get_rows` returns for `select`, for display.
+    pub fn column_names(&self, select: &Select) -> Result<Vec<String>> {
+        let columns = self.query_columns(select)?;
+
+        let mut names = Vec::new();
+        for item in &select.columns {
+            match item {
+                SelectItem::Wildcard => names.extend(columns.iter().map(|col| col.name.clone())),
+                SelectItem::Expr {
+                    alias: Some(alias), ..
+                } => names.push(alias.clone()),
+                SelectItem::Expr { expr, alias: None } => names.push(expr.to_string()),
+            }
+        }
+
+        Ok(names)
+    }
+
+    /// Columns of the rows a query produces before projection. Joined rows
+    /// concatenate every table's columns, named `qualifier.column`.
+    fn query_columns(&self, select: &Select) -> Result<Vec<Column>> {
+        if select.joins.is_empty() {
+            return Ok(self.table_metadata(&select.table.name)?.columns.clone());
+        }
+
+        let mut columns = self.qualified_columns(&select.table)?;
+        let mut qualifiers = vec![select.table.qualifier()];
+        for join in &select.joins {
+            if qualifiers.contains(&join.table.qualifier()) {
+                return Err(anyhow::anyhow!(
+                    "Table {} is specified more than once; give it an alias",
+                    join.table.qualifier()
+                ));
+            }
+            qualifiers.push(join.table.qualifier());
+            columns.extend(self.qualified_columns(&join.table)?);
+        }
+
+        Ok(columns)
+    }
+
+    fn qualified_columns(&self, table: &TableRef) -> Result<Vec<Column>> {
+        Ok(self
+            .table_metadata(&table.name)?
+            .columns
+            .iter()
+            .map(|col| Column {
+                name: format!("{}.{}", table.qualifier(), col.name),
+                ..col.clone()
+            })
+            .collect())
+    }
+
+    fn table_metadata(&self, table_name: &str) -> Result<&TableMetadata> {
+        self.metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table {} not found", table_name))
+    }
+
+    /// Rows of the query's FROM clause that satisfy its WHERE clause.
+    ///
+    /// The FROM table is streamed through every join. Each joined table is
+    /// read in full first, as it is the build side of its hash join.
+    fn scan_query(&self, select: &Select, columns: &[Column]) -> Result<Rows<'_>> {
+        if select.joins.is_empty() {
+            return self.scan_rows(&select.table.name, select.conditions.clone());
+        }
+
+        let mut rows = self.scan_rows(&select.table.name, None)?;
+        let mut width = self.table_metadata(&select.table.name)?.columns.len();
+        for join in &select.joins {
+            let right = self.load_all_rows(&join.table.name)?;
+            let right_width = self.table_metadata(&join.table.name)?.columns.len();
+            rows = join_rows(
+                rows,
+                right,
+                width,
+                &columns[..width + right_width],
+                join.kind,
+                &join.on,
+            );
+            width += right_width;
+        }
+
+        Ok(filter_rows(
+            rows,
+            select.conditions.clone(),
+            columns.to_vec(),
+        ))
+    }
+
+    fn load_all_rows(&self, table_name: &str) -> Result<Vec<Vec<Value>>> {
+        self.scan_rows(table_name, None)?.collect()
+    }
+
+    /// Rows of `table_name` matching `condition`, read through an index when
+    /// one applies.
+    fn scan_rows(&self, table_name: &str, condition: Option<Condition>) -> Result<Rows<'_>> {
+        let columns = self.table_metadata(table_name)?.columns.clone();
+
+        // Index files do not know about staged rows, so a table touched by
+        // the current transaction is always scanned in full.
+        let index_scan = match &condition {
+            Some(condition) if self.pending_table(table_name).is_none() => {
+                self.find_usable_index(table_name, condition)
+            }
+            _ => None,
+        };
+
+        let rows = match index_scan {
+            Some(scan) => self.index_rows(table_name, scan)?,
+            None => Box::new(
+                self.table_rows(table_name)?
+                    .map(|item| item.map(|(_, row)| row)),
+            ),
+        };
+
+        Ok(filter_rows(rows, condition, columns))
+    }
+
+    /// Runs an aggregate query. Input rows are streamed into the groups,
+    /// but every group is kept until the input runs out, and the result rows
+    /// are collected so HAVING, ORDER BY and LIMIT can be applied to them.
+    fn get_aggregated_rows(&self, columns: &[Column], select: Select) -> Result<Vec<Vec<Value>>> {
+        let mut exprs = Vec::new();
+        for item in &select.columns {
+            match item {
+                SelectItem::Wildcard => {
+                    return Err(anyhow::anyhow!(
+                        "SELECT * cannot be combined with GROUP BY or aggregate functions"
+                    ));
+                }
+                SelectItem::Expr { expr, .. } => exprs.push(expr),
+            }
+        }
+        let key_exprs = select.order_by_exprs(columns)?;
+        exprs.extend(&key_exprs);
+        check_grouping(&exprs, select.having.as_ref(), &select.group_by, columns)?;
+
+        let mut aggregates = Vec::new();
+        for expr in exprs {
+            collect_aggregates(expr, &mut aggregates);
+        }
+        if let Some(ref having) = select.having {
+            having.walk_exprs(&mut |expr| collect_aggregates(expr, &mut aggregates));
+        }
+
+        let mut aggregator = HashAggregator::new(&select.group_by, &aggregates, columns);
+        for row in self.scan_query(&select, columns)? {
+            aggregator.push(row?)?;
+        }
+
+        let mut result_rows = Vec::new();
+        for group in aggregator.finish() {
+            let resolve = |expr: &Expr| substitute_aggregates(expr, &aggregates, &group.values);
+
+            if let Some(ref having) = select.having
+                && !having
+                    .transform_exprs(&resolve)
+                    .evaluate(&group.row, columns)?
+            {
+                continue;
+            }
+
+            let mut projected_row = Vec::with_capacity(select.columns.len());
+            for item in &select.columns {
+                if let SelectItem::Expr { expr, .. } = item {
+                    projected_row.push(expr.transform(&resolve).evaluate(&group.row, columns)?);
+                }
+            }
+
+            let mut sort_key = Vec::with_capacity(key_exprs.len());
+            for expr in &key_exprs {
+                sort_key.push(expr.transform(&resolve).evaluate(&group.row, columns)?);
+            }
+            result_rows.push((sort_key, projected_row));
+        }
+
+        if !select.order_by.is_empty() {
+            result_rows.sort_by(|(a, _), (b, _)| compare_sort_keys(&select.order_by, a, b));
+        }
+
+        Ok(result_rows
+            .into_iter()
+            .map(|(_, row)| row)
+            .skip(select.offset.unwrap_or(0))
+            .take(select.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// Picks the index that narrows `condition` the most. An index qualifies
+    /// when its leading columns are compared for equality, when its first
+    /// unconstrained column has a range, or both; the more leading columns
+    /// are pinned down the better.
+    fn find_usable_index(&self, table_name: &str, condition: &Condition) -> Option<IndexScan> {
+        let table_metadata = self.metadata.tables.get(table_name)?;
+        let conjuncts = condition.conjuncts();
+
+        let mut best: Option<(usize, IndexScan)> = None;
+        for index in &table_metadata.indexes {
+            let mut prefix = Vec::new();
+            for col_name in &index.columns {
+                let value = conjuncts.iter().find_map(|condition| {
+                    condition
+                        .column_equality()
+                        .filter(|(column, _)| column == col_name)
+                        .map(|(_, value)| value.clone())
+                });
+                match value {
+                    Some(value) => prefix.push(value),
+                    None => break,
+                }
+            }
+
+            let (mut lower, mut upper) = (Bound::Unbounded, Bound::Unbounded);
+            if let Some(col_name) = index.columns.get(prefix.len()) {
+                for condition in &conjuncts {
+                    if let Some((column, low, high)) = condition.column_range()
+                        && column == col_name
+                    {
+                        lower = tighter_bound(lower, low.cloned(), Ordering::Greater);
+                        upper = tighter_bound(upper, high.cloned(), Ordering::Less);
+                    }
+                }
+            }
+
+            let has_range = lower != Bound::Unbounded || upper != Bound::Unbounded;
+            if prefix.is_empty() && !has_range {
+                continue;
+            }
+
+            let score = prefix.len() * 2 + has_range as usize;
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score > *best_score)
+            {
+                best = Some((
+                    score,
+                    IndexScan {
+                        index: index.name.clone(),
+                        prefix,
+                        lower,
+                        upper,
+                    },
+                ));
+            }
+        }
+
+        best.map(|(_, scan)| scan)
+    }
+
+    /// Rows whose index key falls within `scan`, in index order. Leaves and
+    /// data pages are read as the rows are taken.
+    fn index_rows(&self, table_name: &str, scan: IndexScan) -> Result<Rows<'_>> {
+        let table_metadata = self.table_metadata(table_name)?;
+
+        let index = table_metadata
+            .indexes
+            .iter()
+            .find(|idx| idx.name == scan.index)
+            .ok_or_else(|| anyhow::anyhow!("Index not found"))?;
+
+        // `col = NULL` holds for no row, so neither does the scan.
+        if scan.prefix.iter().any(|value| matches!(value, Value::Null)) {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let data_path = self.table_path(table_name);
+        let has_range = scan.lower != Bound::Unbounded || scan.upper != Bound::Unbounded;
+
+        let mut start = scan.prefix.clone();
+        if let Bound::Included(value) | Bound::Excluded(value) = &scan.lower {
+            start.push(value.clone());
+        }
+
+        let width = scan.prefix.len();
+        let mut entries = BTree::open(Path::new(&index.file_path))?.scan(Some(&start))?;
+        let rows = std::iter::from_fn(move || {
+            loop {
+                let (key, id) = match entries.next()? {
+                    Ok(entry) => entry,
+                    Err(err) => return Some(Err(err)),
+                };
+
+                // Keys are ordered, so the first one outside the prefix or
+                // past the upper bound ends the range.
+                if key[..width] != scan.prefix[..] {
+                    return None;
+                }
+
+                if let Some(value) = key.get(width) {
+                    // NULL satisfies no range, and it sorts first, so the
+                    // NULL keys sit at the low end and are stepped over.
+                    if has_range && matches!(value, Value::Null) {
+                        continue;
+                    }
+                    if matches!(&scan.lower, Bound::Excluded(lower) if value == lower) {
+                        continue;
+                    }
+                    match &scan.upper {
+                        Bound::Included(upper) if value > upper => return None,
+                        Bound::Excluded(upper) if value >= upper => return None,
+                        _ => {}
+                    }
+                }
+
+                match heap::read(&mut self.pool.borrow_mut(), &data_path, id) {
+                    Ok(Some(row)) => return Some(Ok(table_metadata.complete_row(row))),
+                    Ok(None) => continue,
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+        });
+
+        Ok(Box::new(rows.fuse()))
+    }
+
+    /// Creates index `index_name` on `table_name`. Returns `false`, doing
+    /// nothing, if the table already has an index of that name and
+    /// `if_not_exists` is set.
+    pub fn create_index(
+        &mut self,
+        table_name: &str,
+        index_name: &str,
+        columns: Vec<String>,
+        if_not_exists: bool,
+    ) -> Result<bool> {
+        if !self.metadata.tables.contains_key(table_name) {
+            return Err(anyhow::anyhow!("Table does not exist"));
+        }
+
+        let table_metadata = self
+            .metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+        if table_metadata
+            .indexes
+            .iter()
+            .any(|index| index.name == index_name)
+        {
+            if if_not_exists {
+                return Ok(false);
+            }
+            return Err(anyhow::anyhow!("Index already exists"));
+        }
+
+        for col_name in &columns {
+            if !table_metadata
+                .columns
+                .iter()
+                .any(|col| &col.name == col_name)
+            {
+                return Err(anyhow::anyhow!("Column {} not found", col_name));
+            }
+        }
+
+        // Every key must fit the tree, or building it would fail after the
+        // WAL record is written.
+        for item in self.table_rows(table_name)? {
+            let (_, row) = item?;
+            check_index_key(index_name, &table_metadata.column_values(&columns, &row)?)?;
+        }
+
+        self.write(vec![WalRecord::CreateIndex {
+            table: table_name.to_string(),
+            name: index_name.to_string(),
+            columns,
+            unique: false,
+        }])?;
+        Ok(true)
+    }
+
+    fn rebuild_index(&self, table_name: &str, index_name: &str) -> Result<()> {
+        let table_metadata = self
+            .metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+        let index = table_metadata
+            .indexes
+            .iter()
+            .find(|idx| idx.name == index_name)
+            .ok_or_else(|| anyhow::anyhow!("Index not found"))?;
+
+        let mut tree = BTree::create(Path::new(&index.file_path))?;
+
+        heap::scan(&self.pool, &self.table_path(table_name), |id, row| {
+            tree.insert(table_metadata.index_key(index, &row)?, id)?;
+            Ok(true)
+        })?;
+        tree.sync()
+    }
+
+    pub fn update_rows(
+        &mut self,
+        table_name: &str,
+        assignments: Vec<Assignment>,
+        conditions: Option<Condition>,
+    ) -> Result<usize> {
+        let table_metadata = self
+            .metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+        let mut targets = Vec::with_capacity(assignments.len());
+        for assignment in assignments {
+            let col_idx = table_metadata
+                .columns
+                .iter()
+                .position(|col| col.name == assignment.column)
+                .ok_or_else(|| anyhow::anyhow!("Column {} not found", assignment.column))?;
+
+            targets.push((col_idx, assignment.value));
+        }
+
+        let matching = self.matching_rows(table_name, conditions.as_ref())?;
+        if matching.is_empty() {
+            return Ok(0);
+        }
+
+        let mut row_ids = Vec::with_capacity(matching.len());
+        let mut old_rows = Vec::with_capacity(matching.len());
+        let mut rows = Vec::with_capacity(matching.len());
+        for (id, mut row) in matching {
+            old_rows.push(row.clone());
+
+            // Every assignment sees the row as it was before the update.
+            let mut new_values = Vec::with_capacity(targets.len());
+            for (col_idx, expr) in &targets {
+                let value = coerce_value(
+                    expr.evaluate(&row, &table_metadata.columns)?,
+                    &table_metadata.columns[*col_idx].data_type,
+                )?;
+                new_values.push((*col_idx, value));
+            }
+            for (col_idx, value) in new_values {
+                row[col_idx] = value;
+            }
+            check_constraints(table_metadata, &row)?;
+
+            row_ids.push(id);
+            rows.push(row);
+        }
+
+        let replaced = row_ids.iter().copied().collect();
+        self.check_unique(table_name, &rows, &replaced)?;
+        self.check_references(table_name, &rows, &replaced)?;
+        self.check_unreferenced(table_name, &old_rows, &rows, &replaced)?;
+
+        // Rows are variable-length, so an updated row cannot always be
+        // patched in place. The old version is deleted and the new one
+        // appended, both as part of the same logged batch.
+        let updated = rows.len();
+        let at = self.append_cursor(table_name)?;
+        self.write(vec![
+            WalRecord::Delete {
+                table: table_name.to_string(),
+                row_ids,
+            },
+            WalRecord::Insert {
+                table: table_name.to_string(),
+                at,
+                rows,
+            },
+        ])?;
+
+        Ok(updated)
+    }
+
+    pub fn delete_rows(
+        &mut self,
+        table_name: &str,
+        conditions: Option<Condition>,
+    ) -> Result<usize> {
+        let matching = self.matching_rows(table_name, conditions.as_ref())?;
+        if matching.is_empty() {
+            return Ok(0);
+        }
+
+        let deleted = matching.len();
+        let records = self.delete_records(table_name, matching)?;
+        self.write(records)?;
+
+        Ok(deleted)
+    }
+
+    /// Log records that delete `rows` from `table_name` and carry out the
+    /// ON DELETE action of every foreign key referencing them, following
+    /// cascades as far as they go.
+    fn delete_records(
+        &self,
+        table_name: &str,
+        rows: Vec<(u64, Vec<Value>)>,
+    ) -> Result<Vec<WalRecord>> {
+        let mut deleted: HashMap<String, HashSet<u64>> = HashMap::new();
+        let mut nulled: HashMap<String, HashMap<u64, Vec<Value>>> = HashMap::new();
+
+        deleted
+            .entry(table_name.to_string())
+            .or_default()
+            .extend(rows.iter().map(|(id, _)| *id));
+        let mut work = vec![(table_name.to_string(), rows)];
+
+        while let Some((parent_name, parent_rows)) = work.pop() {
+            let parent = self.table_metadata(&parent_name)?;
+
+            for (child_name, fk) in self.referencing_keys(&parent_name) {
+                let keys = parent_keys(parent, &fk, parent_rows.iter().map(|(_, row)| row))?;
+                if keys.is_empty() {
+                    continue;
+                }
+
+                let child = self.table_metadata(&child_name)?;
+                let mut cascaded = Vec::new();
+                for item in self.table_rows(&child_name)? {
+                    let (id, row) = item?;
+                    if deleted
+                        .get(&child_name)
+                        .is_some_and(|ids| ids.contains(&id))
+                    {
+                        continue;
+                    }
+                    // An earlier SET NULL may already have changed the row.
+                    let row = nulled
+                        .get(&child_name)
+                        .and_then(|rows| rows.get(&id))
+                        .cloned()
+                        .unwrap_or(row);
+
+                    let key = child.column_values(&fk.columns, &row)?;
+                    if !keys.contains(&key) {
+                        continue;
+                    }
+
+                    match fk.on_delete {
+                        ReferentialAction::Restrict => {
+                            return Err(referenced_error(parent, &child_name, &fk, &key));
+                        }
+                        ReferentialAction::Cascade => {
+                            deleted.entry(child_name.clone()).or_default().insert(id);
+                            if let Some(rows) = nulled.get_mut(&child_name) {
+                                rows.remove(&id);
+                            }
+                            cascaded.push((id, row));
+                        }
+                        ReferentialAction::SetNull => {
+                            let mut row = row;
+                            for col_name in &fk.columns {
+                                let col_idx = child
+                                    .columns
+                                    .iter()
+                                    .position(|col| &col.name == col_name)
+                                    .ok_or_else(|| {
+                                        anyhow::anyhow!("Column {} not found", col_name)
+                                    })?;
+                                row[col_idx] = Value::Null;
+                            }
+                            check_constraints(child, &row)?;
+                            nulled
+                                .entry(child_name.clone())
+                                .or_default()
+                                .insert(id, row);
+                        }
+                    }
+                }
+
+                if !cascaded.is_empty() {
+                    work.push((child_name, cascaded));
+                }
+            }
+        }
+
+        let mut tables: Vec<&String> = deleted.keys().chain(nulled.keys()).collect();
+        tables.sort();
+        tables.dedup();
+
+        let mut records = Vec::new();
+        for table in tables {
+            let mut row_ids: Vec<u64> = deleted.get(table).into_iter().flatten().copied().collect();
+            let mut rows: Vec<(u64, Vec<Value>)> = nulled
+                .get(table)
+                .into_iter()
+                .flatten()
+                .map(|(id, row)| (*id, row.clone()))
+                .collect();
+            rows.sort_by_key(|(id, _)| *id);
+            row_ids.extend(rows.iter().map(|(id, _)| *id));
+            row_ids.sort();
+
+            records.push(WalRecord::Delete {
+                table: table.clone(),
+                row_ids,
+            });
+            if !rows.is_empty() {
+                // A row whose reference was set to NULL is rewritten like
+                // any other update.
+                let rewritten = rows.iter().map(|(id, _)| *id).collect();
+                let rows: Vec<Vec<Value>> = rows.into_iter().map(|(_, row)| row).collect();
+                self.check_unique(table, &rows, &rewritten)?;
+                records.push(WalRecord::Insert {
+                    table: table.clone(),
+                    at: self.append_cursor(table)?,
+                    rows,
+                });
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Fails if replacing `old_rows` of `table_name` with `new_rows` would
+    /// take away a key that rows elsewhere still reference. The rows in
+    /// `replaced` are the ones being rewritten.
+    fn check_unreferenced(
+        &self,
+        table_name: &str,
+        old_rows: &[Vec<Value>],
+        new_rows: &[Vec<Value>],
+        replaced: &HashSet<u64>,
+    ) -> Result<()> {
+        let table_metadata = self.table_metadata(table_name)?;
+
+        for (child_name, fk) in self.referencing_keys(table_name) {
+            let kept = parent_keys(table_metadata, &fk, new_rows.iter())?;
+            let removed: HashSet<Vec<Value>> = parent_keys(table_metadata, &fk, old_rows.iter())?
+                .into_iter()
+                .filter(|key| !kept.contains(key))
+                .collect();
+            if removed.is_empty() {
+                continue;
+            }
+
+            let child = self.table_metadata(&child_name)?;
+            for item in self.table_rows(&child_name)? {
+                let (id, row) = item?;
+                if child_name == table_name && replaced.contains(&id) {
+                    continue;
+                }
+                let key = child.column_values(&fk.columns, &row)?;
+                if removed.contains(&key) {
+                    return Err(referenced_error(table_metadata, &child_name, &fk, &key));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the row id and contents of every live row that satisfies
+    /// `conditions`.
+    fn matching_rows(
+        &self,
+        table_name: &str,
+        conditions: Option<&Condition>,
+    ) -> Result<Vec<(u64, Vec<Value>)>> {
+        let table_metadata = self
+            .metadata
+            .tables
+            .get(table_name)
+            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+
+        let mut matching = Vec::new();
+        for item in self.table_rows(table_name)? {
+            let (id, row) = item?;
+            let matches = match conditions {
+                Some(condition) => condition.evaluate(&row, &table_metadata.columns)?,
+                None => true,
+            };
+
+            if matches {
+                matching.push((id, row));
+            }
+        }
+
+        Ok(matching)
+    }
+
+    /// Every live row of `table_name` and its id, as seen by the current
+    /// transaction, read one page at a time.
+    fn table_rows(&self, table_name: &str) -> Result<TableRows<'_>> {
+        let table_metadata = self.table_metadata(table_name)?;
+        let pending = self.pending_table(table_name);
+        let base_id = pending.map_or(u64::MAX, |pending| pending.base_id);
+
+        let committed = if base_id > 0 {
+            Some(heap::Scan::new(&self.pool, &self.table_path(table_name))?)
+        } else {
+            None
+        };
+
+        let committed = committed
+            .into_iter()
+            .flatten()
+            .take_while(move |item| !matches!(item, Ok((id, _)) if *id >= base_id))
+            .filter(move |item| {
+                !matches!(item, Ok((id, _))
+                    if pending.is_some_and(|pending| pending.deleted.contains(id)))
+            });
+        let staged = pending
+            .into_iter()
+            .flat_map(|pending| &pending.inserted)
+            .map(|(id, row)| Ok((*id, row.clone())));
+
+        Ok(Box::new(committed.chain(staged).map(|item| {
+            item.map(|(id, row)| (id, table_metadata.complete_row(row)))
+        })))
+    }
+
+    /// Drops table `name`. Returns `false`, doing nothing, if it does not
+    /// exist and `if_exists` is set.
+    pub fn drop_table(&mut self, name: &str, if_exists: bool) -> Result<bool> {
+        if !self.metadata.tables.contains_key(name) {
+            if if_exists {
+                return Ok(false);
+            }
+            return Err(anyhow::anyhow!("Table does not exist"));
+        }
+
+        if let Some((child_name, _)) = self
+            .referencing_keys(name)
+            .into_iter()
+            .find(|(child_name, _)| child_name != name)
+        {
+            return Err(anyhow::anyhow!(
+                "Cannot drop table {}: table {} references it",
+                name,
+                child_name
+            ));
+        }
+
+        self.write(vec![WalRecord::DropTable {
+            name: name.to_string(),
+        }])?;
+        Ok(true)
+    }
+
+    /// Deletes every row of `table_name` by replacing its data file and
+    /// index files with empty ones. Returns `false`, doing nothing, if the
+    /// table does not exist and `if_exists` is set.
+    pub fn truncate_table(&mut self, table_name: &str, if_exists: bool) -> Result<bool> {
+        if if_exists && !self.metadata.tables.contains_key(table_name) {
+            return Ok(false);
+        }
+        self.table_metadata(table_name)?;
+
+        if let Some((child_name, _)) = self
+            .referencing_keys(table_name)
+            .into_iter()
+            .find(|(child_name, _)| child_name != table_name)
+        {
+            return Err(anyhow::anyhow!(
+                "Cannot truncate table {}: table {} references it",
+                table_name,
+                child_name
+            ));
+        }
+
+        self.write(vec![WalRecord::Truncate {
+            table: table_name.to_string(),
+        }])?;
+        Ok(true)
+    }
+
+    /// Applies `action` to `table_name`. Returns `false`, doing nothing, if
+    /// the table does not exist and `if_exists` is set, or if the action's
+    /// own IF [NOT] EXISTS makes it a no-op.
+    pub fn alter_table(
+        &mut self,
+        table_name: &str,
+        if_exists: bool,
+        action: AlterTableAction,
+    ) -> Result<bool> {
+        if if_exists && !self.metadata.tables.contains_key(table_name) {
+            return Ok(false);
+        }
+        let table_metadata = self.table_metadata(table_name)?;
+
+        match action {
+            AlterTableAction::Add {
+                column,
+                if_not_exists,
+            } => {
+                let mut column = *column;
+                if table_metadata
+                    .columns
+                    .iter()
+                    .any(|col| col.name == column.name)
+                {
+                    if if_not_exists {
+                        return Ok(false);
+                    }
+                    return Err(anyhow::anyhow!(
+                        "Column {} already exists in table {}",
+                        column.name,
+                        table_name
+                    ));
+                }
+
+                // Existing rows get a single value for the column, so a
+                // default that differs from row to row cannot fill them.
+                if let Some(default) = &column.default
+                    && default.is_volatile()
+                {
+                    return Err(anyhow::anyhow!(
+                        "Cannot add column {} to table {} with DEFAULT {}: existing rows would all share one value",
+                        column.name,
+                        table_name,
+                        default
+                    ));
+                }
+
+                let mut altered = table_metadata.clone();
+                altered.columns.push(column.clone());
+                check_column_constraints(table_name, &column, &altered.columns)?;
+
+                // Existing rows are not rewritten; they read as having the
+                // default the column has now.
+                column.missing = column
+                    .default
+                    .as_ref()
+                    .map(|default| coerce_value(default.evaluate(&[], &[])?, &column.data_type))
+                    .transpose()?
+                    .filter(|value| *value != Value::Null);
+                altered.columns.last_mut().unwrap().missing = column.missing.clone();
+
+                for item in self.table_rows(table_name)? {
+                    let (_, row) = item?;
+                    check_constraints(&altered, &altered.complete_row(row))?;
+                }
+
+                self.write(vec![WalRecord::AddColumn {
+                    table: table_name.to_string(),
+                    column,
+                }])?;
+            }
+            AlterTableAction::Drop { name, if_exists } => {
+                if !table_metadata.columns.iter().any(|col| col.name == name) {
+                    if if_exists {
+                        return Ok(false);
+                    }
+                    return Err(anyhow::anyhow!(
+                        "Column {} not found in table {}",
+                        name,
+                        table_name
+                    ));
+                }
+                self.check_droppable(table_metadata, &name)?;
+
+                // Later columns shift down one place, so applying the record
+                // rewrites every row without the column.
+                self.write(vec![WalRecord::DropColumn {
+                    table: table_name.to_string(),
+                    name,
+                }])?;
+            }
+            AlterTableAction::Rename { from, to } => {
+                if !table_metadata.columns.iter().any(|col| col.name == from) {
+                    return Err(anyhow::anyhow!(
+                        "Column {} not found in table {}",
+                        from,
+                        table_name
+                    ));
+                }
+                if table_metadata.columns.iter().any(|col| col.name == to) {
+                    return Err(anyhow::anyhow!(
+                        "Column {} already exists in table {}",
+                        to,
+                        table_name
+                    ));
+                }
 
-        Ok(())
+                self.write(vec![WalRecord::RenameColumn {
+                    table: table_name.to_string(),
+                    from,
+                    to,
+                }])?;
+            }
+        }
+        Ok(true)
     }
 
-    fn load_rows_paginated(
-        &self,
-        table_name: &str,
-        start_row: usize,
-        max_rows: usize,
-    ) -> Result<Vec<Vec<Value>>> {
-        let table_path = self.table_path(table_name);
-        let file = File::open(table_path)?;
-        let reader = BufReader::new(file);
+    /// Fails if column `name` of `table` cannot be dropped because a key or
+    /// another column's CHECK depends on it.
+    fn check_droppable(&self, table: &TableMetadata, name: &str) -> Result<()> {
+        let refuse = |reason: String| {
+            Err(anyhow::anyhow!(
+                "Cannot drop column {}.{}: {}",
+                table.name,
+                name,
+                reason
+            ))
+        };
 
-        let mut rows = Vec::new();
-        for (i, line) in reader.lines().enumerate() {
-            if i < start_row {
-                continue;
-            }
+        if table.columns.len() == 1 {
+            return refuse("it is the only column".to_string());
+        }
+        if table.primary_key.iter().any(|col| col == name) {
+            return refuse("it is part of the PRIMARY KEY".to_string());
+        }
+        if table
+            .foreign_keys
+            .iter()
+            .any(|fk| fk.columns.iter().any(|col| col == name))
+        {
+            return refuse("it is part of a foreign key".to_string());
+        }
+        if let Some((child_name, _)) = self
+            .referencing_keys(&table.name)
+            .into_iter()
+            .find(|(_, fk)| fk.parent_columns.iter().any(|col| col == name))
+        {
+            return refuse(format!("table {} references it", child_name));
+        }
 
-            if rows.len() >= max_rows {
-                break;
+        for col in table.columns.iter().filter(|col| col.name != name) {
+            let mut used = false;
+            if let Some(check) = &col.check {
+                check.walk_exprs(&mut |expr| {
+                    used |= matches!(expr, Expr::Column { name: used_name, .. } if used_name == name)
+                });
+            }
+            if used {
+                return refuse(format!("the CHECK on column {} uses it", col.name));
             }
-
-            let line = line?;
-            let row: Vec<Value> = serde_json::from_str(&line)?;
-            rows.push(row);
         }
 
-        Ok(rows)
+        Ok(())
     }
 
-    pub fn get_rows(
-        &self,
+    /// Drops index `index_name` of `table_name`. Returns `false`, doing
+    /// nothing, if there is no such index and `if_exists` is set.
+    pub fn drop_index(
+        &mut self,
         table_name: &str,
-        columns: Vec<String>,
-        conditions: Option<Vec<Condition>>,
-        order_by: Option<OrderBy>,
-        limit: Option<usize>,
-    ) -> Result<Vec<Vec<Value>>> {
-        let table_metadata = self
-            .metadata
-            .tables
-            .get(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+        index_name: &str,
+        if_exists: bool,
+    ) -> Result<bool> {
+        let table_metadata = self.metadata.tables.get(table_name);
+        let exists = table_metadata
+            .is_some_and(|table| table.indexes.iter().any(|idx| idx.name == index_name));
+        if !exists && if_exists {
+            return Ok(false);
+        }
 
-        let mut result_rows = Vec::new();
+        let table_metadata = table_metadata.ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+        if !exists {
+            return Err(anyhow::anyhow!("Index not found"));
+        }
 
-        let use_index = if let Some(ref conditions) = conditions {
-            self.find_usable_index(table_name, conditions)
-        } else {
-            None
-        };
+        if !table_metadata.primary_key.is_empty() && index_name == primary_key_index(table_name) {
+            return Err(anyhow::anyhow!(
+                "Cannot drop index {}: it backs the PRIMARY KEY of table {}",
+                index_name,
+                table_name
+            ));
+        }
 
-        let limit_val = limit.unwrap_or(usize::MAX);
+        self.write(vec![WalRecord::DropIndex {
+            table: table_name.to_string(),
+            name: index_name.to_string(),
+        }])?;
+        Ok(true)
+    }
+}
 
-        if let Some(index_name) = use_index {
-            result_rows = self.get_rows_using_index(
-                table_name,
-                &index_name,
-                &conditions.unwrap(),
-                limit_val,
-            )?;
-        } else {
-            let mut start_row = 0;
-            while result_rows.len() < limit_val {
-                let rows = self.load_rows_paginated(table_name, start_row, self.page_size)?;
-                if rows.is_empty() {
-                    break;
-                }
+/// The keys `fk` refers to in `rows` of its parent table, leaving out any
+/// with a NULL, which nothing can reference.
+fn parent_keys<'a>(
+    parent: &TableMetadata,
+    fk: &ForeignKey,
+    rows: impl Iterator<Item = &'a Vec<Value>>,
+) -> Result<HashSet<Vec<Value>>> {
+    let mut keys = HashSet::new();
+    for row in rows {
+        let key = parent.column_values(&fk.parent_columns, row)?;
+        if !key.contains(&Value::Null) {
+            keys.insert(key);
+        }
+    }
+    Ok(keys)
+}
 
-                for row in rows {
-                    if let Some(ref conditions) = conditions {
-                        let match_all = conditions
-                            .iter()
-                            .all(|condition| condition.evaluate(&row, &table_metadata.columns));
+fn referenced_error(
+    parent: &TableMetadata,
+    child_name: &str,
+    fk: &ForeignKey,
+    key: &[Value],
+) -> anyhow::Error {
+    let values: Vec<String> = key.iter().map(|value| value.to_string()).collect();
+
+    anyhow::anyhow!(
+        "Constraint violation on table {}: key ({}) = ({}) is still referenced from table {}",
+        parent.name,
+        fk.parent_columns.join(", "),
+        values.join(", "),
+        child_name
+    )
+}
 
-                        if !match_all {
-                            continue;
-                        }
-                    }
+fn duplicate_key_error(table: &TableMetadata, index: &Index, key: &[Value]) -> anyhow::Error {
+    let constraint = if index.name == primary_key_index(&table.name) {
+        "PRIMARY KEY".to_string()
+    } else {
+        format!("unique index {}", index.name)
+    };
+    let values: Vec<String> = key.iter().map(|value| value.to_string()).collect();
+
+    anyhow::anyhow!(
+        "Constraint violation on table {}: duplicate key ({}) = ({}) violates {}",
+        table.name,
+        index.columns.join(", "),
+        values.join(", "),
+        constraint
+    )
+}
 
-                    result_rows.push(row);
+/// Compares two ORDER BY keys term by term.
+fn compare_sort_keys(order_by: &[OrderBy], a: &[Value], b: &[Value]) -> Ordering {
+    order_by
+        .iter()
+        .zip(a.iter().zip(b))
+        .map(|(order_by, (a, b))| order_by.compare(a, b))
+        .find(|cmp| cmp.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
 
-                    if result_rows.len() >= limit_val {
-                        break;
-                    }
-                }
+/// Drops the rows that fail `condition`, if there is one.
+fn filter_rows<'a>(rows: Rows<'a>, condition: Option<Condition>, columns: Vec<Column>) -> Rows<'a> {
+    let Some(condition) = condition else {
+        return rows;
+    };
+
+    Box::new(rows.filter_map(move |row| {
+        let keep = row
+            .as_ref()
+            .map_or(Ok(true), |row| condition.evaluate(row, &columns));
+        match keep {
+            Ok(true) => Some(row),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }))
+}
 
-                start_row += self.page_size;
-            }
+/// Checks that the DEFAULT and CHECK of a column being created make sense:
+/// a default cannot depend on a row and must fit the column, and a check may
+/// only refer to columns of its own table.
+fn check_column_constraints(table_name: &str, col: &Column, columns: &[Column]) -> Result<()> {
+    if let Some(default) = &col.default {
+        let mut refers_to_row = false;
+        default.walk(&mut |expr| {
+            refers_to_row |= matches!(expr, Expr::Column { .. } | Expr::Aggregate { .. })
+        });
+        if refers_to_row {
+            return Err(anyhow::anyhow!(
+                "DEFAULT of column {}.{} must be a constant",
+                table_name,
+                col.name
+            ));
         }
+        default
+            .evaluate(&[], &[])
+            .and_then(|value| coerce_value(value, &col.data_type))
+            .map_err(|err| {
+                anyhow::anyhow!("DEFAULT of column {}.{}: {}", table_name, col.name, err)
+            })?;
+    }
 
-        if let Some(order_by) = order_by {
-            let column_idx = table_metadata
-                .columns
-                .iter()
-                .position(|col| col.name == order_by.column)
-                .ok_or_else(|| anyhow::anyhow!("Order by column not found"))?;
+    if let Some(check) = &col.check {
+        let mut result = Ok(());
+        check.walk_exprs(&mut |expr| match expr {
+            Expr::Column { table, name } if result.is_ok() => {
+                result = resolve_column(columns, table.as_deref(), name).map(|_| ());
+            }
+            Expr::Aggregate { .. } => {
+                result = Err(anyhow::anyhow!("aggregates are not allowed"));
+            }
+            _ => {}
+        });
+        result.map_err(|err| {
+            anyhow::anyhow!("CHECK on column {}.{}: {}", table_name, col.name, err)
+        })?;
+    }
 
-            result_rows.sort_by(|a, b| {
-                let cmp = a[column_idx].cmp(&b[column_idx]);
-                if order_by.direction == OrderDirection::Descending {
-                    cmp.reverse()
-                } else {
-                    cmp
-                }
-            });
+    Ok(())
+}
+
+/// Fails if `row` breaks a NOT NULL or CHECK constraint of `table`, or has
+/// a key too long for one of its indexes. A CHECK is skipped while its
+/// column is NULL, as its outcome is then unknown.
+fn check_constraints(table: &TableMetadata, row: &[Value]) -> Result<()> {
+    for index in &table.indexes {
+        check_index_key(&index.name, &table.index_key(index, row)?)?;
+    }
+
+    for (col, value) in table.columns.iter().zip(row) {
+        if col.not_null && *value == Value::Null {
+            return Err(anyhow::anyhow!(
+                "Constraint violation on table {}: column {} violates NOT NULL",
+                table.name,
+                col.name
+            ));
         }
 
-        if !columns.is_empty() && columns[0] != "*" {
-            let mut projected_rows = Vec::new();
+        // A CHECK over a NULL is unknown rather than false, and passes.
+        if let Some(check) = &col.check
+            && *value != Value::Null
+            && !uses_null(check, row, &table.columns)
+            && !check.evaluate(row, &table.columns)?
+        {
+            return Err(anyhow::anyhow!(
+                "Constraint violation on table {}: column {} violates CHECK ({})",
+                table.name,
+                col.name,
+                check
+            ));
+        }
+    }
 
-            let mut col_indices = HashMap::new();
-            for (i, col) in table_metadata.columns.iter().enumerate() {
-                col_indices.insert(&col.name, i);
-            }
+    Ok(())
+}
 
-            for row in result_rows {
-                let mut projected_row = Vec::new();
+/// Whether `condition` refers to a column that is NULL in `row`.
+fn uses_null(condition: &Condition, row: &[Value], columns: &[Column]) -> bool {
+    let mut found = false;
+    condition.walk_exprs(&mut |expr| {
+        if let Expr::Column { table, name } = expr
+            && let Ok(idx) = resolve_column(columns, table.as_deref(), name)
+        {
+            found |= matches!(row.get(idx), None | Some(Value::Null));
+        }
+    });
+    found
+}
 
-                for col_name in &columns {
-                    if let Some(&idx) = col_indices.get(col_name) {
-                        projected_row.push(row[idx].clone());
-                    } else {
-                        return Err(anyhow::anyhow!("Column {} not found", col_name));
-                    }
-                }
+fn check_index_key(index_name: &str, key: &[Value]) -> Result<()> {
+    btree::check_key(key)
+        .map_err(|e| anyhow::anyhow!("Cannot add row to index {}: {}", index_name, e))
+}
 
-                projected_rows.push(projected_row);
-            }
+/// 2^63, the first REAL above every INTEGER.
+const INTEGER_LIMIT: f64 = 9_223_372_036_854_775_808.0;
 
-            return Ok(projected_rows);
-        }
+/// The INTEGER that `r` equals, if there is one.
+pub fn integral_real(r: f64) -> Option<i64> {
+    (r.fract() == 0.0 && (-INTEGER_LIMIT..INTEGER_LIMIT).contains(&r)).then_some(r as i64)
+}
 
-        Ok(result_rows)
+/// Compares an INTEGER with a REAL exactly. Converting the integer to a
+/// REAL instead would round integers beyond 2^53.
+fn compare_integer_real(i: i64, r: f64) -> Option<Ordering> {
+    if r.is_nan() {
+        return None;
+    }
+    if r >= INTEGER_LIMIT {
+        return Some(Ordering::Less);
+    }
+    if r < -INTEGER_LIMIT {
+        return Some(Ordering::Greater);
     }
-    
-    fn find_usable_index(&self, table_name: &str, conditions: &[Condition]) -> Option<String> {
-        let table_metadata = self.metadata.tables.get(table_name)?;
 
-        let mut condition_columns = Vec::new();
-        for condition in conditions {
-            if let Condition::Equal { column, .. } = condition {
-                condition_columns.push(column.clone());
-            }
+    let whole = r.trunc();
+    Some(i.cmp(&(whole as i64)).then(0.0.partial_cmp(&(r - whole))?))
+}
+
+/// Converts `value` for storage in a column of `data_type`. An INTEGER is
+/// promoted to REAL; any other mismatch is an error.
+fn coerce_value(value: Value, data_type: &DataType) -> Result<Value> {
+    match (value, data_type) {
+        (Value::Integer(i), DataType::Real) => Ok(Value::Real(i as f64)),
+        (value, data_type) => {
+            check_value_type(&value, data_type)?;
+            Ok(value)
         }
+    }
+}
 
-        for index in &table_metadata.indexes {
-            let mut matches = true;
-            for index_col in &index.columns {
-                if !condition_columns.contains(index_col) {
-                    matches = false;
-                    break;
-                }
-            }
+fn check_value_type(value: &Value, data_type: &DataType) -> Result<()> {
+    match (value, data_type) {
+        (Value::Null, _) => Ok(()),
+        (Value::Integer(_), DataType::Integer) => Ok(()),
+        (Value::Text(_), DataType::Text) => Ok(()),
+        (Value::Boolean(_), DataType::Boolean) => Ok(()),
+        (Value::Real(_), DataType::Real) => Ok(()),
+        (Value::Date(_), DataType::Date) => Ok(()),
+        (Value::Timestamp(_), DataType::Timestamp) => Ok(()),
+        (Value::Interval(_), DataType::Interval) => Ok(()),
+        (Value::Blob(_), DataType::Blob) => Ok(()),
+        (Value::Uuid(_), DataType::Uuid) => Ok(()),
+        (v, dt) => Err(anyhow::anyhow!(
+            "Type mismatch: {:?} is not compatible with {:?}",
+            v,
+            dt
+        )),
+    }
+}
 
-            if matches && !index.columns.is_empty() {
-                return Some(index.name.clone());
+/// Combines two bounds on the same side of a range, keeping the stricter
+/// one. `stricter` is the ordering a value must have relative to the other
+/// to be the stricter bound: `Greater` for lower bounds, `Less` for upper.
+fn tighter_bound(a: Bound<Value>, b: Bound<Value>, stricter: Ordering) -> Bound<Value> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            match y.cmp(x) {
+                ordering if ordering == stricter => b,
+                Ordering::Equal if matches!(b, Bound::Excluded(_)) => b,
+                _ => a,
             }
         }
-
-        None
     }
+}
 
-    fn get_rows_using_index(
-        &self,
-        table_name: &str,
-        index_name: &str,
-        conditions: &[Condition],
-        limit: usize,
-    ) -> Result<Vec<Vec<Value>>> {
-        let table_metadata = self
-            .metadata
-            .tables
-            .get(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parser::{Parser, Statement};
 
-        let index = table_metadata
-            .indexes
-            .iter()
-            .find(|idx| idx.name == index_name)
-            .ok_or_else(|| anyhow::anyhow!("Index not found"))?;
+    /// An empty directory for one test's database, removed when dropped.
+    pub(crate) struct TempDir(PathBuf);
 
-        let index_path = Path::new(&index.file_path);
-        let index_file = File::open(index_path)?;
-        let reader = BufReader::new(index_file);
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("scythe-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
 
-        let data_path = self.table_path(table_name);
-        let data_file = File::open(data_path)?;
-        let mut data_reader = BufReader::new(data_file);
-
-        let mut key_values = Vec::new();
-        for col_name in &index.columns {
-            for condition in conditions {
-                if let Condition::Equal { column, value } = condition {
-                    if column == col_name {
-                        key_values.push(value.clone());
-                        break;
-                    }
-                }
-            }
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
         }
+    }
 
-        let key_pattern = serde_json::to_string(&key_values)?;
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
 
-        let mut result_rows = Vec::new();
+    pub(crate) fn open(dir: &Path) -> Storage {
+        Storage::new(dir.to_str().unwrap()).unwrap()
+    }
 
-        for line in reader.lines() {
-            if result_rows.len() >= limit {
-                break;
+    /// Runs one statement and returns the rows of a SELECT, or none.
+    pub(crate) fn execute(storage: &mut Storage, sql: &str) -> Result<Vec<Vec<Value>>> {
+        match Parser::new(sql.to_string())?.parse()? {
+            Statement::CreateTable {
+                name,
+                columns,
+                primary_key,
+                foreign_keys,
+                if_not_exists,
+            } => {
+                storage.create_table(&name, columns, primary_key, foreign_keys, if_not_exists)?;
             }
-
-            let line = line?;
-            let parts: Vec<&str> = line.split('\t').collect();
-            if parts.len() != 2 {
-                continue;
+            Statement::CreateIndex {
+                name,
+                table,
+                columns,
+                if_not_exists,
+            } => {
+                storage.create_index(&table, &name, columns, if_not_exists)?;
+            }
+            Statement::Insert {
+                table,
+                columns,
+                values,
+            } => storage.insert_row(&table, columns, values)?,
+            Statement::Update {
+                table,
+                assignments,
+                conditions,
+            } => {
+                storage.update_rows(&table, assignments, conditions)?;
+            }
+            Statement::Delete { table, conditions } => {
+                storage.delete_rows(&table, conditions)?;
+            }
+            Statement::AlterTable {
+                table,
+                if_exists,
+                action,
+            } => {
+                storage.alter_table(&table, if_exists, action)?;
+            }
+            Statement::Truncate { table, if_exists } => {
+                storage.truncate_table(&table, if_exists)?;
             }
+            Statement::DropTable { name, if_exists } => {
+                storage.drop_table(&name, if_exists)?;
+            }
+            Statement::DropIndex {
+                name,
+                table,
+                if_exists,
+            } => {
+                storage.drop_index(&table, &name, if_exists)?;
+            }
+            Statement::Begin => storage.begin()?,
+            Statement::Commit => storage.commit()?,
+            Statement::Rollback => storage.rollback()?,
+            Statement::Select(select) => return storage.get_rows(*select)?.collect(),
+        }
+        Ok(Vec::new())
+    }
 
-            let key = parts[0];
-            if key == key_pattern {
-                let position: u64 = parts[1].parse()?;
+    pub(crate) fn query(storage: &mut Storage, sql: &str) -> Vec<Vec<Value>> {
+        execute(storage, sql).unwrap()
+    }
 
-                data_reader.seek(SeekFrom::Start(position))?;
+    /// The integers of a single-column result.
+    pub(crate) fn integers(rows: Vec<Vec<Value>>) -> Vec<i64> {
+        rows.into_iter()
+            .map(|row| match row[..] {
+                [Value::Integer(i)] => i,
+                _ => panic!("expected one INTEGER, got {:?}", row),
+            })
+            .collect()
+    }
 
-                let mut line = String::new();
-                data_reader.read_line(&mut line)?;
+    #[test]
+    fn committed_writes_are_visible_and_durable() {
+        let dir = TempDir::new("tx-commit");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        query(&mut storage, "INSERT INTO t VALUES (1)");
+        query(&mut storage, "INSERT INTO t VALUES (2)");
+
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "INSERT INTO t VALUES (3)");
+        query(&mut storage, "DELETE FROM t WHERE a = 1");
+        query(&mut storage, "UPDATE t SET a = 20 WHERE a = 2");
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![3, 20]
+        );
+        query(&mut storage, "COMMIT");
+
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![3, 20]
+        );
+        drop(storage);
+
+        let mut storage = open(&dir);
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![3, 20]
+        );
+    }
 
-                let row: Vec<Value> = serde_json::from_str(&line)?;
+    #[test]
+    fn rolled_back_writes_are_discarded() {
+        let dir = TempDir::new("tx-rollback");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        query(&mut storage, "INSERT INTO t VALUES (1)");
+
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "CREATE TABLE u (b INTEGER)");
+        query(&mut storage, "INSERT INTO u VALUES (5)");
+        query(&mut storage, "INSERT INTO t VALUES (2)");
+        query(&mut storage, "DELETE FROM t WHERE a = 1");
+        assert_eq!(integers(query(&mut storage, "SELECT a FROM t")), vec![2]);
+        assert_eq!(integers(query(&mut storage, "SELECT b FROM u")), vec![5]);
+        query(&mut storage, "ROLLBACK");
+
+        assert_eq!(integers(query(&mut storage, "SELECT a FROM t")), vec![1]);
+        assert!(execute(&mut storage, "SELECT b FROM u").is_err());
+
+        // Row ids handed out inside the transaction are free again.
+        query(&mut storage, "INSERT INTO t VALUES (3)");
+        assert_eq!(integers(query(&mut storage, "SELECT a FROM t")), vec![1, 3]);
+    }
 
-                let match_all = conditions
-                    .iter()
-                    .all(|condition| condition.evaluate(&row, &table_metadata.columns));
+    #[test]
+    fn an_open_transaction_is_lost_on_restart() {
+        let dir = TempDir::new("tx-crash");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        query(&mut storage, "INSERT INTO t VALUES (1)");
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "INSERT INTO t VALUES (2)");
+        drop(storage);
+
+        let mut storage = open(&dir);
+        assert_eq!(integers(query(&mut storage, "SELECT a FROM t")), vec![1]);
+    }
 
-                if match_all {
-                    result_rows.push(row);
-                }
-            }
+    fn with_parent(dir: &Path, on_delete: &str) -> Storage {
+        let mut storage = open(dir);
+        query(&mut storage, "CREATE TABLE p (id INTEGER PRIMARY KEY)");
+        query(
+            &mut storage,
+            &format!(
+                "CREATE TABLE c (id INTEGER PRIMARY KEY, pid INTEGER REFERENCES p (id) ON DELETE {})",
+                on_delete
+            ),
+        );
+        for id in 1..=2 {
+            query(&mut storage, &format!("INSERT INTO p VALUES ({})", id));
         }
+        for (id, pid) in [(10, 1), (11, 1), (20, 2)] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO c VALUES ({}, {})", id, pid),
+            );
+        }
+        storage
+    }
 
-        Ok(result_rows)
+    #[test]
+    fn on_delete_cascade_removes_child_rows() {
+        let dir = TempDir::new("fk-cascade");
+        let mut storage = with_parent(&dir, "CASCADE");
+        query(
+            &mut storage,
+            "CREATE TABLE g (id INTEGER, cid INTEGER REFERENCES c (id) ON DELETE CASCADE)",
+        );
+        query(&mut storage, "INSERT INTO g VALUES (100, 10)");
+        query(&mut storage, "INSERT INTO g VALUES (200, 20)");
+
+        query(&mut storage, "DELETE FROM p WHERE id = 1");
+        assert_eq!(integers(query(&mut storage, "SELECT id FROM p")), vec![2]);
+        assert_eq!(integers(query(&mut storage, "SELECT id FROM c")), vec![20]);
+        assert_eq!(integers(query(&mut storage, "SELECT id FROM g")), vec![200]);
     }
 
-    pub fn create_index(
-        &mut self,
-        table_name: &str,
-        index_name: &str,
-        columns: Vec<String>,
-    ) -> Result<()> {
-        let index_path = self.index_path(table_name, index_name);
-        let index_path_str = index_path.to_string_lossy().to_string();
-        let data_path = self.table_path(table_name);
+    #[test]
+    fn on_delete_set_null_clears_the_reference() {
+        let dir = TempDir::new("fk-set-null");
+        let mut storage = with_parent(&dir, "SET NULL");
+
+        query(&mut storage, "DELETE FROM p WHERE id = 1");
+        assert_eq!(
+            query(&mut storage, "SELECT id, pid FROM c ORDER BY id"),
+            vec![
+                vec![Value::Integer(10), Value::Null],
+                vec![Value::Integer(11), Value::Null],
+                vec![Value::Integer(20), Value::Integer(2)],
+            ]
+        );
+    }
 
-        if !self.metadata.tables.contains_key(table_name) {
-            return Err(anyhow::anyhow!("Table does not exist"));
+    #[test]
+    fn on_delete_restrict_refuses_while_referenced() {
+        let dir = TempDir::new("fk-restrict");
+        let mut storage = with_parent(&dir, "RESTRICT");
+
+        assert!(execute(&mut storage, "DELETE FROM p WHERE id = 1").is_err());
+        assert!(execute(&mut storage, "INSERT INTO c VALUES (30, 3)").is_err());
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM p")),
+            vec![1, 2]
+        );
+
+        query(&mut storage, "DELETE FROM c WHERE pid = 1");
+        query(&mut storage, "DELETE FROM p WHERE id = 1");
+        assert_eq!(integers(query(&mut storage, "SELECT id FROM p")), vec![2]);
+    }
+
+    #[test]
+    fn comparisons_with_null_never_hold() {
+        let dir = TempDir::new("null-compare");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, qty INTEGER, low INTEGER)",
+        );
+        query(&mut storage, "INSERT INTO t VALUES (1, 5, 3)");
+        query(&mut storage, "INSERT INTO t VALUES (2, NULL, 3)");
+        query(&mut storage, "INSERT INTO t VALUES (3, 5, NULL)");
+        query(&mut storage, "INSERT INTO t VALUES (4, 2, 3)");
+
+        for (condition, expected) in [
+            ("qty > low", vec![1]),
+            ("qty >= low", vec![1]),
+            ("low < qty", vec![1]),
+            ("low <= qty", vec![1]),
+            ("qty < 10", vec![1, 3, 4]),
+            ("qty BETWEEN low AND 10", vec![1]),
+            ("qty BETWEEN 0 AND low", vec![4]),
+            ("qty + 1 > low", vec![1]),
+        ] {
+            let sql = format!("SELECT id FROM t WHERE {}", condition);
+            assert_eq!(
+                integers(query(&mut storage, &sql)),
+                expected,
+                "{}",
+                condition
+            );
         }
+    }
 
-        let table_metadata = self
-            .metadata
-            .tables
-            .get_mut(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+    #[test]
+    fn index_scans_match_full_scans_around_null_keys() {
+        let dir = TempDir::new("index-null");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, g INTEGER, a INTEGER)",
+        );
+        for (id, g, a) in [
+            (1, "1", "NULL"),
+            (2, "1", "5"),
+            (3, "NULL", "7"),
+            (4, "2", "NULL"),
+        ] {
+            let sql = format!("INSERT INTO t VALUES ({}, {}, {})", id, g, a);
+            query(&mut storage, &sql);
+        }
+        for id in 5..40 {
+            let sql = format!("INSERT INTO t VALUES ({}, {}, {})", id, id % 3, id);
+            query(&mut storage, &sql);
+        }
 
-        if table_metadata
-            .indexes
+        let predicates = [
+            "a < 10",
+            "a <= 7",
+            "a > 30",
+            "a BETWEEN 0 AND 6",
+            "a = NULL",
+            "g = 1 AND a < 20",
+            "g = NULL AND a > 0",
+            "g = 2 AND a >= 0",
+        ];
+        let select = |storage: &mut Storage, predicate: &str| {
+            let sql = format!("SELECT id FROM t WHERE {} ORDER BY id", predicate);
+            integers(query(storage, &sql))
+        };
+        let full: Vec<_> = predicates
             .iter()
-            .any(|index| index.name == index_name)
-        {
-            return Err(anyhow::anyhow!("Index already exists"));
+            .map(|predicate| select(&mut storage, predicate))
+            .collect();
+
+        query(&mut storage, "CREATE INDEX ta ON t (a)");
+        query(&mut storage, "CREATE INDEX tga ON t (g, a)");
+        for (predicate, expected) in predicates.iter().zip(full) {
+            assert!(
+                storage
+                    .find_usable_index("t", &where_clause(predicate))
+                    .is_some()
+            );
+            assert_eq!(select(&mut storage, predicate), expected, "{}", predicate);
         }
 
-        for col_name in &columns {
-            if !table_metadata
-                .columns
-                .iter()
-                .any(|col| &col.name == col_name)
-            {
-                return Err(anyhow::anyhow!("Column {} not found", col_name));
-            }
+        // The index itself steps over NULL keys, before any re-filtering.
+        let scan = storage
+            .find_usable_index("t", &where_clause("a < 10"))
+            .unwrap();
+        let rows: Vec<_> = storage
+            .index_rows("t", scan)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(rows.len(), 7);
+        assert!(rows.iter().all(|row| !matches!(row[2], Value::Null)));
+    }
+
+    fn where_clause(predicate: &str) -> Condition {
+        let sql = format!("DELETE FROM t WHERE {}", predicate);
+        match Parser::new(sql).unwrap().parse().unwrap() {
+            Statement::Delete {
+                conditions: Some(condition),
+                ..
+            } => condition,
+            statement => panic!("expected a DELETE, got {:?}", statement),
         }
+    }
 
-        let mut index_file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&index_path)?;
+    #[test]
+    fn text_casts_to_real_only_when_finite() {
+        let dir = TempDir::new("cast-real");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (s TEXT)");
+        query(&mut storage, "INSERT INTO t VALUES (' 1.5 ')");
+        assert_eq!(
+            query(&mut storage, "SELECT CAST(s AS REAL) FROM t"),
+            vec![vec![Value::Real(1.5)]]
+        );
+
+        for text in ["NaN", "inf", "-infinity", "1e999"] {
+            let sql = format!("SELECT CAST('{}' AS REAL) FROM t", text);
+            let err = execute(&mut storage, &sql).unwrap_err();
+            assert!(
+                err.to_string().starts_with("Cannot cast"),
+                "{}: {}",
+                text,
+                err
+            );
+        }
+    }
 
-        let index = Index {
-            name: index_name.to_string(),
-            columns: columns.clone(),
-            file_path: index_path_str,
+    #[test]
+    fn integers_are_promoted_in_real_columns_and_comparisons() {
+        let dir = TempDir::new("numeric-promotion");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE p (id INTEGER, price REAL)");
+        for (id, price) in [(1, "10"), (2, "10.5"), (3, "9.99")] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO p VALUES ({}, {})", id, price),
+            );
+        }
+        let row = query(&mut storage, "SELECT price FROM p WHERE id = 1");
+        assert!(matches!(row[0][0], Value::Real(r) if r == 10.0));
+
+        // The promotion only goes one way.
+        let err = execute(&mut storage, "INSERT INTO p VALUES (2.0, 1)").unwrap_err();
+        assert!(err.to_string().starts_with("Type mismatch"), "{}", err);
+
+        let ids = |storage: &mut Storage| {
+            let mut found = Vec::new();
+            for predicate in ["price > 10", "price = 10", "price BETWEEN 9 AND 10"] {
+                let sql = format!("SELECT id FROM p WHERE {} ORDER BY id", predicate);
+                found.push(integers(query(storage, &sql)));
+            }
+            found
         };
+        let expected = vec![vec![2], vec![1], vec![1, 3]];
+        assert_eq!(ids(&mut storage), expected);
+        query(&mut storage, "CREATE INDEX p_price ON p (price)");
+        assert_eq!(ids(&mut storage), expected);
+
+        // An INTEGER joins the REAL it equals.
+        query(&mut storage, "CREATE TABLE q (amount INTEGER)");
+        query(&mut storage, "INSERT INTO q VALUES (10)");
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT p.id FROM q JOIN p ON q.amount = p.price"
+            )),
+            vec![1]
+        );
+    }
 
-        let col_indices = {
-            let table_metadata = self.metadata.tables.get_mut(table_name).unwrap();
-            table_metadata.indexes.push(index);
+    #[test]
+    fn integers_and_reals_compare_exactly() {
+        let dir = TempDir::new("numeric-exact");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (i INTEGER)");
+        // 2^53 + 1 has no REAL of its own; converting it would make it equal
+        // to 2^53.
+        query(&mut storage, "INSERT INTO t VALUES (9007199254740993)");
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT i FROM t WHERE i > CAST(9007199254740992 AS REAL)"
+            )
+            .len(),
+            1
+        );
+        assert!(
+            query(
+                &mut storage,
+                "SELECT i FROM t WHERE i = CAST(9007199254740992 AS REAL)"
+            )
+            .is_empty()
+        );
+    }
 
-            columns
-                .iter()
-                .map(|col_name| {
-                    table_metadata
-                        .columns
-                        .iter()
-                        .position(|col| &col.name == col_name)
-                        .ok_or_else(|| anyhow::anyhow!("Column not found"))
-                })
-                .collect::<Result<Vec<_>, _>>()?
+    #[test]
+    fn casts_convert_between_types_or_say_why_not() {
+        let dir = TempDir::new("cast");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (r REAL, b BOOLEAN, s TEXT)");
+        query(&mut storage, "INSERT INTO t VALUES (10.5, TRUE, ' 42 ')");
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT CAST(r AS INTEGER), CAST(b AS INTEGER), CAST(s AS INTEGER), \
+                 CAST(r AS TEXT), CAST('yes' AS BOOLEAN), CAST(NULL AS DATE) FROM t"
+            ),
+            vec![vec![
+                Value::Integer(11),
+                Value::Integer(1),
+                Value::Integer(42),
+                Value::Text("10.5".to_string()),
+                Value::Boolean(true),
+                Value::Null,
+            ]]
+        );
+
+        for (sql, message) in [
+            (
+                "SELECT CAST('abc' AS INTEGER) FROM t",
+                "Cannot cast 'abc' to INTEGER",
+            ),
+            (
+                "SELECT CAST(CAST('1e300' AS REAL) AS INTEGER) FROM t",
+                "out of range",
+            ),
+            ("SELECT CAST(X'01' AS UUID) FROM t", "a UUID is 16 bytes"),
+            ("SELECT CAST(b AS DATE) FROM t", "Cannot cast true to DATE"),
+        ] {
+            let err = execute(&mut storage, sql).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", sql, err);
+        }
+    }
+
+    fn index_names(storage: &Storage, table: &str) -> Vec<String> {
+        let table = storage.metadata.table(table).unwrap();
+        table.indexes.iter().map(|idx| idx.name.clone()).collect()
+    }
+
+    #[test]
+    fn dropping_a_column_drops_every_index_on_it() {
+        let dir = TempDir::new("drop-column");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, a INTEGER, b TEXT, c INTEGER)",
+        );
+        query(&mut storage, "CREATE INDEX tab ON t (a, b)");
+        query(&mut storage, "CREATE INDEX tb ON t (b)");
+        query(&mut storage, "CREATE INDEX tc ON t (c)");
+
+        // Enough rows for the rewrite to take several chunks.
+        query(&mut storage, "BEGIN");
+        for id in 0..1500 {
+            let sql = format!(
+                "INSERT INTO t VALUES ({}, {}, 'row {}', {})",
+                id,
+                id % 10,
+                id,
+                id * 2
+            );
+            query(&mut storage, &sql);
+        }
+        query(&mut storage, "COMMIT");
+
+        query(&mut storage, "ALTER TABLE t DROP COLUMN b");
+        assert_eq!(index_names(&storage, "t"), vec!["tc"]);
+        assert!(!dir.join("t_tab.idx").exists());
+        assert!(!dir.join("t_tb.idx").exists());
+
+        drop(storage);
+        let mut storage = open(&dir);
+        assert_eq!(
+            query(&mut storage, "SELECT * FROM t WHERE id = 1234"),
+            vec![vec![
+                Value::Integer(1234),
+                Value::Integer(4),
+                Value::Integer(2468)
+            ]]
+        );
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT id FROM t WHERE c BETWEEN 10 AND 14"
+            )),
+            vec![5, 6, 7]
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT COUNT(*) FROM t WHERE a = 3")),
+            vec![150]
+        );
+    }
+
+    #[test]
+    fn dropping_a_column_inside_a_transaction() {
+        let dir = TempDir::new("drop-column-tx");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, a INTEGER, c INTEGER)",
+        );
+        query(&mut storage, "CREATE INDEX tc ON t (c)");
+        for id in 1..=3 {
+            let sql = format!("INSERT INTO t VALUES ({}, {}, {})", id, id * 10, id * 100);
+            query(&mut storage, &sql);
+        }
+
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "ALTER TABLE t DROP COLUMN a");
+        query(&mut storage, "ROLLBACK");
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![10, 20, 30]
+        );
+
+        // Rows staged around the drop keep their ids once it is applied.
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "INSERT INTO t VALUES (4, 40, 400)");
+        query(&mut storage, "DELETE FROM t WHERE id = 1");
+        query(&mut storage, "ALTER TABLE t DROP COLUMN a");
+        query(&mut storage, "INSERT INTO t VALUES (5, 500)");
+        query(&mut storage, "DELETE FROM t WHERE id = 2");
+        query(&mut storage, "UPDATE t SET c = 301 WHERE id = 3");
+        query(&mut storage, "COMMIT");
+
+        let expected = vec![vec![3, 301], vec![4, 400], vec![5, 500]];
+        let rows = |storage: &mut Storage, sql: &str| -> Vec<Vec<i64>> {
+            query(storage, sql).into_iter().map(integers_of).collect()
         };
+        assert_eq!(rows(&mut storage, "SELECT * FROM t ORDER BY id"), expected);
+        drop(storage);
+        let mut storage = open(&dir);
+        assert_eq!(
+            rows(&mut storage, "SELECT * FROM t WHERE c > 0 ORDER BY id"),
+            expected
+        );
+    }
 
-        self.save_metadata()?;
+    fn integers_of(row: Vec<Value>) -> Vec<i64> {
+        integers(row.into_iter().map(|value| vec![value]).collect())
+    }
 
-        let data_file = File::open(data_path)?;
-        let reader = BufReader::new(data_file);
+    #[test]
+    fn truncate_if_exists_skips_a_missing_table() {
+        let dir = TempDir::new("truncate-if-exists");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        query(&mut storage, "INSERT INTO t VALUES (1)");
 
-        let mut position: u64 = 0;
-        for line in reader.lines() {
-            let line_position = position;
-            let line = line?;
-            position += line.len() as u64 + 1;
+        assert!(!storage.truncate_table("missing", true).unwrap());
+        assert!(execute(&mut storage, "TRUNCATE missing").is_err());
+        query(&mut storage, "TRUNCATE TABLE IF EXISTS missing");
 
-            let row: Vec<Value> = serde_json::from_str(&line)?;
+        assert!(storage.truncate_table("t", true).unwrap());
+        assert!(query(&mut storage, "SELECT a FROM t").is_empty());
+    }
 
-            let mut key_values = Vec::new();
-            for &idx in &col_indices {
-                if idx < row.len() {
-                    key_values.push(row[idx].clone());
-                } else {
-                    key_values.push(Value::Null);
-                }
-            }
+    fn expressions_table(dir: &Path) -> Storage {
+        let mut storage = open(dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, a INTEGER, b INTEGER, r REAL)",
+        );
+        query(&mut storage, "INSERT INTO t VALUES (1, 7, 2, 1.5)");
+        query(&mut storage, "INSERT INTO t VALUES (2, 3, 3, NULL)");
+        query(&mut storage, "INSERT INTO t VALUES (3, 1, 5, 2.0)");
+        storage
+    }
 
-            let key = serde_json::to_string(&key_values)?;
+    #[test]
+    fn select_computes_expressions_per_row() {
+        let dir = TempDir::new("expr-select");
+        let mut storage = expressions_table(&dir);
+        assert_eq!(
+            query(
+                &mut storage,
+                "SELECT a + b * 2, a / b, a % b, -a, a * r FROM t"
+            ),
+            vec![
+                vec![
+                    Value::Integer(11),
+                    Value::Integer(3),
+                    Value::Integer(1),
+                    Value::Integer(-7),
+                    Value::Real(10.5),
+                ],
+                vec![
+                    Value::Integer(9),
+                    Value::Integer(1),
+                    Value::Integer(0),
+                    Value::Integer(-3),
+                    Value::Null,
+                ],
+                vec![
+                    Value::Integer(11),
+                    Value::Integer(0),
+                    Value::Integer(1),
+                    Value::Integer(-1),
+                    Value::Real(2.0),
+                ],
+            ]
+        );
+
+        let err = execute(&mut storage, "SELECT a / (b - b) FROM t").unwrap_err();
+        assert_eq!(err.to_string(), "Division by zero");
+        let err = execute(&mut storage, "SELECT a + 9223372036854775807 FROM t").unwrap_err();
+        assert_eq!(err.to_string(), "Integer overflow");
+    }
 
-            writeln!(index_file, "{}\t{}", key, line_position)?;
+    #[test]
+    fn where_compares_columns_with_each_other() {
+        let dir = TempDir::new("expr-where");
+        let mut storage = expressions_table(&dir);
+        for (condition, expected) in [
+            ("a > b", vec![1]),
+            ("a = b", vec![2]),
+            ("a <> b", vec![1, 3]),
+            ("a * 2 >= b + 3", vec![1, 2]),
+            ("b BETWEEN a - 1 AND a + 5", vec![2, 3]),
+            ("r * 2 = a + b - 2", vec![3]),
+        ] {
+            let sql = format!("SELECT id FROM t WHERE {}", condition);
+            assert_eq!(
+                integers(query(&mut storage, &sql)),
+                expected,
+                "{}",
+                condition
+            );
         }
+    }
 
-        Ok(())
+    #[test]
+    fn set_expressions_see_the_row_before_the_update() {
+        let dir = TempDir::new("expr-set");
+        let mut storage = expressions_table(&dir);
+        query(&mut storage, "UPDATE t SET a = a + b, b = a WHERE id <> 2");
+        let rows = query(&mut storage, "SELECT id, a, b FROM t ORDER BY id");
+        assert_eq!(
+            rows.into_iter().map(integers_of).collect::<Vec<_>>(),
+            vec![vec![1, 9, 7], vec![2, 3, 3], vec![3, 6, 1]]
+        );
     }
 
-    pub fn drop_table(&mut self, name: &str) -> Result<()> {
-        if !self.metadata.tables.contains_key(name) {
-            return Err(anyhow::anyhow!("Table does not exist"));
+    #[test]
+    fn limit_applies_to_the_sorted_rows() {
+        let dir = TempDir::new("order-limit");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        for a in [5, 3, 9, 1, 7, 3] {
+            query(&mut storage, &format!("INSERT INTO t VALUES ({})", a));
         }
 
-        let table_path = self.table_path(name);
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t ORDER BY a LIMIT 3")),
+            vec![1, 3, 3]
+        );
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT a FROM t ORDER BY a DESC LIMIT 2"
+            )),
+            vec![9, 7]
+        );
+        assert_eq!(
+            integers(query(
+                &mut storage,
+                "SELECT a FROM t WHERE a > 2 ORDER BY a LIMIT 10"
+            )),
+            vec![3, 3, 5, 7, 9]
+        );
+        assert!(query(&mut storage, "SELECT a FROM t ORDER BY a LIMIT 0").is_empty());
+        // Without ORDER BY, LIMIT takes rows in table order.
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t LIMIT 2")),
+            vec![5, 3]
+        );
+    }
+
+    #[test]
+    fn order_by_several_keys_with_null_placement_and_offset() {
+        let dir = TempDir::new("order-keys");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER, g TEXT, v INTEGER)",
+        );
+        for (id, g, v) in [
+            (1, "'b'", "5"),
+            (2, "'a'", "NULL"),
+            (3, "'b'", "1"),
+            (4, "'a'", "7"),
+            (5, "NULL", "3"),
+            (6, "'b'", "5"),
+        ] {
+            query(
+                &mut storage,
+                &format!("INSERT INTO t VALUES ({}, {}, {})", id, g, v),
+            );
+        }
 
-        if table_path.exists() {
-            fs::remove_file(table_path)?;
+        for (order, expected) in [
+            // NULL sorts first ascending and last descending by default.
+            ("v", vec![2, 3, 5, 1, 6, 4]),
+            ("v DESC", vec![4, 1, 6, 5, 3, 2]),
+            ("v NULLS LAST", vec![3, 5, 1, 6, 4, 2]),
+            ("v DESC NULLS FIRST", vec![2, 4, 1, 6, 5, 3]),
+            ("g, v DESC", vec![5, 4, 2, 1, 6, 3]),
+            ("g DESC NULLS LAST, v", vec![3, 1, 6, 2, 4, 5]),
+            ("g DESC NULLS LAST, v LIMIT 3 OFFSET 1", vec![1, 6, 2]),
+            ("v OFFSET 4", vec![6, 4]),
+            ("v LIMIT 2 OFFSET 10", vec![]),
+            ("1 DESC LIMIT 2", vec![6, 5]),
+        ] {
+            let sql = format!("SELECT id FROM t ORDER BY {}", order);
+            assert_eq!(integers(query(&mut storage, &sql)), expected, "{}", order);
         }
+    }
 
-        let index_files = {
-            let table = self.metadata.tables.get(name).unwrap();
-            table
-                .indexes
-                .iter()
-                .map(|idx| idx.name.clone())
-                .collect::<Vec<_>>()
-        };
+    fn assert_violates(storage: &mut Storage, sql: &str, message: &str) {
+        let err = execute(storage, sql).unwrap_err().to_string();
+        assert!(err.contains(message), "{}: {}", sql, err);
+    }
 
-        index_files.iter().for_each(|index_name| {
-            self.drop_index(name, index_name).unwrap();
-        });
+    #[test]
+    fn primary_keys_refuse_duplicates_and_nulls() {
+        let dir = TempDir::new("primary-key");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE p (id INTEGER PRIMARY KEY, name TEXT)",
+        );
+        query(&mut storage, "INSERT INTO p VALUES (1, 'x')");
+        query(&mut storage, "INSERT INTO p VALUES (2, 'y')");
+
+        assert_violates(
+            &mut storage,
+            "INSERT INTO p VALUES (1, 'z')",
+            "duplicate key (id) = (1) violates PRIMARY KEY",
+        );
+        assert_violates(
+            &mut storage,
+            "INSERT INTO p (name) VALUES ('z')",
+            "PRIMARY KEY column id cannot be NULL",
+        );
+        assert_violates(
+            &mut storage,
+            "UPDATE p SET id = 1 WHERE id = 2",
+            "duplicate key (id) = (1) violates PRIMARY KEY",
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM p")),
+            vec![1, 2]
+        );
+
+        // Keys are checked once the whole update is done, so they can shift.
+        query(&mut storage, "UPDATE p SET id = id + 1");
+        query(&mut storage, "INSERT INTO p VALUES (1, 'z')");
+
+        drop(storage);
+        let mut storage = open(&dir);
+        assert_eq!(index_names(&storage, "p"), vec!["p_pkey"]);
+        assert_violates(
+            &mut storage,
+            "INSERT INTO p VALUES (3, 'w')",
+            "duplicate key (id) = (3) violates PRIMARY KEY",
+        );
+    }
 
-        self.metadata.tables.remove(name);
-        self.save_metadata()?;
+    #[test]
+    fn composite_primary_keys_compare_every_column() {
+        let dir = TempDir::new("primary-key-composite");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE q (a INTEGER, b INTEGER, c TEXT, PRIMARY KEY (a, b))",
+        );
+        query(&mut storage, "INSERT INTO q VALUES (1, 1, 'x')");
+        query(&mut storage, "INSERT INTO q VALUES (1, 2, 'y')");
+        query(&mut storage, "INSERT INTO q VALUES (2, 1, 'z')");
+        assert_violates(
+            &mut storage,
+            "INSERT INTO q VALUES (1, 2, 'w')",
+            "duplicate key (a, b) = (1, 2) violates PRIMARY KEY",
+        );
+
+        // Rows staged by a transaction count too.
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "INSERT INTO q VALUES (3, 3, 'v')");
+        assert_violates(
+            &mut storage,
+            "INSERT INTO q VALUES (3, 3, 'u')",
+            "duplicate key (a, b) = (3, 3) violates PRIMARY KEY",
+        );
+        query(&mut storage, "COMMIT");
+        assert_eq!(
+            integers(query(&mut storage, "SELECT COUNT(*) FROM q")),
+            vec![4]
+        );
+
+        assert_violates(
+            &mut storage,
+            "CREATE TABLE r (id INTEGER PRIMARY KEY, x INTEGER PRIMARY KEY)",
+            "more than one PRIMARY KEY",
+        );
+    }
 
-        Ok(())
+    #[test]
+    fn a_check_over_a_null_column_passes() {
+        let dir = TempDir::new("check-null");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (lo INTEGER, hi INTEGER CHECK (hi > lo))",
+        );
+        query(&mut storage, "INSERT INTO t VALUES (NULL, 2)");
+        query(&mut storage, "INSERT INTO t VALUES (1, NULL)");
+        query(&mut storage, "INSERT INTO t VALUES (1, 2)");
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t VALUES (3, 2)",
+            "violates CHECK (hi > lo)",
+        );
+        assert_violates(
+            &mut storage,
+            "UPDATE t SET lo = 5 WHERE lo = 1",
+            "violates CHECK",
+        );
+        query(&mut storage, "UPDATE t SET lo = NULL");
     }
 
-    pub fn drop_index(&mut self, table_name: &str, index_name: &str) -> Result<()> {
-        let table_metadata = self
-            .metadata
-            .tables
-            .get_mut(table_name)
-            .ok_or_else(|| anyhow::anyhow!("Table not found"))?;
+    #[test]
+    fn not_null_default_and_check_are_enforced() {
+        let dir = TempDir::new("column-constraints");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER NOT NULL, qty INTEGER DEFAULT 5 CHECK (qty >= 0), note TEXT DEFAULT 'none')",
+        );
+
+        query(&mut storage, "INSERT INTO t (id) VALUES (1)");
+        query(
+            &mut storage,
+            "INSERT INTO t (id, qty, note) VALUES (2, NULL, NULL)",
+        );
+        assert_eq!(
+            query(&mut storage, "SELECT qty, note FROM t"),
+            vec![
+                vec![Value::Integer(5), Value::Text("none".to_string())],
+                vec![Value::Null, Value::Null],
+            ]
+        );
+
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t (qty) VALUES (1)",
+            "Constraint violation on table t: column id violates NOT NULL",
+        );
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t VALUES (3, -1, 'x')",
+            "Constraint violation on table t: column qty violates CHECK (qty >= 0)",
+        );
+        assert_violates(
+            &mut storage,
+            "UPDATE t SET qty = qty - 10",
+            "violates CHECK (qty >= 0)",
+        );
+        assert_violates(
+            &mut storage,
+            "UPDATE t SET id = NULL WHERE id = 2",
+            "violates NOT NULL",
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT qty FROM t WHERE id = 1")),
+            vec![5]
+        );
+
+        // The constraints survive a reopen.
+        drop(storage);
+        let mut storage = open(&dir);
+        query(&mut storage, "INSERT INTO t (id) VALUES (3)");
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t VALUES (4, -1, 'x')",
+            "violates CHECK",
+        );
+    }
 
-        let index_pos = table_metadata
-            .indexes
-            .iter()
-            .position(|idx| idx.name == index_name)
-            .ok_or_else(|| anyhow::anyhow!("Index not found"))?;
+    #[test]
+    fn invalid_defaults_and_checks_are_refused_up_front() {
+        let dir = TempDir::new("column-constraints-invalid");
+        let mut storage = open(&dir);
+        assert_violates(
+            &mut storage,
+            "CREATE TABLE u (a INTEGER DEFAULT 'x')",
+            "DEFAULT of column u.a: Type mismatch",
+        );
+        assert_violates(
+            &mut storage,
+            "CREATE TABLE u (a INTEGER CHECK (b > 0))",
+            "CHECK on column u.a: Column b not found",
+        );
+        assert!(storage.metadata.table("u").is_none());
+    }
+
+    #[test]
+    fn added_columns_read_their_default_in_old_rows() {
+        let dir = TempDir::new("add-column");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (id INTEGER)");
+        query(&mut storage, "INSERT INTO t VALUES (1)");
+        query(&mut storage, "INSERT INTO t VALUES (2)");
+
+        query(&mut storage, "ALTER TABLE t ADD COLUMN c INTEGER DEFAULT 7");
+        query(&mut storage, "ALTER TABLE t ADD COLUMN d TEXT");
+        query(&mut storage, "INSERT INTO t VALUES (3, 8, 'x')");
+        assert_violates(
+            &mut storage,
+            "ALTER TABLE t ADD COLUMN e INTEGER NOT NULL",
+            "column e violates NOT NULL",
+        );
+        assert_violates(
+            &mut storage,
+            "ALTER TABLE t ADD COLUMN d INTEGER",
+            "Column d already exists in table t",
+        );
+        assert_violates(
+            &mut storage,
+            "ALTER TABLE t ADD COLUMN e TIMESTAMP DEFAULT NOW()",
+            "existing rows would all share one value",
+        );
+        assert!(
+            !storage
+                .alter_table(
+                    "missing",
+                    true,
+                    AlterTableAction::Rename {
+                        from: "a".to_string(),
+                        to: "b".to_string(),
+                    }
+                )
+                .unwrap()
+        );
+
+        drop(storage);
+        let mut storage = open(&dir);
+        assert_eq!(
+            query(&mut storage, "SELECT * FROM t WHERE c >= 7"),
+            vec![
+                vec![Value::Integer(1), Value::Integer(7), Value::Null],
+                vec![Value::Integer(2), Value::Integer(7), Value::Null],
+                vec![
+                    Value::Integer(3),
+                    Value::Integer(8),
+                    Value::Text("x".to_string())
+                ],
+            ]
+        );
+    }
 
-        let index_path = table_metadata.indexes[index_pos].file_path.clone();
+    #[test]
+    fn renamed_columns_carry_their_indexes_and_checks() {
+        let dir = TempDir::new("rename-column");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER CHECK (a > 0))",
+        );
+        query(
+            &mut storage,
+            "CREATE TABLE c (pid INTEGER REFERENCES t (id))",
+        );
+        query(&mut storage, "CREATE INDEX ta ON t (a)");
+        query(&mut storage, "INSERT INTO t VALUES (1, 10)");
+        query(&mut storage, "INSERT INTO t VALUES (2, 20)");
+
+        query(&mut storage, "ALTER TABLE t RENAME COLUMN a TO amount");
+        query(&mut storage, "ALTER TABLE t RENAME COLUMN id TO ident");
+        assert_violates(
+            &mut storage,
+            "ALTER TABLE t RENAME COLUMN amount TO ident",
+            "Column ident already exists in table t",
+        );
+
+        drop(storage);
+        let mut storage = open(&dir);
+        assert!(
+            storage
+                .find_usable_index("t", &where_clause("amount > 15"))
+                .is_some()
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT ident FROM t WHERE amount > 15")),
+            vec![2]
+        );
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t VALUES (3, -1)",
+            "violates CHECK (amount > 0)",
+        );
+        assert_violates(
+            &mut storage,
+            "INSERT INTO t VALUES (2, 5)",
+            "duplicate key (ident) = (2)",
+        );
+        query(&mut storage, "INSERT INTO c VALUES (1)");
+        assert!(execute(&mut storage, "INSERT INTO c VALUES (9)").is_err());
+        assert!(execute(&mut storage, "SELECT a FROM t").is_err());
+    }
 
-        let path = Path::new(&index_path);
-        if path.exists() {
-            fs::remove_file(path)?;
-        }
+    #[test]
+    fn if_exists_and_if_not_exists_make_ddl_a_no_op() {
+        let dir = TempDir::new("ddl-if-exists");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER)");
+        query(&mut storage, "CREATE TABLE IF NOT EXISTS t (b INTEGER)");
+        assert_violates(
+            &mut storage,
+            "CREATE TABLE t (b INTEGER)",
+            "Table already exists",
+        );
+        assert!(execute(&mut storage, "SELECT a FROM t").is_ok());
+
+        query(&mut storage, "CREATE INDEX ta ON t (a)");
+        assert!(
+            !storage
+                .create_index("t", "ta", vec!["a".to_string()], true)
+                .unwrap()
+        );
+        assert_violates(
+            &mut storage,
+            "CREATE INDEX ta ON t (a)",
+            "Index already exists",
+        );
+
+        query(&mut storage, "DROP INDEX ta ON t");
+        assert!(!dir.join("t_ta.idx").exists());
+        assert_violates(&mut storage, "DROP INDEX ta ON t", "Index not found");
+        assert!(!storage.drop_index("t", "ta", true).unwrap());
+
+        assert!(!storage.drop_table("missing", true).unwrap());
+        assert_violates(&mut storage, "DROP TABLE missing", "Table does not exist");
+        query(&mut storage, "DROP TABLE IF EXISTS t");
+        assert!(storage.metadata.table("t").is_none());
+        assert!(!dir.join("t.data").exists());
+    }
 
-        table_metadata.indexes.remove(index_pos);
-        self.save_metadata()?;
+    #[test]
+    fn truncate_empties_the_table_and_its_indexes() {
+        let dir = TempDir::new("truncate");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE p (id INTEGER PRIMARY KEY, a INTEGER)",
+        );
+        query(&mut storage, "CREATE INDEX pa ON p (a)");
+        for id in 1..=3 {
+            query(
+                &mut storage,
+                &format!("INSERT INTO p VALUES ({}, {})", id, id * 10),
+            );
+        }
 
-        Ok(())
+        query(&mut storage, "BEGIN");
+        query(&mut storage, "TRUNCATE p");
+        assert!(query(&mut storage, "SELECT id FROM p").is_empty());
+        query(&mut storage, "ROLLBACK");
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM p")),
+            vec![1, 2, 3]
+        );
+
+        query(&mut storage, "TRUNCATE TABLE p");
+        assert!(query(&mut storage, "SELECT id FROM p").is_empty());
+        // Keys of the old rows are free again, and the indexes only see the
+        // new rows.
+        query(&mut storage, "INSERT INTO p VALUES (1, 20)");
+        drop(storage);
+        let mut storage = open(&dir);
+        assert_eq!(
+            integers(query(&mut storage, "SELECT id FROM p WHERE a >= 10")),
+            vec![1]
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT COUNT(*) FROM p")),
+            vec![1]
+        );
+
+        query(
+            &mut storage,
+            "CREATE TABLE c (pid INTEGER REFERENCES p (id))",
+        );
+        assert_violates(
+            &mut storage,
+            "TRUNCATE p",
+            "Cannot truncate table p: table c references it",
+        );
     }
 }
@@ -0,0 +1,312 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::heap::AppendCursor;
+use crate::storage::{Column, ForeignKey, Value};
+
+/// One logical change to the database. Each record carries everything needed
+/// to apply it again from scratch, so replaying a record that was already
+/// partly or fully applied leaves the same result as applying it once.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub enum WalRecord {
+    CreateTable {
+        name: String,
+        columns: Vec<Column>,
+        #[serde(default)]
+        primary_key: Vec<String>,
+        #[serde(default)]
+        foreign_keys: Vec<ForeignKey>,
+    },
+    DropTable {
+        name: String,
+    },
+    CreateIndex {
+        table: String,
+        name: String,
+        columns: Vec<String>,
+        #[serde(default)]
+        unique: bool,
+    },
+    DropIndex {
+        table: String,
+        name: String,
+    },
+    /// Replaces the table's data file and index files with empty ones.
+    Truncate {
+        table: String,
+    },
+    /// Adds `column` at the end of the table's columns. Rows are left as
+    /// they are; those written before read the column's `missing` value.
+    AddColumn {
+        table: String,
+        column: Column,
+    },
+    /// Removes a column from the table and drops the indexes on it. The data
+    /// file is rewritten without the column, so every row gets a new id.
+    DropColumn {
+        table: String,
+        name: String,
+    },
+    RenameColumn {
+        table: String,
+        from: String,
+        to: String,
+    },
+    /// Appends `rows` to the data file starting at `at`. Anything already
+    /// past `at` is left over from an interrupted attempt.
+    Insert {
+        table: String,
+        at: AppendCursor,
+        rows: Vec<Vec<Value>>,
+    },
+    /// Marks the rows with these ids as deleted.
+    Delete {
+        table: String,
+        row_ids: Vec<u64>,
+    },
+}
+
+impl WalRecord {
+    pub fn table(&self) -> &str {
+        match self {
+            WalRecord::CreateTable { name, .. } | WalRecord::DropTable { name } => name,
+            WalRecord::CreateIndex { table, .. }
+            | WalRecord::DropIndex { table, .. }
+            | WalRecord::Truncate { table }
+            | WalRecord::AddColumn { table, .. }
+            | WalRecord::DropColumn { table, .. }
+            | WalRecord::RenameColumn { table, .. }
+            | WalRecord::Insert { table, .. }
+            | WalRecord::Delete { table, .. } => table,
+        }
+    }
+}
+
+/// Write-ahead log kept as `wal.log` in the database directory.
+///
+/// Every batch of records is written as a single JSON line and synced before
+/// any of it is applied, and the log is emptied once the batch has been
+/// applied. A line that was cut short by a crash fails to parse and is
+/// discarded along with everything after it; nothing in it was applied yet.
+pub struct WriteAheadLog {
+    file: File,
+}
+
+impl WriteAheadLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(WriteAheadLog { file })
+    }
+
+    /// Returns the records of every complete batch still in the log, in the
+    /// order they were written.
+    pub fn pending(&mut self) -> Result<Vec<WalRecord>> {
+        let mut contents = String::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_string(&mut contents)?;
+
+        let mut records = Vec::new();
+        let mut rest = contents.as_str();
+        while let Some((line, tail)) = rest.split_once('\n') {
+            match serde_json::from_str::<Vec<WalRecord>>(line) {
+                Ok(batch) => records.extend(batch),
+                Err(_) => break,
+            }
+            rest = tail;
+        }
+
+        Ok(records)
+    }
+
+    pub fn append(&mut self, records: &[WalRecord]) -> Result<()> {
+        let line = serde_json::to_string(records)?;
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) -> Result<()> {
+        self.file.set_len(0)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::{self, BufferPool};
+    use crate::storage::tests::{TempDir, integers, open, query};
+
+    fn rows(values: &[i64]) -> Vec<Vec<Value>> {
+        values
+            .iter()
+            .map(|&i| vec![Value::Integer(i), Value::Text(format!("row {}", i))])
+            .collect()
+    }
+
+    #[test]
+    fn pending_stops_at_a_torn_batch() {
+        let dir = TempDir::new("wal-torn");
+        let mut wal = WriteAheadLog::open(&dir.join("wal.log")).unwrap();
+        let batch = vec![WalRecord::Delete {
+            table: "t".to_string(),
+            row_ids: vec![7],
+        }];
+        wal.append(&batch).unwrap();
+        write!(wal.file, "[{{\"Delete\":{{\"table\":\"t\",\"row").unwrap();
+
+        let pending = wal.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert!(matches!(&pending[0], WalRecord::Delete { row_ids, .. } if row_ids == &[7]));
+    }
+
+    #[test]
+    fn replaying_a_half_applied_batch_applies_it_once() {
+        let dir = TempDir::new("wal-replay");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER, b TEXT)");
+        query(&mut storage, "CREATE INDEX ta ON t (a)");
+        query(&mut storage, "INSERT INTO t VALUES (1, 'row 1')");
+        drop(storage);
+
+        // Log a batch, then crash after its insert reached the data file
+        // but before the delete did.
+        let table_path = dir.join("t.data");
+        let mut pool = BufferPool::new(8);
+        let at = heap::append_cursor(&mut pool, &table_path).unwrap();
+        let ids = heap::append(&mut pool, &table_path, at, &rows(&[2, 3, 4])).unwrap();
+        pool.sync(&table_path).unwrap();
+        drop(pool);
+
+        let batch = vec![
+            WalRecord::Insert {
+                table: "t".to_string(),
+                at,
+                rows: rows(&[2, 3, 4]),
+            },
+            WalRecord::Delete {
+                table: "t".to_string(),
+                row_ids: vec![ids[1]],
+            },
+        ];
+        WriteAheadLog::open(&dir.join("wal.log"))
+            .unwrap()
+            .append(&batch)
+            .unwrap();
+
+        let mut storage = open(&dir);
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![1, 2, 4]
+        );
+        drop(storage);
+
+        // Crash again before the log was cleared: the batch is replayed a
+        // second time over its own results.
+        WriteAheadLog::open(&dir.join("wal.log"))
+            .unwrap()
+            .append(&batch)
+            .unwrap();
+
+        let mut storage = open(&dir);
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t")),
+            vec![1, 2, 4]
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT a FROM t WHERE a >= 2")),
+            vec![2, 4]
+        );
+        assert_eq!(
+            integers(query(&mut storage, "SELECT COUNT(*) FROM t")),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn replaying_an_index_created_in_the_same_batch_as_a_delete() {
+        let dir = TempDir::new("wal-index-delete");
+        let mut storage = open(&dir);
+        query(&mut storage, "CREATE TABLE t (a INTEGER, b TEXT)");
+        query(&mut storage, "INSERT INTO t VALUES (1, 'row 1')");
+        query(&mut storage, "INSERT INTO t VALUES (2, 'row 2')");
+        drop(storage);
+
+        // What `BEGIN; CREATE INDEX ...; DELETE ...; COMMIT` logs, left in
+        // the log by a crash before any of it was applied.
+        let batch = vec![
+            WalRecord::CreateIndex {
+                table: "t".to_string(),
+                name: "ta".to_string(),
+                columns: vec!["a".to_string()],
+                unique: false,
+            },
+            WalRecord::Delete {
+                table: "t".to_string(),
+                row_ids: vec![heap::row_id(1, 0)],
+            },
+        ];
+        WriteAheadLog::open(&dir.join("wal.log"))
+            .unwrap()
+            .append(&batch)
+            .unwrap();
+
+        for _ in 0..2 {
+            let mut storage = open(&dir);
+            assert_eq!(integers(query(&mut storage, "SELECT a FROM t")), vec![2]);
+            assert_eq!(
+                integers(query(&mut storage, "SELECT a FROM t WHERE a >= 1")),
+                vec![2]
+            );
+        }
+    }
+
+    #[test]
+    fn replaying_a_dropped_column_rewrites_the_rows_once() {
+        let dir = TempDir::new("wal-drop-column");
+        let mut storage = open(&dir);
+        query(
+            &mut storage,
+            "CREATE TABLE t (a INTEGER, b TEXT, c INTEGER)",
+        );
+        query(&mut storage, "CREATE INDEX tc ON t (c)");
+        query(&mut storage, "INSERT INTO t VALUES (1, 'row 1', 10)");
+        query(&mut storage, "INSERT INTO t VALUES (2, 'row 2', 20)");
+        drop(storage);
+
+        // Logged but not applied at all, then logged again after it was.
+        let batch = vec![WalRecord::DropColumn {
+            table: "t".to_string(),
+            name: "b".to_string(),
+        }];
+        for _ in 0..2 {
+            WriteAheadLog::open(&dir.join("wal.log"))
+                .unwrap()
+                .append(&batch)
+                .unwrap();
+
+            let mut storage = open(&dir);
+            assert_eq!(
+                query(&mut storage, "SELECT * FROM t"),
+                vec![
+                    vec![Value::Integer(1), Value::Integer(10)],
+                    vec![Value::Integer(2), Value::Integer(20)],
+                ]
+            );
+            assert_eq!(
+                integers(query(&mut storage, "SELECT a FROM t WHERE c > 15")),
+                vec![2]
+            );
+        }
+        assert!(!dir.join("t.data.new").exists());
+    }
+}